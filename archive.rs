@@ -0,0 +1,351 @@
+use crate::{staged_output::StagedOutput, stages::hash_file, Error, Result};
+use std::{
+	collections::BTreeMap,
+	io::{Read, Write},
+	path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+// Archives are reproducible: entries are sorted by path, every timestamp is set to the
+// earliest time zip can represent, ownership is cleared, and modes are reduced to 755 for
+// directories and executables and 644 otherwise.
+const ARCHIVE_MTIME: u64 = 315_532_800;
+
+// The first entry of every archive, listing the hash of each file and the target of each
+// symlink, so unpacking can check the tree it produces.
+pub const PACK_MANIFEST_FILE_NAME: &str = ".windows_sdk-pack.json";
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct PackManifest {
+	pub files: BTreeMap<String, String>,
+	pub symlinks: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ArchiveFormat {
+	Tar,
+	TarZstd,
+	Zip,
+}
+
+impl ArchiveFormat {
+	fn new(archive_path: &Path) -> Result<ArchiveFormat> {
+		let archive_name = archive_path.to_string_lossy().to_lowercase();
+		if archive_name.ends_with(".zip") {
+			Ok(ArchiveFormat::Zip)
+		} else if archive_name.ends_with(".tar") {
+			Ok(ArchiveFormat::Tar)
+		} else if archive_name.ends_with(".tar.zst") {
+			Ok(ArchiveFormat::TarZstd)
+		} else {
+			Err(Error::UnsupportedArchive {
+				path: archive_path.to_owned(),
+			})
+		}
+	}
+}
+
+enum ArchiveEntry {
+	Directory,
+	File { executable: bool },
+	Symlink { target: PathBuf },
+}
+
+pub fn pack(output_path: PathBuf, archive_path: PathBuf) -> Result<()> {
+	let format = ArchiveFormat::new(&archive_path)?;
+	let mut entries = Vec::new();
+	let mut manifest = PackManifest::default();
+	for entry in WalkDir::new(&output_path)
+		.min_depth(1)
+		.sort_by(|a, b| a.file_name().cmp(b.file_name()))
+	{
+		let entry = entry?;
+		let relative_path = entry.path().strip_prefix(&output_path).unwrap();
+		let name = relative_path
+			.components()
+			.map(|component| component.as_os_str().to_string_lossy())
+			.collect::<Vec<_>>()
+			.join("/");
+		// The record of what extraction produced is only meaningful for the output
+		// directory itself, and a tree unpacked from another archive gets a new manifest.
+		if name == crate::ownership::OWNERSHIP_FILE_NAME
+			|| name == crate::msi_metadata::MSI_METADATA_FILE_NAME
+			|| name == PACK_MANIFEST_FILE_NAME
+		{
+			continue;
+		}
+		let archive_entry = if entry.file_type().is_dir() {
+			ArchiveEntry::Directory
+		} else if entry.file_type().is_symlink() {
+			let target = std::fs::read_link(entry.path())?;
+			manifest.symlinks.insert(name.clone(), target.clone());
+			ArchiveEntry::Symlink { target }
+		} else {
+			manifest
+				.files
+				.insert(name.clone(), hex::encode(hash_file(entry.path())?));
+			ArchiveEntry::File {
+				executable: is_executable(&entry.metadata()?),
+			}
+		};
+		entries.push((entry.path().to_owned(), name, archive_entry));
+	}
+	let manifest = serde_json::to_vec_pretty(&manifest)?;
+	let file = std::fs::File::create(&archive_path).map_err(Error::file(&archive_path))?;
+	match format {
+		ArchiveFormat::Zip => write_zip(file, &manifest, entries),
+		ArchiveFormat::Tar => {
+			write_tar(file, &manifest, entries)?;
+			Ok(())
+		}
+		ArchiveFormat::TarZstd => {
+			let encoder = zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+			write_tar(encoder, &manifest, entries)?.finish()?;
+			Ok(())
+		}
+	}
+}
+
+fn write_zip(
+	file: std::fs::File,
+	manifest: &[u8],
+	entries: Vec<(PathBuf, String, ArchiveEntry)>,
+) -> Result<()> {
+	let mut writer = zip::ZipWriter::new(file);
+	let options = zip::write::FileOptions::default()
+		.compression_method(zip::CompressionMethod::Deflated)
+		.last_modified_time(zip::DateTime::default());
+	writer.start_file(PACK_MANIFEST_FILE_NAME, options.unix_permissions(0o644))?;
+	writer.write_all(manifest)?;
+	for (path, name, entry) in entries {
+		match entry {
+			ArchiveEntry::Directory => {
+				writer.add_directory(name, options.unix_permissions(0o755))?;
+			}
+			ArchiveEntry::File { executable } => {
+				let mode = if executable { 0o755 } else { 0o644 };
+				writer.start_file(name, options.unix_permissions(mode))?;
+				std::io::copy(&mut std::fs::File::open(path)?, &mut writer)?;
+			}
+			ArchiveEntry::Symlink { target } => {
+				writer.add_symlink(name, target.to_string_lossy(), options)?;
+			}
+		}
+	}
+	writer.finish()?;
+	Ok(())
+}
+
+fn write_tar<W: Write>(
+	writer: W,
+	manifest: &[u8],
+	entries: Vec<(PathBuf, String, ArchiveEntry)>,
+) -> Result<W> {
+	let mut builder = tar::Builder::new(writer);
+	let new_header = || {
+		let mut header = tar::Header::new_gnu();
+		header.set_mtime(ARCHIVE_MTIME);
+		header.set_uid(0);
+		header.set_gid(0);
+		header
+	};
+	let mut header = new_header();
+	header.set_entry_type(tar::EntryType::Regular);
+	header.set_mode(0o644);
+	header.set_size(manifest.len() as u64);
+	builder.append_data(&mut header, PACK_MANIFEST_FILE_NAME, manifest)?;
+	for (path, name, entry) in entries {
+		let mut header = new_header();
+		match entry {
+			ArchiveEntry::Directory => {
+				header.set_entry_type(tar::EntryType::Directory);
+				header.set_mode(0o755);
+				header.set_size(0);
+				builder.append_data(&mut header, name, std::io::empty())?;
+			}
+			ArchiveEntry::File { executable } => {
+				let file = std::fs::File::open(path)?;
+				header.set_entry_type(tar::EntryType::Regular);
+				header.set_mode(if executable { 0o755 } else { 0o644 });
+				header.set_size(file.metadata()?.len());
+				builder.append_data(&mut header, name, file)?;
+			}
+			ArchiveEntry::Symlink { target } => {
+				header.set_entry_type(tar::EntryType::Symlink);
+				header.set_mode(0o777);
+				header.set_size(0);
+				builder.append_link(&mut header, name, target)?;
+			}
+		}
+	}
+	Ok(builder.into_inner()?)
+}
+
+// Unpack an archive made by `pack` into the output directory, replacing the tree there
+// only once every file matches the archive's manifest. The tree it replaces is kept as a
+// backup when `keep_backups` is more than zero, as for extraction.
+pub fn unpack(archive_path: PathBuf, output_path: PathBuf, keep_backups: usize) -> Result<()> {
+	let format = ArchiveFormat::new(&archive_path)?;
+	let staged_output = StagedOutput::new(&output_path)?;
+	let file = std::fs::File::open(&archive_path).map_err(Error::file(&archive_path))?;
+	match format {
+		ArchiveFormat::Zip => read_zip(file, &archive_path, staged_output.path())?,
+		ArchiveFormat::Tar => read_tar(file, &archive_path, staged_output.path())?,
+		ArchiveFormat::TarZstd => read_tar(
+			zstd::Decoder::new(file)?,
+			&archive_path,
+			staged_output.path(),
+		)?,
+	}
+	let manifest_path = staged_output.path().join(PACK_MANIFEST_FILE_NAME);
+	let manifest: PackManifest = match std::fs::read(&manifest_path) {
+		Ok(manifest) => serde_json::from_slice(&manifest)?,
+		Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+			return Err(Error::InvalidPath {
+				path: archive_path,
+				reason: format!(
+					"it has no {}, so it was not made by pack",
+					PACK_MANIFEST_FILE_NAME
+				),
+			});
+		}
+		Err(error) => return Err(error.into()),
+	};
+	std::fs::remove_file(&manifest_path)?;
+	let count = verify_unpacked(staged_output.path(), &manifest)?;
+	if count > 0 {
+		return Err(Error::ArchiveVerificationFailed {
+			path: archive_path,
+			count,
+		});
+	}
+	staged_output.commit(keep_backups)
+}
+
+fn unsafe_entry(archive_path: &Path, name: &str) -> Error {
+	Error::InvalidPath {
+		path: archive_path.to_owned(),
+		reason: format!("its entry {} is outside the archive", name),
+	}
+}
+
+fn read_tar<R: Read>(reader: R, archive_path: &Path, unpacked_path: &Path) -> Result<()> {
+	let mut archive = tar::Archive::new(reader);
+	for entry in archive.entries()? {
+		let mut entry = entry?;
+		if !entry.unpack_in(unpacked_path)? {
+			let name = entry.path()?.display().to_string();
+			return Err(unsafe_entry(archive_path, &name));
+		}
+	}
+	Ok(())
+}
+
+fn read_zip(file: std::fs::File, archive_path: &Path, unpacked_path: &Path) -> Result<()> {
+	let mut archive = zip::ZipArchive::new(file)?;
+	for index in 0..archive.len() {
+		let mut file = archive.by_index(index)?;
+		let path = match file.enclosed_name() {
+			Some(name) => unpacked_path.join(name),
+			None => return Err(unsafe_entry(archive_path, file.name())),
+		};
+		let mode = file.unix_mode();
+		if file.is_dir() {
+			std::fs::create_dir_all(&path)?;
+			continue;
+		}
+		if let Some(parent_path) = path.parent() {
+			std::fs::create_dir_all(parent_path)?;
+		}
+		// Zip stores the target of a symlink as its contents, marked by the file type in its mode.
+		if mode
+			.map(|mode| mode & 0o170000 == 0o120000)
+			.unwrap_or(false)
+		{
+			let mut target = String::new();
+			file.read_to_string(&mut target)?;
+			create_symlink(Path::new(&target), &path)?;
+			continue;
+		}
+		std::io::copy(&mut file, &mut std::fs::File::create(&path)?)?;
+		#[cfg(unix)]
+		if let Some(mode) = mode {
+			use std::os::unix::fs::PermissionsExt;
+			std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode & 0o777))?;
+		}
+	}
+	Ok(())
+}
+
+// Compare the unpacked tree with the manifest, logging each difference, and return the
+// number of differences.
+fn verify_unpacked(unpacked_path: &Path, manifest: &PackManifest) -> Result<usize> {
+	let mut failures = 0;
+	let mut seen = 0;
+	for entry in WalkDir::new(unpacked_path).min_depth(1) {
+		let entry = entry?;
+		if entry.file_type().is_dir() {
+			continue;
+		}
+		let name = entry
+			.path()
+			.strip_prefix(unpacked_path)
+			.unwrap()
+			.components()
+			.map(|component| component.as_os_str().to_string_lossy())
+			.collect::<Vec<_>>()
+			.join("/");
+		let matches = if entry.file_type().is_symlink() {
+			manifest
+				.symlinks
+				.get(&name)
+				.map(|target| std::fs::read_link(entry.path()).ok().as_ref() == Some(target))
+		} else {
+			let hash = hex::encode(hash_file(entry.path())?);
+			manifest.files.get(&name).map(|expected| *expected == hash)
+		};
+		match matches {
+			Some(true) => seen += 1,
+			Some(false) => {
+				seen += 1;
+				failures += 1;
+				tracing::error!("{} does not match the archive manifest", name);
+			}
+			None => {
+				failures += 1;
+				tracing::error!("{} is not in the archive manifest", name);
+			}
+		}
+	}
+	let missing = manifest.files.len() + manifest.symlinks.len() - seen;
+	if missing > 0 {
+		tracing::error!(
+			"{} files in the archive manifest are missing from the archive",
+			missing
+		);
+	}
+	Ok(failures + missing)
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, path: &Path) -> Result<()> {
+	std::os::unix::fs::symlink(target, path)?;
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_symlink(target: &Path, path: &Path) -> Result<()> {
+	std::fs::copy(path.parent().unwrap().join(target), path)?;
+	Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+	use std::os::unix::fs::PermissionsExt;
+	metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+	false
+}
@@ -0,0 +1,245 @@
+use crate::{Cache, HttpOptions, Package, Result};
+use digest::Digest;
+use futures::StreamExt;
+use indicatif::HumanBytes;
+use rayon::prelude::*;
+use sha2::Sha256;
+use std::{
+	io::Write,
+	path::PathBuf,
+	time::{Duration, Instant},
+};
+use url::Url;
+
+#[derive(Debug)]
+pub struct BenchOptions {
+	pub cache_path: PathBuf,
+	// The selection whose largest payload is downloaded to measure the download speed.
+	pub packages_path: Option<PathBuf>,
+	// A URL to download instead of a payload of the selection.
+	pub url: Option<Url>,
+	// The number of bytes written, hashed by each job, and downloaded by each measurement.
+	pub sample_size: u64,
+	pub http: HttpOptions,
+}
+
+// The number of jobs is only recommended over a smaller one when it is this much faster,
+// since more jobs use more memory and connections.
+const MIN_SPEEDUP: f64 = 1.1;
+
+// The most connections the download is measured with.
+const MAX_CONNECTIONS: usize = 16;
+
+// Measure how fast the cache filesystem is written, how fast payloads are hashed with
+// more and more jobs, and how fast a sample payload downloads over more and more
+// connections, then print the job and connection limits past which this machine gets no
+// faster.
+pub fn bench(options: BenchOptions) -> Result<()> {
+	let cache = Cache::new(options.cache_path.clone());
+	cache.create()?;
+	let sample = sample_bytes(options.sample_size as usize);
+
+	let write_time = measure_write(cache.path(), &sample)?;
+	println!("disk write: {}", rate(sample.len() as u64, write_time));
+
+	println!("hash:");
+	// Rayon's global pool has a thread for each CPU.
+	let cpus = rayon::current_num_threads();
+	let mut hash_rates = Vec::new();
+	for jobs in job_counts(cpus) {
+		let time = measure_hash(&sample, jobs)?;
+		let bytes = sample.len() as u64 * jobs as u64;
+		println!("\t--hash-jobs {}: {}", jobs, rate(bytes, time));
+		hash_rates.push((jobs, bytes as f64 / time.as_secs_f64()));
+	}
+	let hash_jobs = recommended(&hash_rates);
+
+	let url = match (options.url.clone(), options.packages_path.as_ref()) {
+		(Some(url), _) => Some(url),
+		(None, Some(packages_path)) => Package::read_all(packages_path)?
+			.iter()
+			.flat_map(|package| package.payloads.iter())
+			.max_by_key(|payload| payload.size)
+			.map(|payload| options.http.payload_url(payload)),
+		(None, None) => None,
+	};
+	let connections = match url {
+		Some(url) => {
+			println!("download {}:", url);
+			let runtime = tokio::runtime::Runtime::new()?;
+			let client = options.http.client()?;
+			let mut download_rates = Vec::new();
+			let mut file_size = None;
+			for connections in job_counts(MAX_CONNECTIONS) {
+				let (bytes, time, size) = runtime.block_on(measure_download(
+					&options.http,
+					&client,
+					&url,
+					options.sample_size,
+					connections,
+					file_size,
+				))?;
+				file_size = file_size.or(size);
+				println!(
+					"\t--per-host-connections {}: {}",
+					connections,
+					rate(bytes, time)
+				);
+				download_rates.push((connections, bytes as f64 / time.as_secs_f64()));
+				// The server stops getting faster well before the last measurement, which
+				// would only add load.
+				if recommended(&download_rates) < connections / 2 {
+					break;
+				}
+			}
+			Some(recommended(&download_rates))
+		}
+		None => {
+			println!("download: skipped, pass --packages or --url to measure the download speed");
+			None
+		}
+	};
+
+	print!("recommended: --hash-jobs {}", hash_jobs);
+	if let Some(connections) = connections {
+		print!(
+			" --download-jobs {} --per-host-connections {}",
+			connections, connections
+		);
+	}
+	println!();
+	Ok(())
+}
+
+// Bytes that do not compress or deduplicate, so filesystems that do either are measured
+// writing all of them.
+fn sample_bytes(size: usize) -> Vec<u8> {
+	let mut state = 0x2545_f491_4f6c_dd1du64;
+	let mut bytes = Vec::with_capacity(size + 8);
+	while bytes.len() < size {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		bytes.extend_from_slice(&state.to_le_bytes());
+	}
+	bytes.truncate(size);
+	bytes
+}
+
+// Write the sample to a file in the cache and wait until it is on the disk, in the chunks
+// downloads are written in.
+fn measure_write(cache_path: &std::path::Path, sample: &[u8]) -> Result<Duration> {
+	let mut file = tempfile::NamedTempFile::new_in(cache_path)?;
+	let start = Instant::now();
+	for chunk in sample.chunks(1 << 20) {
+		file.write_all(chunk)?;
+	}
+	file.as_file().sync_all()?;
+	Ok(start.elapsed())
+}
+
+// Hash the sample once on each of the jobs' threads, the way the verify stage hashes payloads.
+fn measure_hash(sample: &[u8], jobs: usize) -> Result<Duration> {
+	let thread_pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(jobs)
+		.build()
+		.map_err(|error| std::io::Error::new(std::io::ErrorKind::Other, error))?;
+	let start = Instant::now();
+	thread_pool.install(|| {
+		(0..jobs).into_par_iter().for_each(|_| {
+			Sha256::new_with_prefix(sample).finalize();
+		})
+	});
+	Ok(start.elapsed())
+}
+
+// Download the URL over the connections at once, each reading its own part of the sample,
+// and return the number of bytes read and the size of the file, if the server sent it.
+// Parts past the end of a file smaller than the sample start again from its beginning,
+// once its size is known. Servers that ignore the range send the whole file, which is
+// read only as far as the part.
+async fn measure_download(
+	http: &HttpOptions,
+	client: &reqwest::Client,
+	url: &Url,
+	sample_size: u64,
+	connections: usize,
+	file_size: Option<u64>,
+) -> Result<(u64, Duration, Option<u64>)> {
+	let part_size = (sample_size / connections as u64).max(1);
+	let start = Instant::now();
+	let parts = futures::future::join_all((0..connections).map(|index| async move {
+		let offset = match file_size {
+			Some(file_size) if file_size > 0 => index as u64 * part_size % file_size,
+			_ => index as u64 * part_size,
+		};
+		let response = http
+			.get(client, url.clone())
+			.header(
+				reqwest::header::RANGE,
+				format!("bytes={}-{}", offset, offset + part_size - 1),
+			)
+			.send()
+			.await?
+			.error_for_status()?;
+		let size = response
+			.headers()
+			.get(reqwest::header::CONTENT_RANGE)
+			.and_then(|range| range.to_str().ok())
+			.and_then(|range| range.rsplit('/').next())
+			.and_then(|size| size.parse::<u64>().ok())
+			.or_else(|| {
+				(response.status() == reqwest::StatusCode::OK)
+					.then(|| response.content_length())
+					.flatten()
+			});
+		let mut bytes = 0;
+		let mut stream = response.bytes_stream();
+		while let Some(chunk) = stream.next().await {
+			bytes += chunk?.len() as u64;
+			if bytes >= part_size {
+				break;
+			}
+		}
+		Ok::<_, crate::Error>((bytes.min(part_size), size))
+	}))
+	.await;
+	let time = start.elapsed();
+	let mut bytes = 0;
+	let mut size = None;
+	for part in parts {
+		let (part_bytes, part_size) = part?;
+		bytes += part_bytes;
+		size = size.or(part_size);
+	}
+	Ok((bytes, time, size))
+}
+
+// One, then doubling up to the maximum, which is always included.
+fn job_counts(max: usize) -> Vec<usize> {
+	let mut counts = Vec::new();
+	let mut count = 1;
+	while count < max {
+		counts.push(count);
+		count *= 2;
+	}
+	counts.push(max);
+	counts
+}
+
+// The fewest jobs within the speedup of the fastest measurement.
+fn recommended(rates: &[(usize, f64)]) -> usize {
+	let best = rates.iter().map(|(_, rate)| *rate).fold(0.0, f64::max);
+	rates
+		.iter()
+		.find(|(_, rate)| rate * MIN_SPEEDUP >= best)
+		.map(|(jobs, _)| *jobs)
+		.unwrap_or(1)
+}
+
+fn rate(bytes: u64, time: Duration) -> String {
+	format!(
+		"{}/s",
+		HumanBytes((bytes as f64 / time.as_secs_f64().max(1e-9)) as u64)
+	)
+}
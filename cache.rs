@@ -0,0 +1,387 @@
+use crate::{hash_file, Error, Package, Payload, Result};
+use rayon::prelude::*;
+use std::{
+	collections::{BTreeSet, HashMap, HashSet},
+	hash::{BuildHasher, Hasher},
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime},
+};
+use url::Url;
+
+// Payloads are stored content-addressed under `ab/cd/<hash>`, fanned out by the first two
+// bytes of their hash so no directory grows too large, and shared by all namespaces,
+// while each namespace tracks which payloads it uses under `namespaces/<namespace>`.
+#[derive(Clone, Debug)]
+pub struct Cache {
+	path: PathBuf,
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyCacheOptions {
+	// Skip payloads that were verified less than this long ago.
+	pub since: Option<Duration>,
+	// Only re-hash a random sample of this percentage of the remaining payloads.
+	pub sample: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Collected {
+	pub count: usize,
+	pub size: u64,
+}
+
+impl Cache {
+	pub fn new(path: PathBuf) -> Cache {
+		Cache { path }
+	}
+
+	// The cache used when none is given, `~/.cache/windows_sdk` on Linux,
+	// `~/Library/Caches/windows_sdk` on macOS, and `%LOCALAPPDATA%\windows_sdk\cache` on
+	// Windows. There is none without a home directory.
+	pub fn default_path() -> Option<PathBuf> {
+		directories::ProjectDirs::from("", "", "windows_sdk")
+			.map(|directories| directories.cache_dir().to_owned())
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	pub fn payload_path(&self, payload: &Payload) -> PathBuf {
+		self.hash_path(&hex::encode(payload.sha256))
+	}
+
+	fn hash_path(&self, hash: &str) -> PathBuf {
+		self.path.join(&hash[0..2]).join(&hash[2..4]).join(hash)
+	}
+
+	// The path of the payload with the hex encoded sha256, if it is in the cache.
+	pub fn cached_path(&self, hash: &str) -> Option<PathBuf> {
+		let hash = hash.to_ascii_lowercase();
+		if !is_hash(&hash) {
+			return None;
+		}
+		let path = self.hash_path(&hash);
+		if path.is_file() {
+			Some(path)
+		} else {
+			None
+		}
+	}
+
+	// Record that the payload's contents were just checked against its hash.
+	pub fn record_verified(&self, payload: &Payload) -> Result<()> {
+		let path = self.payload_path(payload);
+		let mut metadata = PayloadMetadata::read(&path)?;
+		metadata.verified = Some(unix_time(SystemTime::now()));
+		metadata.write(&path)
+	}
+
+	// Record the URL the payload was downloaded from, which may be a mirror or a
+	// cache-busting variant of its URL.
+	pub fn record_source(&self, payload: &Payload, url: &Url) -> Result<()> {
+		let path = self.payload_path(payload);
+		let mut metadata = PayloadMetadata::read(&path)?;
+		metadata.url = Some(url.clone());
+		metadata.write(&path)
+	}
+
+	pub fn create(&self) -> Result<()> {
+		if !self.path.exists() {
+			std::fs::create_dir_all(&self.path)?;
+		}
+		self.migrate()
+	}
+
+	// Move payloads stored by older versions at the root of the cache into their fan-out
+	// directories.
+	pub fn migrate(&self) -> Result<()> {
+		let entries = match std::fs::read_dir(&self.path) {
+			Ok(entries) => entries,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+			Err(error) => return Err(error.into()),
+		};
+		for entry in entries {
+			let entry = entry?;
+			let hash = entry.file_name().to_string_lossy().into_owned();
+			if !entry.file_type()?.is_file() || !is_hash(&hash) {
+				continue;
+			}
+			let path = self.hash_path(&hash);
+			std::fs::create_dir_all(path.parent().unwrap())?;
+			std::fs::rename(entry.path(), path)?;
+		}
+		Ok(())
+	}
+
+	// List the hashes of all payloads in the cache.
+	fn hashes(&self) -> Result<Vec<String>> {
+		let mut hashes = Vec::new();
+		for entry in walkdir::WalkDir::new(&self.path).min_depth(3).max_depth(3) {
+			let entry = entry?;
+			let hash = entry.file_name().to_string_lossy().into_owned();
+			if entry.file_type().is_file() && is_hash(&hash) {
+				hashes.push(hash);
+			}
+		}
+		Ok(hashes)
+	}
+
+	pub fn record_namespace(&self, namespace: &str, packages: &[Package]) -> Result<()> {
+		let mut namespace_metadata = NamespaceMetadata::read(&self.path, namespace)?;
+		namespace_metadata.current = packages
+			.iter()
+			.flat_map(|package| package.payloads.iter())
+			.map(|payload| hex::encode(payload.sha256))
+			.collect();
+		namespace_metadata
+			.downloaded
+			.extend(namespace_metadata.current.iter().cloned());
+		namespace_metadata.write(&self.path, namespace)
+	}
+
+	pub fn gc(&self, namespace: Option<&str>) -> Result<Collected> {
+		self.migrate()?;
+		// Payloads in use by any namespace's most recent download are live.
+		let namespaces = NamespaceMetadata::namespaces(&self.path)?;
+		let mut metadata = namespaces
+			.iter()
+			.map(|namespace| {
+				Ok((
+					namespace.to_owned(),
+					NamespaceMetadata::read(&self.path, namespace)?,
+				))
+			})
+			.collect::<Result<HashMap<_, _>>>()?;
+		let live = metadata
+			.values()
+			.flat_map(|metadata| metadata.current.iter().cloned())
+			.collect::<HashSet<_>>();
+		// Collect either every unreferenced payload in the cache or only those downloaded
+		// through the requested namespace.
+		let candidates = match namespace {
+			Some(namespace) => metadata
+				.get(namespace)
+				.ok_or_else(|| Error::MissingNamespace {
+					namespace: namespace.to_owned(),
+				})?
+				.downloaded
+				.iter()
+				.cloned()
+				.collect::<Vec<_>>(),
+			None => self.hashes()?,
+		};
+		let mut collected = Collected::default();
+		for hash in candidates.iter().filter(|hash| !live.contains(*hash)) {
+			let path = self.hash_path(hash);
+			if path.exists() {
+				collected.add(&path)?;
+			}
+		}
+		for (name, metadata) in metadata.iter_mut() {
+			if namespace.is_none() || namespace == Some(name.as_str()) {
+				metadata.downloaded = metadata.current.clone();
+				metadata.write(&self.path, name)?;
+			}
+		}
+		Ok(collected)
+	}
+
+	// Remove every payload that none of the packages reference.
+	pub fn prune(&self, packages: &[Package]) -> Result<Collected> {
+		self.migrate()?;
+		let live = packages
+			.iter()
+			.flat_map(|package| package.payloads.iter())
+			.map(|payload| hex::encode(payload.sha256))
+			.collect::<HashSet<_>>();
+		let mut collected = Collected::default();
+		for hash in self.hashes()?.iter().filter(|hash| !live.contains(*hash)) {
+			collected.add(&self.hash_path(hash))?;
+		}
+		Ok(collected)
+	}
+
+	// Re-hash the payloads and remove the ones whose contents no longer match their name.
+	// The time each payload was last verified is kept in a `<hash>.json` sidecar, so
+	// periodic checks of large caches can skip recently verified payloads or only check a
+	// sample.
+	pub fn verify(&self, options: &VerifyCacheOptions) -> Result<Verified> {
+		self.migrate()?;
+		let now = SystemTime::now();
+		let cutoff = options
+			.since
+			.and_then(|since| now.checked_sub(since))
+			.map(unix_time);
+		let mut hashes = Vec::new();
+		let mut skipped = 0;
+		for hash in self.hashes()? {
+			let verified = PayloadMetadata::read(&self.hash_path(&hash))?.verified;
+			let recent =
+				matches!((verified, cutoff), (Some(verified), Some(cutoff)) if verified > cutoff);
+			if recent {
+				skipped += 1;
+			} else {
+				hashes.push(hash);
+			}
+		}
+		if let Some(sample) = options.sample {
+			// The hasher is seeded randomly per process, so each run checks a different sample.
+			let random_state = std::collections::hash_map::RandomState::new();
+			let threshold = (sample / 100.0).clamp(0.0, 1.0) * u64::MAX as f64;
+			let count = hashes.len();
+			hashes.retain(|hash| {
+				let mut hasher = random_state.build_hasher();
+				hasher.write(hash.as_bytes());
+				(hasher.finish() as f64) < threshold
+			});
+			skipped += count - hashes.len();
+		}
+		let corrupt = hashes
+			.par_iter()
+			.map(|hash| {
+				let path = self.hash_path(hash);
+				if hex::encode(hash_file(&path)?) != *hash {
+					return Ok(Some(hash));
+				}
+				let mut metadata = PayloadMetadata::read(&path)?;
+				metadata.verified = Some(unix_time(now));
+				metadata.write(&path)?;
+				Ok(None)
+			})
+			.collect::<Result<Vec<_>>>()?;
+		let mut verified = Verified {
+			count: hashes.len(),
+			skipped,
+			..Default::default()
+		};
+		for hash in corrupt.into_iter().flatten() {
+			verified.removed.add(&self.hash_path(hash))?;
+		}
+		Ok(verified)
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct Verified {
+	pub count: usize,
+	pub skipped: usize,
+	pub removed: Collected,
+}
+
+impl Collected {
+	fn add(&mut self, path: &Path) -> Result<()> {
+		let metadata = std::fs::metadata(path)?;
+		std::fs::remove_file(path)?;
+		match std::fs::remove_file(PayloadMetadata::path(path)) {
+			Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error.into()),
+			_ => {}
+		}
+		self.size += metadata.len();
+		self.count += 1;
+		Ok(())
+	}
+}
+
+fn is_hash(name: &str) -> bool {
+	name.len() == 64 && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+	time.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|duration| duration.as_secs())
+		.unwrap_or(0)
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct PayloadMetadata {
+	// When the payload was last verified, in seconds since the Unix epoch.
+	#[serde(default)]
+	verified: Option<u64>,
+	// The URL the payload was downloaded from.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	url: Option<Url>,
+}
+
+impl PayloadMetadata {
+	fn path(payload_path: &Path) -> PathBuf {
+		payload_path.with_extension("json")
+	}
+
+	fn read(payload_path: &Path) -> Result<PayloadMetadata> {
+		match std::fs::read(PayloadMetadata::path(payload_path)) {
+			Ok(bytes) => Ok(serde_json::from_slice(&bytes).unwrap_or_default()),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+			Err(error) => Err(error.into()),
+		}
+	}
+
+	fn write(&self, payload_path: &Path) -> Result<()> {
+		std::fs::write(
+			PayloadMetadata::path(payload_path),
+			serde_json::to_vec(self)?,
+		)?;
+		Ok(())
+	}
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct NamespaceMetadata {
+	// The payloads referenced by the namespace's most recent download.
+	current: BTreeSet<String>,
+	// The payloads downloaded through the namespace since it was last collected.
+	downloaded: BTreeSet<String>,
+}
+
+impl NamespaceMetadata {
+	// Namespaces name a directory in the cache, so they are limited to characters that
+	// cannot leave it.
+	fn path(cache_path: &Path, namespace: &str) -> Result<PathBuf> {
+		if !is_valid_namespace(namespace) {
+			return Err(Error::InvalidNamespace {
+				namespace: namespace.to_owned(),
+			});
+		}
+		Ok(cache_path
+			.join("namespaces")
+			.join(namespace)
+			.join("payloads.json"))
+	}
+
+	fn read(cache_path: &Path, namespace: &str) -> Result<NamespaceMetadata> {
+		match std::fs::read(NamespaceMetadata::path(cache_path, namespace)?) {
+			Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+			Err(error) => Err(error.into()),
+		}
+	}
+
+	fn write(&self, cache_path: &Path, namespace: &str) -> Result<()> {
+		let path = NamespaceMetadata::path(cache_path, namespace)?;
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+		Ok(())
+	}
+
+	fn namespaces(cache_path: &Path) -> Result<Vec<String>> {
+		match std::fs::read_dir(cache_path.join("namespaces")) {
+			Ok(entries) => entries
+				.map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+				.filter(
+					|namespace| !matches!(namespace, Ok(namespace) if !is_valid_namespace(namespace)),
+				)
+				.collect(),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+			Err(error) => Err(error.into()),
+		}
+	}
+}
+
+fn is_valid_namespace(namespace: &str) -> bool {
+	!namespace.is_empty()
+		&& namespace != "."
+		&& namespace != ".."
+		&& namespace
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
@@ -0,0 +1,164 @@
+use crate::{ChannelSource, DependencyChip, Error, Layout, Result};
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+};
+
+// The name of the config file looked for in the current directory.
+pub const CONFIG_FILE_NAME: &str = "windows-sdk.toml";
+
+// The settings of an install, read from a config file so a project can check in what it
+// needs. Each key is named after the flag that sets it, and every subcommand that takes
+// the flag reads the key, with the flag overriding it.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub major_version: Option<String>,
+	#[serde(default, with = "from_str", skip_serializing_if = "Option::is_none")]
+	pub channel: Option<ChannelSource>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub packages: Vec<String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub chips: Vec<DependencyChip>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub languages: Vec<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cache: Option<PathBuf>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub output: Option<PathBuf>,
+	#[serde(default, with = "from_str", skip_serializing_if = "Option::is_none")]
+	pub layout: Option<Layout>,
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub profiles: BTreeMap<String, Profile>,
+}
+
+// A named package set in `[profiles.<name>]`, such as one per architecture, that replaces
+// the packages, chips, and languages of the rest of the file when it is selected.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub packages: Vec<String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub chips: Vec<DependencyChip>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub languages: Vec<String>,
+}
+
+impl Config {
+	// Relative paths in the file are relative to the directory it is in, so it gives the
+	// same install from any directory.
+	pub fn read(path: &Path) -> Result<Config> {
+		let contents = std::fs::read_to_string(path).map_err(Error::file(path))?;
+		let mut config: Config =
+			toml::from_str(&contents).map_err(|error| Error::InvalidConfig {
+				path: path.to_owned(),
+				reason: error.to_string(),
+			})?;
+		let directory = path.parent().unwrap_or_else(|| Path::new(""));
+		for path in [&mut config.cache, &mut config.output]
+			.into_iter()
+			.flatten()
+		{
+			*path = directory.join(&*path);
+		}
+		if let Some(ChannelSource::File(path)) = &mut config.channel {
+			*path = directory.join(&*path);
+		}
+		Ok(config)
+	}
+
+	// Read the config file at the path, or in the current directory when there is no
+	// path. Without a file in the current directory, every setting comes from the flags.
+	pub fn discover(path: Option<&Path>) -> Result<Config> {
+		if let Some(path) = path {
+			return Config::read(path);
+		}
+		let path = Path::new(CONFIG_FILE_NAME);
+		if !path.is_file() {
+			return Ok(Config::default());
+		}
+		tracing::debug!("reading {}", path.display());
+		Config::read(path)
+	}
+
+	// The settings in the overrides replace these, and lists replace lists rather than
+	// extending them.
+	pub fn merge(self, overrides: Config) -> Config {
+		Config {
+			major_version: overrides.major_version.or(self.major_version),
+			channel: overrides.channel.or(self.channel),
+			packages: list(self.packages, overrides.packages),
+			chips: list(self.chips, overrides.chips),
+			languages: list(self.languages, overrides.languages),
+			cache: overrides.cache.or(self.cache),
+			output: overrides.output.or(self.output),
+			layout: overrides.layout.or(self.layout),
+			profiles: if overrides.profiles.is_empty() {
+				self.profiles
+			} else {
+				overrides.profiles
+			},
+		}
+	}
+
+	// The settings with the profile's package set in place of the file's.
+	pub fn profile(&self, name: &str) -> Result<Config> {
+		let profile = self
+			.profiles
+			.get(name)
+			.ok_or_else(|| Error::MissingProfile {
+				name: name.to_owned(),
+			})?
+			.clone();
+		Ok(Config {
+			packages: list(self.packages.clone(), profile.packages),
+			chips: list(self.chips.clone(), profile.chips),
+			languages: list(self.languages.clone(), profile.languages),
+			profiles: BTreeMap::new(),
+			..self.clone()
+		})
+	}
+
+	pub fn to_toml(&self) -> Result<String> {
+		toml::to_string(self).map_err(|error| {
+			std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string()).into()
+		})
+	}
+}
+
+fn list<T>(value: Vec<T>, override_value: Vec<T>) -> Vec<T> {
+	if override_value.is_empty() {
+		value
+	} else {
+		override_value
+	}
+}
+
+// Settings that are written the same way as their flags.
+mod from_str {
+	use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+	pub fn serialize<S, T>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+		T: std::fmt::Display,
+	{
+		match value {
+			Some(value) => serializer.collect_str(value),
+			None => serializer.serialize_none(),
+		}
+	}
+
+	pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+	where
+		D: Deserializer<'de>,
+		T: std::str::FromStr,
+		T::Err: std::fmt::Display,
+	{
+		Option::<String>::deserialize(deserializer)?
+			.map(|value| value.parse().map_err(D::Error::custom))
+			.transpose()
+	}
+}
@@ -0,0 +1,231 @@
+use crate::{
+	get_channel_async,
+	paths::{available_space, existing_ancestor},
+	ChannelItemType, ChannelSource, Error, HttpOptions, Result,
+};
+use duct::cmd;
+use indicatif::HumanBytes;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct DoctorOptions {
+	pub major_version: String,
+	pub channel: ChannelSource,
+	pub cache_path: Option<PathBuf>,
+	pub output_path: Option<PathBuf>,
+	pub http: HttpOptions,
+}
+
+// The tools that use an extracted tree, and the one `--chown` runs.
+const TOOLS: &[(&str, &str)] = &[
+	(
+		"clang-cl",
+		"install clang to compile against the extracted headers",
+	),
+	(
+		"lld-link",
+		"install lld to link against the extracted libraries",
+	),
+	("llvm-rc", "install llvm to compile resource scripts"),
+	("chown", "install coreutils to use --chown"),
+];
+
+// A Windows SDK and MSVC for one architecture take a few gibibytes, and the payloads as
+// much again in the cache.
+const MIN_AVAILABLE_SPACE: u64 = 8 << 30;
+
+enum Outcome {
+	Ok(String),
+	Warning(String, String),
+	Failure(String, String),
+}
+
+// Check that the network, the tools, and the cache and output directories are ready for
+// an install, printing how to fix each problem found. Warnings do not fail the check, as
+// they only affect some uses.
+pub fn doctor(options: DoctorOptions) -> Result<()> {
+	let mut outcomes = Vec::new();
+	let runtime = tokio::runtime::Runtime::new()?;
+	outcomes.push((
+		"channel",
+		runtime.block_on(check_network(
+			&options.major_version,
+			&options.channel,
+			&options.http,
+		)),
+	));
+	for (tool, fix) in TOOLS {
+		outcomes.push(("tool", check_tool(tool, fix)));
+	}
+	if let Some(cache_path) = options.cache_path.as_ref() {
+		outcomes.push(("cache", check_writable(cache_path)));
+		outcomes.push(("cache space", check_available_space(cache_path)));
+	}
+	if let Some(output_path) = options.output_path.as_ref() {
+		outcomes.push(("output", check_writable(output_path)));
+		outcomes.push(("output case", check_case_sensitivity(output_path)));
+		outcomes.push(("output space", check_available_space(output_path)));
+	}
+	let mut failures = 0;
+	for (check, outcome) in outcomes {
+		match outcome {
+			Outcome::Ok(message) => println!("ok {}: {}", check, message),
+			Outcome::Warning(message, fix) => {
+				println!("warning {}: {}\n\tfix: {}", check, message, fix)
+			}
+			Outcome::Failure(message, fix) => {
+				println!("error {}: {}\n\tfix: {}", check, message, fix);
+				failures += 1;
+			}
+		}
+	}
+	if failures > 0 {
+		return Err(Error::DoctorFailed { count: failures });
+	}
+	Ok(())
+}
+
+// Download the channel and request the first bytes of its manifest, which is on the CDN
+// the payloads come from.
+async fn check_network(
+	major_version: &str,
+	channel: &ChannelSource,
+	http: &HttpOptions,
+) -> Outcome {
+	let fix = "check the network connection, or pass --proxy, --ca-cert, or --mirror-base-url";
+	let channel = match get_channel_async(major_version.to_owned(), channel, http).await {
+		Ok(channel) => channel,
+		Err(error) => {
+			return Outcome::Failure(
+				format!("failed to download the channel: {}", error),
+				fix.to_owned(),
+			)
+		}
+	};
+	let manifest = channel
+		.channel_items
+		.iter()
+		.filter(|channel_item| channel_item.ty == ChannelItemType::Manifest)
+		.find_map(|channel_item| {
+			let payload = channel_item.payloads.as_ref()?.first()?;
+			Some((channel_item.version.as_str(), payload.url.clone()))
+		});
+	let (version, manifest_url) = match manifest {
+		Some(manifest) => manifest,
+		None => {
+			return Outcome::Failure(
+				"the channel has no manifest".to_owned(),
+				"pass a different --major-version".to_owned(),
+			)
+		}
+	};
+	let response = match http.client() {
+		Ok(client) => {
+			http.get(&client, manifest_url.clone())
+				.header(reqwest::header::RANGE, "bytes=0-0")
+				.send()
+				.await
+		}
+		Err(error) => return Outcome::Failure(error.to_string(), fix.to_owned()),
+	};
+	match response.and_then(|response| response.error_for_status()) {
+		Ok(_) => Outcome::Ok(format!(
+			"the manifest for {} at {} is reachable",
+			version, manifest_url
+		)),
+		Err(error) => Outcome::Failure(
+			format!("failed to reach {}: {}", manifest_url, error),
+			fix.to_owned(),
+		),
+	}
+}
+
+fn check_tool(tool: &str, fix: &str) -> Outcome {
+	let output = cmd!(tool, "--version")
+		.stdout_capture()
+		.stderr_capture()
+		.unchecked()
+		.run();
+	match output {
+		// Some tools do not print a version, so only show one when there is one.
+		Ok(output) => {
+			let version = String::from_utf8_lossy(&output.stdout);
+			match version.lines().next().map(str::trim) {
+				Some(version) if output.status.success() && !version.is_empty() => {
+					Outcome::Ok(version.to_owned())
+				}
+				_ => Outcome::Ok(format!("{} is installed", tool)),
+			}
+		}
+		Err(_) => Outcome::Warning(format!("{} was not found", tool), fix.to_owned()),
+	}
+}
+
+// Directories that do not exist yet are checked at the ancestor they would be created in,
+// so the check does not create them.
+fn check_writable(path: &Path) -> Outcome {
+	let fix = format!("make {} writable, or pass a different path", path.display());
+	let existing = match existing_ancestor(path) {
+		Some(existing) => existing,
+		None => {
+			return Outcome::Failure(format!("{} has no existing ancestor", path.display()), fix)
+		}
+	};
+	match tempfile::tempfile_in(&existing) {
+		Ok(_) => Outcome::Ok(format!("{} is writable", existing.display())),
+		Err(error) => Outcome::Failure(
+			format!("{} is not writable: {}", existing.display(), error),
+			fix,
+		),
+	}
+}
+
+// The headers and libraries are referenced with inconsistent casing, so case sensitive
+// filesystems need the case variants or the VFS overlay that extraction creates.
+fn check_case_sensitivity(path: &Path) -> Outcome {
+	let path = existing_ancestor(path).unwrap_or_else(|| path.to_owned());
+	let result = tempfile::Builder::new()
+		.prefix(".windows_sdk-Doctor")
+		.tempfile_in(&path)
+		.map(|file| {
+			let file_name = file.path().file_name().unwrap().to_string_lossy();
+			path.join(file_name.to_lowercase()).exists()
+		});
+	match result {
+		Ok(true) => Outcome::Ok(format!("{} is case insensitive", path.display())),
+		Ok(false) => Outcome::Warning(
+			format!("{} is case sensitive", path.display()),
+			"keep the default --case-variants copy, or use --hook vfs-overlay with clang"
+				.to_owned(),
+		),
+		Err(error) => Outcome::Failure(
+			format!("failed to check {}: {}", path.display(), error),
+			"make it writable".to_owned(),
+		),
+	}
+}
+
+fn check_available_space(path: &Path) -> Outcome {
+	match available_space(path) {
+		Some(available) if available < MIN_AVAILABLE_SPACE => Outcome::Warning(
+			format!(
+				"only {} are available at {}",
+				HumanBytes(available),
+				path.display()
+			),
+			format!(
+				"free up space until at least {} are available",
+				HumanBytes(MIN_AVAILABLE_SPACE)
+			),
+		),
+		Some(available) => Outcome::Ok(format!(
+			"{} available at {}",
+			HumanBytes(available),
+			path.display()
+		)),
+		None => Outcome::Warning(
+			format!("failed to find the available space at {}", path.display()),
+			"check the available space by hand".to_owned(),
+		),
+	}
+}
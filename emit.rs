@@ -0,0 +1,75 @@
+use crate::{Cache, Package, Result};
+use std::{collections::HashSet, fmt::Write, path::PathBuf};
+
+// Other ways to provision a resolved selection, printed by choose-packages alongside the
+// selection it writes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EmitFormat {
+	Dockerfile,
+}
+
+impl std::str::FromStr for EmitFormat {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"dockerfile" => Ok(EmitFormat::Dockerfile),
+			_ => Err(format!(
+				"invalid emit format \"{}\", expected dockerfile",
+				value
+			)),
+		}
+	}
+}
+
+impl EmitFormat {
+	pub fn emit(&self, packages: &[Package]) -> Result<String> {
+		match self {
+			EmitFormat::Dockerfile => dockerfile(packages),
+		}
+	}
+}
+
+// A Dockerfile fragment that adds each payload to a cache by URL, pinned to its hash,
+// writes the selection next to it, and extracts it, so an image build provisions the same
+// SDK without downloading the manifest. `ADD --checksum` needs BuildKit with the
+// Dockerfile 1.6 syntax.
+fn dockerfile(packages: &[Package]) -> Result<String> {
+	let cache = Cache::new(PathBuf::from("$WINDOWS_SDK/cache"));
+	let mut dockerfile = String::new();
+	writeln!(
+		dockerfile,
+		"# Needs `# syntax=docker/dockerfile:1.6` or newer on the first line of the Dockerfile, and windows_sdk on the PATH."
+	)
+	.unwrap();
+	writeln!(dockerfile, "ARG WINDOWS_SDK=/opt/windows-sdk").unwrap();
+	let mut seen = HashSet::new();
+	for payload in packages.iter().flat_map(|package| package.payloads.iter()) {
+		if !seen.insert(payload.sha256) {
+			continue;
+		}
+		writeln!(
+			dockerfile,
+			"ADD --checksum=sha256:{} {} {}",
+			hex::encode(payload.sha256),
+			payload.url,
+			cache
+				.payload_path(payload)
+				.display()
+				.to_string()
+				.replace('\\', "/")
+		)
+		.unwrap();
+	}
+	writeln!(
+		dockerfile,
+		"COPY <<'PACKAGES' $WINDOWS_SDK/packages.json\n{}\nPACKAGES",
+		Package::to_json_all(packages)?
+	)
+	.unwrap();
+	writeln!(
+		dockerfile,
+		"RUN windows_sdk extract-packages --packages \"$WINDOWS_SDK/packages.json\" --cache \"$WINDOWS_SDK/cache\" --output \"$WINDOWS_SDK/output\" --progress plain"
+	)
+	.unwrap();
+	Ok(dockerfile)
+}
@@ -0,0 +1,441 @@
+use crate::{DependencyChip, Result};
+use std::{
+	collections::BTreeMap,
+	fmt::Write,
+	path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default)]
+pub struct GenerateEnvOptions {
+	// Only generate files for these architectures. An empty list generates them for every
+	// architecture found in the output.
+	pub chips: Vec<DependencyChip>,
+	pub cmake: bool,
+	pub msbuild: bool,
+}
+
+// The include and library directories of an extracted tree, in either the flat or the splat layout.
+#[derive(Debug, Default)]
+pub struct Directories {
+	pub include: Vec<PathBuf>,
+	pub lib: BTreeMap<&'static str, Vec<PathBuf>>,
+}
+
+pub(crate) const ARCHES: &[(DependencyChip, &str, &str)] = &[
+	(DependencyChip::X86, "x86", "i686"),
+	(DependencyChip::X64, "x64", "x86_64"),
+	(DependencyChip::Arm, "arm", "thumbv7a"),
+	(DependencyChip::Arm64, "arm64", "aarch64"),
+];
+const SDK_INCLUDE_KINDS: &[&str] = &["ucrt", "um", "shared", "winrt", "cppwinrt"];
+const SDK_LIB_KINDS: &[&str] = &["ucrt", "um"];
+
+// Write `env-<arch>.sh` and `env-<arch>.bat` setting INCLUDE and LIB, a
+// `cargo-config.toml` snippet with the library search paths for the `*-pc-windows-msvc`
+// targets, optionally a `toolchain-<arch>.cmake` for clang-cl and lld-link, and
+// optionally a `Directory.Build.props` for MSBuild, into the output directory.
+pub fn generate_env(output_path: PathBuf, options: GenerateEnvOptions) -> Result<()> {
+	let output_path = output_path.canonicalize()?;
+	let directories = find_directories(&output_path);
+	let arches = ARCHES
+		.iter()
+		.filter(|(chip, _, _)| options.chips.is_empty() || options.chips.contains(chip))
+		.filter(|(_, arch, _)| directories.lib.contains_key(arch))
+		.collect::<Vec<_>>();
+	let include = join_paths(&directories.include);
+	let mut cargo_config = String::new();
+	if options.msbuild {
+		std::fs::write(
+			output_path.join("Directory.Build.props"),
+			msbuild_props(&output_path, &directories, &arches),
+		)?;
+	}
+	for (_, arch, target_arch) in arches {
+		let lib_paths = &directories.lib[arch];
+		let lib = join_paths(lib_paths);
+		std::fs::write(
+			output_path.join(format!("env-{}.sh", arch)),
+			format!("export INCLUDE=\"{}\"\nexport LIB=\"{}\"\n", include, lib),
+		)?;
+		std::fs::write(
+			output_path.join(format!("env-{}.bat", arch)),
+			format!("set \"INCLUDE={}\"\r\nset \"LIB={}\"\r\n", include, lib),
+		)?;
+		let rustflags = lib_paths
+			.iter()
+			.map(|path| format!("\"-Lnative={}\"", toml_escape(path)))
+			.collect::<Vec<_>>()
+			.join(", ");
+		writeln!(
+			cargo_config,
+			"[target.{}-pc-windows-msvc]\nrustflags = [{}]\n",
+			target_arch, rustflags
+		)
+		.unwrap();
+		if options.cmake {
+			std::fs::write(
+				output_path.join(format!("toolchain-{}.cmake", arch)),
+				cmake_toolchain(arch, target_arch, &directories.include, lib_paths),
+			)?;
+		}
+	}
+	std::fs::write(output_path.join("cargo-config.toml"), cargo_config)?;
+	Ok(())
+}
+
+pub fn find_directories(output_path: &Path) -> Directories {
+	let mut directories = Directories::default();
+	// The splat layout.
+	directories
+		.include
+		.extend(child(output_path, &["crt", "include"]));
+	for kind in SDK_INCLUDE_KINDS {
+		directories
+			.include
+			.extend(child(output_path, &["sdk", "include", kind]));
+	}
+	for (_, arch, _) in ARCHES {
+		let lib = directories.lib.entry(arch).or_default();
+		lib.extend(child(output_path, &["crt", "lib", arch]));
+		for kind in SDK_LIB_KINDS {
+			lib.extend(child(output_path, &["sdk", "lib", kind, arch]));
+		}
+	}
+	// The flat layout, with one directory per installed version.
+	for msvc_path in children(output_path, &["VC", "Tools", "MSVC"]) {
+		directories.include.extend(child(&msvc_path, &["include"]));
+		for (_, arch, _) in ARCHES {
+			let lib = directories.lib.entry(arch).or_default();
+			lib.extend(child(&msvc_path, &["lib", arch]));
+		}
+	}
+	let sdk_path = ["Program Files", "Windows Kits", "10"];
+	for include_path in children(output_path, &[&sdk_path[..], &["Include"]].concat()) {
+		for kind in SDK_INCLUDE_KINDS {
+			directories.include.extend(child(&include_path, &[kind]));
+		}
+	}
+	for lib_path in children(output_path, &[&sdk_path[..], &["Lib"]].concat()) {
+		for (_, arch, _) in ARCHES {
+			let lib = directories.lib.entry(arch).or_default();
+			for kind in SDK_LIB_KINDS {
+				lib.extend(child(&lib_path, &[kind, arch]));
+			}
+		}
+	}
+	directories.lib.retain(|_, paths| !paths.is_empty());
+	directories
+}
+
+// Write `wine/<arch>/{cl,link,lib,rc}` scripts that run the extracted tools for each
+// target architecture under Wine, with INCLUDE and LIB pointing into the output
+// directory. Wine maps the Unix root to the `Z:` drive.
+// The scripts refer to the tools and directories at the install path, which is where
+// the tree in the output path will be moved, or the output path itself.
+pub fn generate_wine_wrappers(output_path: &Path, install_path: &Path) -> Result<()> {
+	let output_path = output_path.canonicalize()?;
+	let install_path = crate::paths::absolute_path(install_path)?;
+	let installed = |path: &Path| install_path.join(path.strip_prefix(&output_path).unwrap());
+	let directories = find_directories(&output_path);
+	let windows_paths = |paths: &[PathBuf]| {
+		paths
+			.iter()
+			.map(|path| {
+				format!(
+					"Z:{}",
+					installed(path).display().to_string().replace('/', "\\")
+				)
+			})
+			.collect::<Vec<_>>()
+			.join(";")
+	};
+	let include = windows_paths(&directories.include);
+	// Prefer the 64 bit tools of the newest toolset and SDK.
+	let hosts = ["Hostx64", "Hostx86"];
+	// The tools are at `crt/bin/Host<arch>/<arch>` and `sdk/bin/<host>` in the splat
+	// layout, and at `VC/Tools/MSVC/<version>/bin` and
+	// `Program Files/Windows Kits/10/bin/<version>/<host>` in the flat layout.
+	let tools_path = child(&output_path, &["crt", "bin"]).or_else(|| {
+		children(&output_path, &["VC", "Tools", "MSVC"])
+			.pop()
+			.and_then(|msvc_path| child(&msvc_path, &["bin"]))
+	});
+	let mut sdk_bin_paths = children(
+		&output_path,
+		&["Program Files", "Windows Kits", "10", "bin"],
+	);
+	sdk_bin_paths.extend(child(&output_path, &["sdk", "bin"]));
+	let rc_path = sdk_bin_paths.iter().rev().find_map(|path| {
+		hosts
+			.iter()
+			.find_map(|host| child_file(path, &[&host["Host".len()..], "rc.exe"]))
+	});
+	let mut wrapped = false;
+	for (_, arch, _) in ARCHES {
+		let mut tools = Vec::new();
+		if let Some(tools_path) = tools_path.as_ref() {
+			for tool in ["cl", "link", "lib"] {
+				let tool_path = hosts.iter().find_map(|host| {
+					child_file(tools_path, &[host, arch, &format!("{}.exe", tool)])
+				});
+				if let Some(tool_path) = tool_path {
+					tools.push((tool, tool_path));
+				}
+			}
+		}
+		if tools.is_empty() {
+			continue;
+		}
+		wrapped = true;
+		if let Some(rc_path) = rc_path.as_ref() {
+			tools.push(("rc", rc_path.to_owned()));
+		}
+		let lib = windows_paths(directories.lib.get(arch).map(Vec::as_slice).unwrap_or(&[]));
+		let wrapper_dir_path = output_path.join("wine").join(arch);
+		std::fs::create_dir_all(&wrapper_dir_path)?;
+		for (tool, tool_path) in tools {
+			let wrapper_path = wrapper_dir_path.join(tool);
+			let mut script = String::new();
+			writeln!(script, "#!/bin/sh").unwrap();
+			writeln!(script, "export WINEDEBUG=\"${{WINEDEBUG:--all}}\"").unwrap();
+			writeln!(script, "export INCLUDE={}", shell_quote(&include)).unwrap();
+			writeln!(script, "export LIB={}", shell_quote(&lib)).unwrap();
+			writeln!(
+				script,
+				"exec \"${{WINE:-wine}}\" {} \"$@\"",
+				shell_quote(&installed(&tool_path).display().to_string())
+			)
+			.unwrap();
+			std::fs::write(&wrapper_path, script)?;
+			#[cfg(unix)]
+			{
+				use std::os::unix::fs::PermissionsExt;
+				std::fs::set_permissions(&wrapper_path, std::fs::Permissions::from_mode(0o755))?;
+			}
+		}
+	}
+	if !wrapped {
+		tracing::warn!("no compiler or linker found to write wine wrappers for");
+	}
+	Ok(())
+}
+
+// Find the directory at the relative path, matching each component case-insensitively.
+pub(crate) fn child(path: &Path, components: &[&str]) -> Option<PathBuf> {
+	let mut path = path.to_owned();
+	for component in components {
+		path = std::fs::read_dir(&path)
+			.ok()?
+			.filter_map(|entry| entry.ok())
+			.find(|entry| {
+				entry.path().is_dir()
+					&& entry
+						.file_name()
+						.to_string_lossy()
+						.eq_ignore_ascii_case(component)
+			})?
+			.path();
+	}
+	Some(path)
+}
+
+pub(crate) fn child_file(path: &Path, components: &[&str]) -> Option<PathBuf> {
+	let (name, components) = components.split_last()?;
+	std::fs::read_dir(child(path, components)?)
+		.ok()?
+		.filter_map(|entry| entry.ok())
+		.find(|entry| {
+			entry.path().is_file()
+				&& entry
+					.file_name()
+					.to_string_lossy()
+					.eq_ignore_ascii_case(name)
+		})
+		.map(|entry| entry.path())
+}
+
+pub(crate) fn children(path: &Path, components: &[&str]) -> Vec<PathBuf> {
+	let mut paths = child(path, components)
+		.and_then(|path| std::fs::read_dir(path).ok())
+		.into_iter()
+		.flatten()
+		.filter_map(|entry| entry.ok())
+		.map(|entry| entry.path())
+		.filter(|path| path.is_dir())
+		.collect::<Vec<_>>();
+	paths.sort();
+	paths
+}
+
+// Properties for MSBuild C++ projects, such as ones built with `dotnet msbuild`, to
+// import from their own `Directory.Build.props`. The tool directories are the newest
+// versions in the flat layout, or the crt and sdk directories of the splat layout, which
+// has no versions.
+fn msbuild_props(
+	output_path: &Path,
+	directories: &Directories,
+	arches: &[&(DependencyChip, &str, &str)],
+) -> String {
+	let version = |path: &Path| path.file_name().unwrap().to_string_lossy().into_owned();
+	let msvc_path = children(output_path, &["VC", "Tools", "MSVC"]).pop();
+	let sdk_path = child(output_path, &["Program Files", "Windows Kits", "10"]);
+	let sdk_version = children(
+		output_path,
+		&["Program Files", "Windows Kits", "10", "Include"],
+	)
+	.pop();
+	let mut properties = Vec::new();
+	if let Some(sdk_path) = sdk_path.or_else(|| child(output_path, &["sdk"])) {
+		properties.push(("WindowsSdkDir", msbuild_directory(&sdk_path)));
+	}
+	if let Some(sdk_version) = sdk_version {
+		properties.push(("WindowsTargetPlatformVersion", version(&sdk_version)));
+	}
+	match msvc_path.as_ref() {
+		Some(msvc_path) => {
+			properties.push(("VCToolsInstallDir", msbuild_directory(msvc_path)));
+			properties.push(("VCToolsVersion", version(msvc_path)));
+		}
+		None => {
+			if let Some(crt_path) = child(output_path, &["crt"]) {
+				properties.push(("VCToolsInstallDir", msbuild_directory(&crt_path)));
+			}
+		}
+	}
+	properties.push((
+		"IncludePath",
+		format!("{};$(IncludePath)", join_paths(&directories.include)),
+	));
+	let mut props = String::new();
+	writeln!(props, "<?xml version=\"1.0\" encoding=\"utf-8\"?>").unwrap();
+	writeln!(props, "<Project>").unwrap();
+	writeln!(props, "  <PropertyGroup>").unwrap();
+	for (name, value) in properties {
+		writeln!(props, "    <{0}>{1}</{0}>", name, xml_escape(&value)).unwrap();
+	}
+	writeln!(props, "  </PropertyGroup>").unwrap();
+	for (_, arch, _) in arches {
+		let platform = match *arch {
+			"x86" => "Win32",
+			"x64" => "x64",
+			"arm" => "ARM",
+			_ => "ARM64",
+		};
+		let lib = format!("{};$(LibraryPath)", join_paths(&directories.lib[arch]));
+		writeln!(
+			props,
+			"  <PropertyGroup Condition=\"'$(Platform)' == '{}'\">",
+			platform
+		)
+		.unwrap();
+		writeln!(props, "    <LibraryPath>{}</LibraryPath>", xml_escape(&lib)).unwrap();
+		writeln!(props, "  </PropertyGroup>").unwrap();
+	}
+	writeln!(props, "</Project>").unwrap();
+	props
+}
+
+// MSBuild expects directory properties to end in a separator.
+fn msbuild_directory(path: &Path) -> String {
+	format!("{}/", path.display())
+}
+
+fn xml_escape(value: &str) -> String {
+	value
+		.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+}
+
+// clang-cl and lld-link split INCLUDE and LIB on semicolons on every host.
+fn join_paths(paths: &[PathBuf]) -> String {
+	paths
+		.iter()
+		.map(|path| path.display().to_string())
+		.collect::<Vec<_>>()
+		.join(";")
+}
+
+fn shell_quote(value: &str) -> String {
+	format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn toml_escape(path: &Path) -> String {
+	path.display()
+		.to_string()
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+}
+
+fn cmake_toolchain(
+	arch: &str,
+	target_arch: &str,
+	include_paths: &[PathBuf],
+	lib_paths: &[PathBuf],
+) -> String {
+	let processor = match arch {
+		"x86" => "X86",
+		"x64" => "AMD64",
+		"arm" => "ARM",
+		_ => "ARM64",
+	};
+	let target = format!("{}-pc-windows-msvc", target_arch);
+	let compile_flags = include_paths
+		.iter()
+		.map(|path| format!("/imsvc \\\"{}\\\"", path.display()))
+		.collect::<Vec<_>>()
+		.join(" ");
+	let link_flags = lib_paths
+		.iter()
+		.map(|path| format!("/libpath:\\\"{}\\\"", path.display()))
+		.collect::<Vec<_>>()
+		.join(" ");
+	let mut toolchain = String::new();
+	writeln!(toolchain, "set(CMAKE_SYSTEM_NAME Windows)").unwrap();
+	writeln!(toolchain, "set(CMAKE_SYSTEM_PROCESSOR {})", processor).unwrap();
+	writeln!(toolchain, "set(CMAKE_C_COMPILER clang-cl)").unwrap();
+	writeln!(toolchain, "set(CMAKE_CXX_COMPILER clang-cl)").unwrap();
+	writeln!(toolchain, "set(CMAKE_C_COMPILER_TARGET {})", target).unwrap();
+	writeln!(toolchain, "set(CMAKE_CXX_COMPILER_TARGET {})", target).unwrap();
+	writeln!(toolchain, "set(CMAKE_LINKER lld-link)").unwrap();
+	writeln!(toolchain, "set(CMAKE_RC_COMPILER llvm-rc)").unwrap();
+	writeln!(toolchain, "set(CMAKE_MT llvm-mt)").unwrap();
+	writeln!(toolchain, "set(CMAKE_C_FLAGS_INIT \"{}\")", compile_flags).unwrap();
+	writeln!(toolchain, "set(CMAKE_CXX_FLAGS_INIT \"{}\")", compile_flags).unwrap();
+	for kind in ["EXE", "SHARED", "MODULE"] {
+		writeln!(
+			toolchain,
+			"set(CMAKE_{}_LINKER_FLAGS_INIT \"{}\")",
+			kind, link_flags
+		)
+		.unwrap();
+	}
+	toolchain
+}
+
+#[cfg(test)]
+mod tests {
+	use super::generate_wine_wrappers;
+	use std::path::Path;
+
+	fn write(root: &Path, path: &str) {
+		let path = root.join(path);
+		std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+		std::fs::write(path, b"").unwrap();
+	}
+
+	#[test]
+	fn test_wine_wrappers_splat_layout() {
+		let tempdir = tempfile::tempdir().unwrap();
+		let root = tempdir.path();
+		write(root, "crt/bin/Hostx64/x64/cl.exe");
+		write(root, "crt/lib/x64/msvcrt.lib");
+		write(root, "sdk/bin/x64/rc.exe");
+		generate_wine_wrappers(root, root).unwrap();
+		let cl = std::fs::read_to_string(root.join("wine/x64/cl")).unwrap();
+		assert!(cl.contains("crt/bin/Hostx64/x64/cl.exe"));
+		assert!(cl.contains("crt\\lib\\x64"));
+		let rc = std::fs::read_to_string(root.join("wine/x64/rc")).unwrap();
+		assert!(rc.contains("sdk/bin/x64/rc.exe"));
+	}
+}
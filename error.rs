@@ -0,0 +1,298 @@
+use std::path::{Path, PathBuf};
+use url::Url;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+	Io(std::io::Error),
+	// An I/O error reading or writing the file at the path.
+	File {
+		path: PathBuf,
+		source: std::io::Error,
+	},
+	Json(serde_json::Error),
+	Network(reqwest::Error),
+	HashMismatch {
+		url: Url,
+	},
+	InvalidHash(String),
+	MissingPayload {
+		id: String,
+	},
+	MissingChannelItem {
+		ty: crate::ChannelItemType,
+	},
+	MissingChannelUrl {
+		channel: crate::ChannelSource,
+	},
+	MissingPackage {
+		id: String,
+		// The ids in the manifest closest to the requested one.
+		suggestions: Vec<String>,
+	},
+	MissingNamespace {
+		namespace: String,
+	},
+	InvalidNamespace {
+		namespace: String,
+	},
+	MissingCachedPayloads {
+		count: usize,
+	},
+	MissingOwner {
+		path: PathBuf,
+	},
+	MissingVersion {
+		component: &'static str,
+		version: Option<String>,
+	},
+	Extraction {
+		path: PathBuf,
+		source: std::io::Error,
+	},
+	ExtractionTimeout {
+		path: PathBuf,
+		timeout: std::time::Duration,
+	},
+	IncompleteExtraction {
+		count: usize,
+	},
+	UnsupportedArchive {
+		path: PathBuf,
+	},
+	StaleSelection {
+		count: usize,
+	},
+	LockfileMismatch {
+		count: usize,
+	},
+	Lockfile {
+		path: PathBuf,
+		reason: String,
+	},
+	InvalidArgument {
+		argument: String,
+		reason: String,
+	},
+	InvalidPath {
+		path: PathBuf,
+		reason: String,
+	},
+	DoctorFailed {
+		count: usize,
+	},
+	SchemaDrift {
+		count: usize,
+	},
+	InsufficientSpace {
+		path: PathBuf,
+		required: u64,
+		available: u64,
+	},
+	OutputVerificationFailed {
+		count: usize,
+	},
+	ArchiveVerificationFailed {
+		path: PathBuf,
+		count: usize,
+	},
+	InvalidSignatures {
+		count: usize,
+	},
+	UnsupportedSchemaVersion {
+		path: PathBuf,
+		version: u64,
+	},
+	InvalidConfig {
+		path: PathBuf,
+		reason: String,
+	},
+	MissingProfile {
+		name: String,
+	},
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::Io(error) => write!(f, "{}", error),
+			Error::File { path, source } => write!(f, "{}: {}", path.display(), source),
+			Error::Json(error) => write!(f, "failed to parse json: {}", error),
+			Error::Network(error) => write!(f, "network request failed: {}", error),
+			Error::HashMismatch { url } => write!(f, "hash did not match for {}", url),
+			Error::InvalidHash(hash) => write!(f, "invalid sha256 \"{}\"", hash),
+			Error::MissingPayload { id } => write!(f, "channel item {} has no payload", id),
+			Error::MissingChannelItem { ty } => {
+				write!(f, "the channel has no {:?} item", ty)
+			}
+			Error::MissingChannelUrl { channel } => {
+				write!(f, "the channel {} has no URL to download it from", channel)
+			}
+			Error::MissingPackage { id, suggestions } => {
+				write!(f, "no package with id {} in the manifest", id)?;
+				if !suggestions.is_empty() {
+					write!(f, ", did you mean {}?", suggestions.join(", "))?;
+				}
+				Ok(())
+			}
+			Error::MissingCachedPayloads { count } => write!(
+				f,
+				"{} payloads are missing from the cache, and offline mode does not download them",
+				count
+			),
+			Error::MissingOwner { path } => {
+				write!(f, "no package extracted {}", path.display())
+			}
+			Error::MissingVersion { component, version } => match version {
+				Some(version) => write!(
+					f,
+					"no {} version matching {} in the manifest",
+					component, version
+				),
+				None => write!(f, "no {} in the manifest", component),
+			},
+			Error::MissingNamespace { namespace } => {
+				write!(f, "no namespace named {} in the cache", namespace)
+			}
+			Error::InvalidNamespace { namespace } => write!(
+				f,
+				"invalid namespace \"{}\", expected letters, digits, '.', '_', or '-'",
+				namespace
+			),
+			Error::Extraction { path, source } => {
+				write!(f, "failed to extract {}: {}", path.display(), source)
+			}
+			Error::ExtractionTimeout { path, timeout } => write!(
+				f,
+				"extracting {} took longer than {} seconds",
+				path.display(),
+				timeout.as_secs()
+			),
+			Error::IncompleteExtraction { count } => {
+				write!(f, "{} packages failed to extract", count)
+			}
+			Error::UnsupportedArchive { path } => write!(
+				f,
+				"unsupported archive {}, expected a .tar, .tar.zst, or .zip extension",
+				path.display()
+			),
+			Error::InvalidPath { path, reason } => {
+				write!(f, "refusing to use {}: {}", path.display(), reason)
+			}
+			Error::StaleSelection { count } => write!(
+				f,
+				"the selection no longer matches the manifest, found {} differences",
+				count
+			),
+			Error::LockfileMismatch { count } => write!(
+				f,
+				"the selection does not match the lockfile, found {} differences",
+				count
+			),
+			Error::Lockfile { path, reason } => {
+				write!(f, "invalid lockfile {}: {}", path.display(), reason)
+			}
+			Error::InvalidArgument { argument, reason } => {
+				write!(f, "invalid {}: {}", argument, reason)
+			}
+			Error::DoctorFailed { count } => write!(f, "{} checks failed", count),
+			Error::UnsupportedSchemaVersion { path, version } => write!(
+				f,
+				"{} has schema version {}, but this version only reads up to {}, upgrade windows_sdk to read it",
+				path.display(),
+				version,
+				crate::PACKAGES_SCHEMA_VERSION
+			),
+			Error::MissingProfile { name } => {
+				write!(f, "no profile named {} in the config", name)
+			}
+			Error::InvalidConfig { path, reason } => {
+				write!(f, "invalid config {}: {}", path.display(), reason)
+			}
+			Error::OutputVerificationFailed { count } => {
+				write!(f, "the output is incomplete, {} checks failed", count)
+			}
+			Error::ArchiveVerificationFailed { path, count } => write!(
+				f,
+				"{} does not match its manifest, found {} differences",
+				path.display(),
+				count
+			),
+			Error::InvalidSignatures { count } => write!(
+				f,
+				"{} payloads are unsigned or not signed by a trusted certificate",
+				count
+			),
+			Error::SchemaDrift { count } => write!(
+				f,
+				"the manifest does not match the schema, found {} differences",
+				count
+			),
+			Error::InsufficientSpace {
+				path,
+				required,
+				available,
+			} => write!(
+				f,
+				"{} needs {} but only {} are available",
+				path.display(),
+				indicatif::HumanBytes(*required),
+				indicatif::HumanBytes(*available)
+			),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(error) => Some(error),
+			Error::Json(error) => Some(error),
+			Error::Network(error) => Some(error),
+			Error::File { source, .. } => Some(source),
+			Error::Extraction { source, .. } => Some(source),
+			_ => None,
+		}
+	}
+}
+
+impl Error {
+	// Attach the path to the I/O error of a file operation, as in `.map_err(Error::file(path))`.
+	pub(crate) fn file(path: &Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+		move |source| Error::File {
+			path: path.to_owned(),
+			source,
+		}
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(value: std::io::Error) -> Self {
+		Error::Io(value)
+	}
+}
+
+impl From<walkdir::Error> for Error {
+	fn from(value: walkdir::Error) -> Self {
+		Error::Io(value.into())
+	}
+}
+
+impl From<zip::result::ZipError> for Error {
+	fn from(value: zip::result::ZipError) -> Self {
+		Error::Io(value.into())
+	}
+}
+
+impl From<serde_json::Error> for Error {
+	fn from(value: serde_json::Error) -> Self {
+		Error::Json(value)
+	}
+}
+
+impl From<reqwest::Error> for Error {
+	fn from(value: reqwest::Error) -> Self {
+		Error::Network(value)
+	}
+}
@@ -36,10 +36,6 @@
           (lib.optional stdenv.isDarwin darwin.Security)
           (lib.optional stdenv.isDarwin libiconv)
         ];
-        propagatedBuildInputs = with pkgs; [
-          msitools
-          unzip
-        ];
         cargoLock = { lockFile = ./Cargo.lock; };
       };
       devShell = pkgs.mkShell {
@@ -47,9 +43,7 @@
           (lib.optional stdenv.isDarwin darwin.Security)
           (lib.optional stdenv.isDarwin libiconv)
           jq
-          msitools
           rust
-          unzip
           xh
         ];
       };
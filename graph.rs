@@ -0,0 +1,255 @@
+use crate::{DependencyChip, DependencyType, Package};
+use std::{
+	collections::{HashMap, HashSet},
+	fmt::Write,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GraphFormat {
+	Dot,
+	Json,
+}
+
+impl Default for GraphFormat {
+	fn default() -> Self {
+		GraphFormat::Dot
+	}
+}
+
+impl std::str::FromStr for GraphFormat {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"dot" => Ok(GraphFormat::Dot),
+			"json" => Ok(GraphFormat::Json),
+			_ => Err(format!(
+				"invalid graph format \"{}\", expected dot or json",
+				value
+			)),
+		}
+	}
+}
+
+// The dependency graph of a selection. Every dependency of a selected package is an edge,
+// including the optional and recommended ones and those for other chips that the resolver
+// did not follow, so it is possible to trace both why a package was selected and why
+// another was not.
+#[derive(Debug, serde::Serialize)]
+pub struct Graph {
+	pub nodes: Vec<Node>,
+	pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Node {
+	pub id: String,
+	pub version: String,
+	// Whether the package was requested rather than pulled in by another package.
+	pub root: bool,
+	pub size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Edge {
+	pub from: String,
+	pub to: String,
+	#[serde(rename = "type")]
+	pub ty: &'static str,
+	pub chip: Option<DependencyChip>,
+	// Whether the dependency is part of the selection.
+	pub selected: bool,
+}
+
+impl Graph {
+	pub fn new(packages: &[Package], root_package_ids: &[String]) -> Graph {
+		let selected_package_ids = packages
+			.iter()
+			.map(|package| package.id.to_ascii_lowercase())
+			.collect::<HashSet<_>>();
+		let mut seen_package_ids = HashSet::new();
+		let mut nodes = Vec::new();
+		let mut edges = Vec::new();
+		for package in packages.iter() {
+			// Packages with variants for several chips or languages are a single node.
+			if !seen_package_ids.insert(package.id.to_ascii_lowercase()) {
+				continue;
+			}
+			let variants = packages
+				.iter()
+				.filter(|variant| variant.id.eq_ignore_ascii_case(&package.id))
+				.collect::<Vec<_>>();
+			nodes.push(Node {
+				id: package.id.to_owned(),
+				version: package.version.to_owned(),
+				root: root_package_ids
+					.iter()
+					.any(|id| id.eq_ignore_ascii_case(&package.id)),
+				size: variants
+					.iter()
+					.flat_map(|variant| variant.payloads.iter())
+					.map(|payload| payload.size)
+					.sum(),
+			});
+			let mut seen_edges = HashSet::new();
+			for (id, dependency) in variants
+				.iter()
+				.flat_map(|variant| variant.dependencies.iter())
+			{
+				if !seen_edges.insert((id.to_ascii_lowercase(), dependency.chip)) {
+					continue;
+				}
+				edges.push(Edge {
+					from: package.id.to_owned(),
+					to: id.to_owned(),
+					ty: match dependency.ty {
+						None => "required",
+						Some(DependencyType::Recommended) => "recommended",
+						Some(DependencyType::Optional) => "optional",
+					},
+					chip: dependency.chip,
+					selected: selected_package_ids.contains(&id.to_ascii_lowercase()),
+				});
+			}
+		}
+		Graph { nodes, edges }
+	}
+
+	// Remove the nodes of the packages that are only groups of other packages, replacing
+	// each path through them with an edge from the package that depends on the group to
+	// each package the group leads to. The edge takes the weakest type and the innermost
+	// chip along the path, and a requested group makes the packages it leads to roots.
+	pub fn flatten_groups(self, packages: &[Package]) -> Graph {
+		let group_ids = packages
+			.iter()
+			.filter(|package| package.is_group())
+			.map(|package| package.id.to_ascii_lowercase())
+			.collect::<HashSet<_>>();
+		let mut edges_from = HashMap::<String, Vec<&Edge>>::new();
+		for edge in self.edges.iter() {
+			edges_from
+				.entry(edge.from.to_ascii_lowercase())
+				.or_default()
+				.push(edge);
+		}
+		let mut edges = Vec::new();
+		let mut seen_edges = HashSet::new();
+		let mut root_ids = HashSet::new();
+		for node in self.nodes.iter() {
+			let node_id = node.id.to_ascii_lowercase();
+			let is_group = group_ids.contains(&node_id);
+			if is_group && !node.root {
+				continue;
+			}
+			let mut queue = edges_from
+				.get(&node_id)
+				.into_iter()
+				.flatten()
+				.map(|edge| (*edge, edge.ty, edge.chip, edge.selected))
+				.collect::<Vec<_>>();
+			let mut visited = HashSet::new();
+			visited.insert(node_id.clone());
+			while let Some((edge, ty, chip, selected)) = queue.pop() {
+				let to_id = edge.to.to_ascii_lowercase();
+				if group_ids.contains(&to_id) {
+					if !visited.insert(to_id.clone()) {
+						continue;
+					}
+					for next in edges_from.get(&to_id).into_iter().flatten() {
+						let ty = weakest_dependency_type(ty, next.ty);
+						queue.push((next, ty, next.chip.or(chip), selected && next.selected));
+					}
+					continue;
+				}
+				// A requested group contributes roots rather than edges.
+				if is_group {
+					if selected {
+						root_ids.insert(to_id);
+					}
+					continue;
+				}
+				if seen_edges.insert((node_id.clone(), to_id, ty, chip)) {
+					edges.push(Edge {
+						from: node.id.to_owned(),
+						to: edge.to.to_owned(),
+						ty,
+						chip,
+						selected,
+					});
+				}
+			}
+		}
+		let nodes = self
+			.nodes
+			.into_iter()
+			.filter(|node| !group_ids.contains(&node.id.to_ascii_lowercase()))
+			.map(|node| Node {
+				root: node.root || root_ids.contains(&node.id.to_ascii_lowercase()),
+				..node
+			})
+			.collect();
+		Graph { nodes, edges }
+	}
+
+	pub fn to_dot(&self) -> String {
+		let mut dot = String::new();
+		writeln!(dot, "digraph packages {{").unwrap();
+		writeln!(dot, "\tnode [shape=box];").unwrap();
+		for node in self.nodes.iter() {
+			let style = if node.root { ", style=bold" } else { "" };
+			writeln!(
+				dot,
+				"\t{} [label={}{}];",
+				dot_string(&node.id),
+				dot_string(&format!("{}\n{}", node.id, node.version)),
+				style
+			)
+			.unwrap();
+		}
+		for edge in self.edges.iter() {
+			let label = match edge.chip {
+				Some(chip) => format!("{} ({})", edge.ty, chip),
+				None => edge.ty.to_owned(),
+			};
+			// Dependencies that were not selected are drawn dashed.
+			let style = if edge.selected {
+				""
+			} else {
+				", style=dashed, color=gray"
+			};
+			writeln!(
+				dot,
+				"\t{} -> {} [label={}{}];",
+				dot_string(&edge.from),
+				dot_string(&edge.to),
+				dot_string(&label),
+				style
+			)
+			.unwrap();
+		}
+		writeln!(dot, "}}").unwrap();
+		dot
+	}
+}
+
+fn weakest_dependency_type(a: &'static str, b: &'static str) -> &'static str {
+	let rank = |ty: &str| match ty {
+		"optional" => 2,
+		"recommended" => 1,
+		_ => 0,
+	};
+	if rank(a) >= rank(b) {
+		a
+	} else {
+		b
+	}
+}
+
+fn dot_string(value: &str) -> String {
+	format!(
+		"\"{}\"",
+		value
+			.replace('\\', "\\\\")
+			.replace('"', "\\\"")
+			.replace('\n', "\\n")
+	)
+}
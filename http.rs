@@ -0,0 +1,242 @@
+use crate::{Error, Payload, Result};
+use std::{
+	collections::HashSet,
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+use url::Url;
+
+// How to reach the network from behind a corporate proxy or through an internal mirror.
+// Options are set with the builder methods, starting from `HttpOptions::default()`, which
+// uses the proxies from the standard environment variables and the system's root
+// certificates.
+#[derive(Clone, Debug, Default)]
+pub struct HttpOptions {
+	pub proxy: Option<Url>,
+	// PEM files with root certificates to trust in addition to the system's.
+	pub ca_certificates: Vec<PathBuf>,
+	pub headers: Vec<HostHeader>,
+	// Download payloads from this URL instead, with the path of their original URL
+	// appended. Payloads are still verified against the manifest's sha256.
+	pub mirror_base_url: Option<Url>,
+	// Download payloads by their hash from another machine's cache, served by
+	// `windows_sdk serve`. This takes precedence over the mirror.
+	pub cache_server: Option<Url>,
+	// Mirrors to fall back to when downloads do not match their hash, with the same
+	// layout as the mirror.
+	pub alternate_mirror_base_urls: Vec<Url>,
+}
+
+// A header sent with every request to a host, such as the bearer token for a mirror.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HostHeader {
+	pub host: String,
+	pub name: String,
+	pub value: String,
+}
+
+impl std::str::FromStr for HostHeader {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let error = || {
+			format!(
+				"invalid header \"{}\", expected HOST=NAME:VALUE",
+				value.split(':').next().unwrap()
+			)
+		};
+		let (host, header) = value.split_once('=').ok_or_else(error)?;
+		let (name, header_value) = header.split_once(':').ok_or_else(error)?;
+		if host.is_empty() || name.trim().is_empty() {
+			return Err(error());
+		}
+		Ok(HostHeader {
+			host: host.to_owned(),
+			name: name.trim().to_owned(),
+			value: header_value.trim().to_owned(),
+		})
+	}
+}
+
+impl HttpOptions {
+	pub fn proxy(mut self, proxy: Url) -> Self {
+		self.proxy = Some(proxy);
+		self
+	}
+
+	pub fn ca_certificate(mut self, path: PathBuf) -> Self {
+		self.ca_certificates.push(path);
+		self
+	}
+
+	pub fn header(mut self, host: &str, name: &str, value: &str) -> Self {
+		self.headers.push(HostHeader {
+			host: host.to_owned(),
+			name: name.to_owned(),
+			value: value.to_owned(),
+		});
+		self
+	}
+
+	pub fn mirror_base_url(mut self, mirror_base_url: Url) -> Self {
+		self.mirror_base_url = Some(mirror_base_url);
+		self
+	}
+
+	pub fn cache_server(mut self, cache_server: Url) -> Self {
+		self.cache_server = Some(cache_server);
+		self
+	}
+
+	pub fn alternate_mirror_base_url(mut self, mirror_base_url: Url) -> Self {
+		self.alternate_mirror_base_urls.push(mirror_base_url);
+		self
+	}
+
+	pub fn client(&self) -> Result<reqwest::Client> {
+		let mut builder = reqwest::Client::builder();
+		if let Some(proxy) = self.proxy.as_ref() {
+			builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+		}
+		for path in self.ca_certificates.iter() {
+			let certificate_bytes = std::fs::read(path).map_err(Error::file(path))?;
+			builder =
+				builder.add_root_certificate(reqwest::Certificate::from_pem(&certificate_bytes)?);
+		}
+		Ok(builder.build()?)
+	}
+
+	// Build a GET request with the headers registered for the URL's host.
+	pub fn get(&self, client: &reqwest::Client, url: Url) -> reqwest::RequestBuilder {
+		let host = url.host_str().map(|host| host.to_ascii_lowercase());
+		let mut request = client.get(url);
+		for header in self.headers.iter() {
+			if host.as_deref() == Some(header.host.to_ascii_lowercase().as_str()) {
+				request = request.header(header.name.as_str(), header.value.as_str());
+			}
+		}
+		request
+	}
+
+	// Download the URL to the file at the path and return its contents. When the file was
+	// downloaded from the same URL before, the server is asked to send it only if it
+	// changed since, using the validators it sent last time, which are saved next to the
+	// file. Servers that sent no validators are always downloaded from.
+	pub async fn get_cached(
+		&self,
+		client: &reqwest::Client,
+		url: Url,
+		path: &Path,
+		force_refresh: bool,
+	) -> Result<Vec<u8>> {
+		let validators_path = validators_path(path);
+		let validators = std::fs::read(&validators_path)
+			.ok()
+			.and_then(|bytes| serde_json::from_slice::<Validators>(&bytes).ok())
+			.filter(|validators| validators.url == url && !force_refresh && path.is_file());
+		let mut request = self.get(client, url.clone());
+		if let Some(validators) = validators.as_ref() {
+			if let Some(etag) = validators.etag.as_ref() {
+				request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+			}
+			if let Some(last_modified) = validators.last_modified.as_ref() {
+				request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+			}
+		}
+		let response = request.send().await?.error_for_status()?;
+		if validators.is_some() && response.status() == reqwest::StatusCode::NOT_MODIFIED {
+			tracing::info!("{} is unchanged", url);
+			return Ok(std::fs::read(path)?);
+		}
+		let header = |name| {
+			response
+				.headers()
+				.get(name)
+				.and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+				.map(|value| value.to_owned())
+		};
+		let validators = Validators {
+			url,
+			etag: header(reqwest::header::ETAG),
+			last_modified: header(reqwest::header::LAST_MODIFIED),
+		};
+		let bytes = response.bytes().await?.to_vec();
+		std::fs::write(path, &bytes)?;
+		if validators.etag.is_some() || validators.last_modified.is_some() {
+			std::fs::write(&validators_path, serde_json::to_vec(&validators)?)?;
+		} else {
+			std::fs::remove_file(&validators_path).ok();
+		}
+		Ok(bytes)
+	}
+
+	// The URL to download a payload from, on the cache server or the mirror if there is one.
+	pub fn payload_url(&self, payload: &Payload) -> Url {
+		let url = &payload.url;
+		if let Some(cache_server) = self.cache_server.as_ref() {
+			let mut cache_server_url = cache_server.clone();
+			let path = format!(
+				"{}/{}",
+				cache_server.path().trim_end_matches('/'),
+				hex::encode(payload.sha256)
+			);
+			cache_server_url.set_path(&path);
+			return cache_server_url;
+		}
+		match self.mirror_base_url.as_ref() {
+			Some(mirror_base_url) => mirror_url(mirror_base_url, url),
+			None => url.clone(),
+		}
+	}
+
+	// The URLs to try in turn when a download does not match the payload's hash, which is
+	// usually a stale or corrupt copy on a CDN edge: the payload URL, the same URL with a
+	// query parameter that bypasses caches, the original URL if the payload URL is on a
+	// mirror or cache server, and the alternate mirrors.
+	pub fn payload_urls(&self, payload: &Payload) -> Vec<Url> {
+		let payload_url = self.payload_url(payload);
+		let mut cache_busting_url = payload_url.clone();
+		let nonce = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.map(|duration| duration.as_nanos())
+			.unwrap_or(0);
+		cache_busting_url
+			.query_pairs_mut()
+			.append_pair("windows_sdk_cache_bust", &nonce.to_string());
+		let mut urls = vec![payload_url, cache_busting_url, payload.url.clone()];
+		urls.extend(
+			self.alternate_mirror_base_urls
+				.iter()
+				.map(|mirror_base_url| mirror_url(mirror_base_url, &payload.url)),
+		);
+		let mut seen = HashSet::new();
+		urls.retain(|url| seen.insert(url.clone()));
+		urls
+	}
+}
+
+// The `ETag` and `Last-Modified` headers of the response a file was saved from, at
+// `<file>.validators.json`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Validators {
+	url: Url,
+	etag: Option<String>,
+	#[serde(rename = "lastModified")]
+	last_modified: Option<String>,
+}
+
+fn validators_path(path: &Path) -> PathBuf {
+	let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+	path.with_file_name(format!("{}.validators.json", file_name))
+}
+
+fn mirror_url(mirror_base_url: &Url, url: &Url) -> Url {
+	let mut mirror_url = mirror_base_url.clone();
+	let path = format!(
+		"{}/{}",
+		mirror_base_url.path().trim_end_matches('/'),
+		url.path().trim_start_matches('/')
+	);
+	mirror_url.set_path(&path);
+	mirror_url.set_query(url.query());
+	mirror_url
+}
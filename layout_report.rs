@@ -0,0 +1,216 @@
+use crate::{
+	env::{child, children, find_directories, ARCHES},
+	Error, OwnershipDatabase, Result,
+};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	path::{Path, PathBuf},
+};
+
+// What an extracted tree holds, as far as can be told from its directories. The tree may
+// have been produced by another tool or an older version, so nothing here is read from
+// the options it was extracted with, except the versions in the ownership record of a
+// tree in the splat layout, whose paths no longer have them.
+#[derive(Debug, serde::Serialize)]
+pub struct LayoutReport {
+	// flat, splat, mixed, or unknown.
+	pub layout: &'static str,
+	#[serde(rename = "msvcVersions")]
+	pub msvc_versions: Vec<String>,
+	#[serde(rename = "sdkVersions")]
+	pub sdk_versions: Vec<String>,
+	pub include: Vec<PathBuf>,
+	pub lib: BTreeMap<&'static str, Vec<PathBuf>>,
+	// The number of packages in the ownership record, if the tree has one.
+	#[serde(rename = "recordedPackages")]
+	pub recorded_packages: Option<usize>,
+	pub problems: Vec<String>,
+}
+
+const MSVC_PATH: &[&str] = &["VC", "Tools", "MSVC"];
+const SDK_PATH: &[&str] = &["Program Files", "Windows Kits", "10"];
+
+impl LayoutReport {
+	pub fn new(output_path: &Path) -> Result<LayoutReport> {
+		if !output_path.is_dir() {
+			return Err(Error::InvalidPath {
+				path: output_path.to_owned(),
+				reason: "it is not a directory".to_owned(),
+			});
+		}
+		let is_flat =
+			child(output_path, MSVC_PATH).is_some() || child(output_path, SDK_PATH).is_some();
+		let is_splat =
+			child(output_path, &["crt"]).is_some() || child(output_path, &["sdk"]).is_some();
+		let layout = match (is_flat, is_splat) {
+			(true, false) => "flat",
+			(false, true) => "splat",
+			(true, true) => "mixed",
+			(false, false) => "unknown",
+		};
+		let mut problems = Vec::new();
+		match layout {
+			"mixed" => problems.push(
+				"the tree has directories of both the flat and the splat layout, so the include and library paths mix two extractions".to_owned(),
+			),
+			"unknown" => problems.push(
+				"the tree has neither VC/Tools/MSVC and Program Files/Windows Kits/10 nor crt and sdk".to_owned(),
+			),
+			_ => {}
+		}
+		let database = OwnershipDatabase::read(output_path)?;
+		let msvc_versions = version_names(&children(output_path, MSVC_PATH));
+		let sdk_include_versions =
+			version_names(&children(output_path, &[SDK_PATH, &["Include"]].concat()));
+		let sdk_lib_versions =
+			version_names(&children(output_path, &[SDK_PATH, &["Lib"]].concat()));
+		let (msvc_versions, sdk_versions) = if layout == "splat" {
+			database.as_ref().map(recorded_versions).unwrap_or_default()
+		} else {
+			let mut sdk_versions = sdk_include_versions.clone();
+			sdk_versions.extend(sdk_lib_versions.iter().cloned());
+			(msvc_versions, sdk_versions)
+		};
+		for version in sdk_include_versions.difference(&sdk_lib_versions) {
+			problems.push(format!("SDK {} has headers but no libraries", version));
+		}
+		for version in sdk_lib_versions.difference(&sdk_include_versions) {
+			problems.push(format!("SDK {} has libraries but no headers", version));
+		}
+		// Every version is on the include and library paths, so the newest headers may be
+		// used with older libraries.
+		if msvc_versions.len() > 1 {
+			problems.push(format!(
+				"there are {} MSVC versions, {}",
+				msvc_versions.len(),
+				join(&msvc_versions)
+			));
+		}
+		if sdk_versions.len() > 1 {
+			problems.push(format!(
+				"there are {} SDK versions, {}",
+				sdk_versions.len(),
+				join(&sdk_versions)
+			));
+		}
+		let directories = find_directories(output_path);
+		if directories.include.is_empty() && layout != "unknown" {
+			problems.push("there are no include directories".to_owned());
+		}
+		if directories.lib.is_empty() && layout != "unknown" {
+			problems.push("there are no library directories for any architecture".to_owned());
+		}
+		for (_, arch, _) in ARCHES {
+			let has_crt = child(output_path, &["crt", "lib", arch]).is_some()
+				|| children(output_path, MSVC_PATH)
+					.iter()
+					.any(|path| child(path, &["lib", arch]).is_some());
+			let has_sdk = child(output_path, &["sdk", "lib", "um", arch]).is_some()
+				|| children(output_path, &[SDK_PATH, &["Lib"]].concat())
+					.iter()
+					.any(|path| child(path, &["um", arch]).is_some());
+			match (has_crt, has_sdk) {
+				(true, false) => {
+					problems.push(format!("{} has CRT libraries but no SDK libraries", arch))
+				}
+				(false, true) => {
+					problems.push(format!("{} has SDK libraries but no CRT libraries", arch))
+				}
+				_ => {}
+			}
+		}
+		Ok(LayoutReport {
+			layout,
+			msvc_versions: msvc_versions.into_iter().collect(),
+			sdk_versions: sdk_versions.into_iter().collect(),
+			include: directories.include,
+			lib: directories.lib,
+			recorded_packages: database.map(|database| database.packages.len()),
+			problems,
+		})
+	}
+
+	pub fn print(&self) {
+		println!("layout: {}", self.layout);
+		let versions = |versions: &[String]| {
+			if versions.is_empty() {
+				"unknown".to_owned()
+			} else {
+				versions.join(", ")
+			}
+		};
+		println!("msvc: {}", versions(&self.msvc_versions));
+		println!("sdk: {}", versions(&self.sdk_versions));
+		match self.recorded_packages {
+			Some(count) => println!("recorded packages: {}", count),
+			None => println!("recorded packages: none"),
+		}
+		println!("include:");
+		for path in self.include.iter() {
+			println!("\t{}", path.display());
+		}
+		for (arch, paths) in self.lib.iter() {
+			println!("lib {}:", arch);
+			for path in paths {
+				println!("\t{}", path.display());
+			}
+		}
+		for problem in self.problems.iter() {
+			println!("problem: {}", problem);
+		}
+	}
+}
+
+fn version_names(paths: &[PathBuf]) -> BTreeSet<String> {
+	paths
+		.iter()
+		.map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+		.collect()
+}
+
+// The paths in the ownership record are the ones extraction produced, which still have
+// the versions the splat layout drops.
+fn recorded_versions(database: &OwnershipDatabase) -> (BTreeSet<String>, BTreeSet<String>) {
+	let mut msvc_versions = BTreeSet::new();
+	let mut sdk_versions = BTreeSet::new();
+	let matches = |components: &[&str], names: &[&str]| {
+		components.len() > names.len()
+			&& components
+				.iter()
+				.zip(names)
+				.all(|(component, name)| component.eq_ignore_ascii_case(name))
+	};
+	for file in database
+		.packages
+		.iter()
+		.flat_map(|package| package.files.iter())
+	{
+		let components = file.path.split('/').collect::<Vec<_>>();
+		if matches(&components, MSVC_PATH) && components.len() > 4 {
+			msvc_versions.insert(components[3].to_owned());
+		}
+		for kind in ["Include", "Lib"] {
+			if matches(&components, &[SDK_PATH, &[kind]].concat()) && components.len() > 5 {
+				sdk_versions.insert(components[4].to_owned());
+			}
+		}
+	}
+	(msvc_versions, sdk_versions)
+}
+
+fn join(versions: &BTreeSet<String>) -> String {
+	versions.iter().cloned().collect::<Vec<_>>().join(", ")
+}
+
+// Print the layout of an extracted tree, the versions of the MSVC toolset and Windows SDK
+// in it, its include and library directories for each architecture, and anything that
+// looks wrong about its structure.
+pub fn print_layout(output_path: PathBuf, json: bool) -> Result<()> {
+	let report = LayoutReport::new(&output_path)?;
+	if json {
+		println!("{}", serde_json::to_string_pretty(&report)?);
+	} else {
+		report.print();
+	}
+	Ok(())
+}
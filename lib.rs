@@ -1,140 +1,127 @@
+pub mod archive;
+pub mod bench;
+pub mod cache;
+pub mod config;
+pub mod doctor;
+pub mod emit;
+pub mod env;
+mod error;
+pub mod graph;
+pub mod http;
+pub mod layout_report;
+pub mod limits;
+pub mod lockfile;
+mod logging;
+mod manifest;
+pub mod manifest_archive;
+pub mod manifest_diff;
+pub mod msi_metadata;
+pub mod ownership;
+mod paths;
+pub mod progress;
+pub mod sbom;
+pub mod schema;
+pub mod serve;
+pub mod size;
+mod staged_output;
+pub mod stages;
+pub mod verify_output;
+pub mod vfs;
+
+pub use self::{
+	archive::{pack, unpack, PackManifest, PACK_MANIFEST_FILE_NAME},
+	bench::{bench, BenchOptions},
+	cache::{Cache, VerifyCacheOptions},
+	config::{Config, Profile, CONFIG_FILE_NAME},
+	doctor::{doctor, DoctorOptions},
+	emit::EmitFormat,
+	env::{generate_env, GenerateEnvOptions},
+	error::{Error, Result},
+	graph::{Graph, GraphFormat},
+	http::{HostHeader, HttpOptions},
+	layout_report::{print_layout, LayoutReport},
+	limits::Limits,
+	lockfile::Lockfile,
+	logging::init_logging,
+	manifest::*,
+	manifest_archive::{ArchivedManifest, ManifestArchive},
+	manifest_diff::{diff_manifests, DiffManifestsOptions, ManifestDiff},
+	ownership::{owner_of, OwnershipDatabase},
+	progress::{ProgressMode, ProgressSink},
+	sbom::{write_sbom, SbomFormat, SbomOptions},
+	schema::{validate_manifest, SchemaReport, ValidateManifestOptions},
+	size::SizeEstimate,
+	stages::{
+		extract::ExtractFilter,
+		prefetch,
+		resolve::{ChoosePackagesOptions, Target},
+		splat::Layout,
+		verify::{hash_file, verify_payload},
+		Prefetch, Resolution, Stage,
+	},
+	verify_output::{verify_output, VerifyOutputOptions},
+	vfs::{generate_vfs_overlay, resolve_path_case_insensitive},
+};
 use digest::Digest;
-use duct::cmd;
-use futures::{future::join_all, StreamExt};
-use indexmap::IndexMap;
-use indicatif::{ProgressBar, ProgressStyle};
 use sha2::Sha256;
+use staged_output::StagedOutput;
 use std::{
-	collections::{HashMap, HashSet},
-	path::PathBuf,
+	path::{Path, PathBuf},
+	sync::Arc,
 };
-use tempfile::tempdir;
-use tokio::io::AsyncWriteExt;
 use url::Url;
-use walkdir::WalkDir;
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct Channel {
-	#[serde(rename = "channelItems")]
-	channel_items: Vec<ChannelItem>,
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct ChannelItem {
-	id: String,
-	version: String,
-	#[serde(rename = "type")]
-	ty: ChannelItemType,
-	payloads: Option<Vec<Payload>>,
-}
-
-#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
-enum ChannelItemType {
-	Manifest,
-	#[serde(other)]
-	Other,
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct Manifest {
-	#[serde(rename = "manifestVersion")]
-	pub manifest_version: String,
-	#[serde(rename = "engineVersion")]
-	pub engine_version: String,
-	pub packages: Vec<Package>,
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct Package {
-	pub id: String,
-	pub version: String,
-	#[serde(rename = "type")]
-	pub ty: PackageType,
-	#[serde(default)]
-	pub dependencies: IndexMap<String, Dependency>,
-	#[serde(default)]
-	pub payloads: Vec<Payload>,
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-#[serde(from = "DependencyRaw")]
-pub struct Dependency {
-	pub version: String,
-	pub ty: Option<DependencyType>,
-	pub chip: Option<DependencyChip>,
-}
-
-impl From<DependencyRaw> for Dependency {
-	fn from(value: DependencyRaw) -> Self {
-		match value {
-			DependencyRaw::String(version) => Dependency {
-				version,
-				ty: Default::default(),
-				chip: Default::default(),
-			},
-			DependencyRaw::Map { version, ty, chip } => Dependency { version, ty, chip },
-		}
-	}
+
+pub fn get_channel(
+	major_version: String,
+	channel: &ChannelSource,
+	http: &HttpOptions,
+) -> Result<Channel> {
+	tokio::runtime::Runtime::new()?.block_on(get_channel_async(major_version, channel, http))
 }
 
-#[derive(Debug, serde::Deserialize)]
-#[serde(untagged)]
-enum DependencyRaw {
-	String(String),
-	Map {
-		version: String,
-		#[serde(rename = "type")]
-		ty: Option<DependencyType>,
-		chip: Option<DependencyChip>,
-	},
+pub async fn get_channel_async(
+	major_version: String,
+	channel: &ChannelSource,
+	http: &HttpOptions,
+) -> Result<Channel> {
+	let channel_bytes = get_channel_bytes_async(&major_version, channel, http).await?;
+	let channel = serde_json::from_slice(&channel_bytes)?;
+	Ok(channel)
+}
+
+// The channel as served, so it can be saved without round tripping it through `Channel`,
+// which drops the fields it does not model.
+pub async fn get_channel_bytes_async(
+	major_version: &str,
+	channel: &ChannelSource,
+	http: &HttpOptions,
+) -> Result<Vec<u8>> {
+	let channel_url = match channel {
+		ChannelSource::File(path) => return Ok(tokio::fs::read(path).await?),
+		_ => channel
+			.url(major_version)
+			.ok_or_else(|| Error::MissingChannelUrl {
+				channel: channel.clone(),
+			})?,
+	};
+	let channel_bytes = http
+		.get(&http.client()?, channel_url)
+		.send()
+		.await?
+		.error_for_status()?
+		.bytes()
+		.await?;
+	Ok(channel_bytes.to_vec())
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub enum DependencyType {
-	Optional,
-	Recommended,
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub enum DependencyChip {
-	#[serde(rename = "x86", alias = "X86")]
-	X86,
-	#[serde(rename = "x64", alias = "X64")]
-	X64,
-	#[serde(rename = "arm")]
-	Arm,
-	#[serde(rename = "arm64")]
-	Arm64,
-}
-
-#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
-pub enum PackageType {
-	Component,
-	Exe,
-	Group,
-	Msi,
-	Msu,
-	Nupkg,
-	Product,
-	Vsix,
-	WindowsFeature,
-	Workload,
-	Zip,
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct Payload {
-	#[serde(rename = "fileName")]
-	pub file_name: String,
-	#[serde(with = "hex::serde")]
-	pub sha256: [u8; 32],
-	pub size: u64,
-	pub url: Url,
-}
-
-pub fn get_manifest_urls(major_version: String) {
-	let channel_url = format!("https://aka.ms/vs/{}/release/channel", major_version);
-	let channel: Channel = reqwest::blocking::get(channel_url).unwrap().json().unwrap();
+pub fn get_manifest_urls(
+	major_version: String,
+	channel: ChannelSource,
+	archive_path: Option<PathBuf>,
+	http: HttpOptions,
+) -> Result<()> {
+	let channel = get_channel(major_version, &channel, &http)?;
+	let archive = archive_path.map(ManifestArchive::new);
 	for manifest_channel_item in channel
 		.channel_items
 		.iter()
@@ -143,281 +130,1052 @@ pub fn get_manifest_urls(major_version: String) {
 		let manifest_payload = manifest_channel_item
 			.payloads
 			.as_ref()
-			.unwrap()
-			.first()
-			.unwrap();
+			.and_then(|payloads| payloads.first())
+			.ok_or_else(|| Error::MissingPayload {
+				id: manifest_channel_item.id.to_owned(),
+			})?;
+		if let Some(archive) = archive.as_ref() {
+			archive.record(
+				&manifest_payload.url,
+				&manifest_payload.sha256,
+				Some(&manifest_channel_item.version),
+			)?;
+		}
 		println!(
 			"{} {}",
 			manifest_payload.url,
 			hex::encode(manifest_payload.sha256)
 		);
 	}
+	Ok(())
+}
+
+pub fn show_channel(
+	major_version: String,
+	channel: ChannelSource,
+	json: bool,
+	http: HttpOptions,
+) -> Result<()> {
+	let channel = get_channel(major_version, &channel, &http)?;
+	let channel_items = channel
+		.channel_items
+		.iter()
+		.filter(|channel_item| channel_item.ty != ChannelItemType::Other)
+		.collect::<Vec<_>>();
+	if json {
+		println!("{}", serde_json::to_string_pretty(&channel_items)?);
+		return Ok(());
+	}
+	for channel_item in channel_items {
+		println!(
+			"{:?} {} {}",
+			channel_item.ty, channel_item.id, channel_item.version
+		);
+		for payload in channel_item.payloads.iter().flatten() {
+			println!("\t{} {}", payload.url, hex::encode(payload.sha256));
+		}
+	}
+	Ok(())
+}
+
+// Where to find the manifest to download: at a URL with a known sha256, or in the channel
+// for a major version, which lists the URL and sha256 of the current manifest.
+#[derive(Debug)]
+pub enum ManifestSource {
+	Url {
+		url: Url,
+		sha256: String,
+	},
+	Channel {
+		major_version: String,
+		channel: ChannelSource,
+	},
+}
+
+#[derive(Debug, Default)]
+pub struct DownloadManifestOptions {
+	// Refuse a channel whose manifest does not have this sha256.
+	pub expected_sha256: Option<String>,
+	// Save the channel, as served, to this path.
+	pub channel_output: Option<PathBuf>,
+	pub archive_path: Option<PathBuf>,
+	// Download the channel and manifest even when the saved copies are up to date.
+	pub force_refresh: bool,
+	pub http: HttpOptions,
+}
+
+// Download the manifest, verify it against its sha256, and write it to the output path
+// exactly as served. With a channel, the channel version, URL, and sha256 of the manifest
+// are printed. A manifest already at the output path with the sha256 is not downloaded
+// again, and a channel saved with `channel_output` is only downloaded again when the
+// server has a newer one, so running this again when nothing changed makes at most one
+// request.
+pub fn download_manifest(
+	source: ManifestSource,
+	output_path: PathBuf,
+	options: DownloadManifestOptions,
+) -> Result<()> {
+	let decode_hash = |sha256: String| hex::decode(&sha256).map_err(|_| Error::InvalidHash(sha256));
+	let runtime = tokio::runtime::Runtime::new()?;
+	let archive = options.archive_path.map(ManifestArchive::new);
+	let (manifest_url, sha256) = match source {
+		ManifestSource::Url { url, sha256 } => (url, decode_hash(sha256)?),
+		ManifestSource::Channel {
+			major_version,
+			channel,
+		} => {
+			let channel_bytes = match (options.channel_output.as_ref(), channel.url(&major_version))
+			{
+				(Some(channel_output), Some(channel_url)) => {
+					runtime.block_on(options.http.get_cached(
+						&options.http.client()?,
+						channel_url,
+						channel_output,
+						options.force_refresh,
+					))?
+				}
+				(channel_output, _) => {
+					let channel_bytes = runtime.block_on(get_channel_bytes_async(
+						&major_version,
+						&channel,
+						&options.http,
+					))?;
+					if let Some(channel_output) = channel_output {
+						std::fs::write(channel_output, &channel_bytes)
+							.map_err(Error::file(channel_output))?;
+					}
+					channel_bytes
+				}
+			};
+			let channel: Channel = serde_json::from_slice(&channel_bytes)?;
+			let (manifest_channel_item, manifest_payload) = channel.manifest()?;
+			if let Some(expected_sha256) = options.expected_sha256 {
+				if decode_hash(expected_sha256)? != manifest_payload.sha256 {
+					return Err(Error::HashMismatch {
+						url: manifest_payload.url.clone(),
+					});
+				}
+			}
+			if let Some(archive) = archive.as_ref() {
+				archive.record(
+					&manifest_payload.url,
+					&manifest_payload.sha256,
+					Some(&manifest_channel_item.version),
+				)?;
+			}
+			println!(
+				"{} {} {}",
+				manifest_channel_item.version,
+				manifest_payload.url,
+				hex::encode(manifest_payload.sha256)
+			);
+			(
+				manifest_payload.url.clone(),
+				manifest_payload.sha256.to_vec(),
+			)
+		}
+	};
+	if !options.force_refresh && archive.is_none() {
+		if let Ok(manifest_bytes) = std::fs::read(&output_path) {
+			if Sha256::new_with_prefix(&manifest_bytes)
+				.finalize()
+				.as_slice() == sha256
+			{
+				tracing::info!("{} is up to date", output_path.display());
+				return Ok(());
+			}
+		}
+	}
+	let manifest_bytes = runtime.block_on(fetch_manifest(
+		manifest_url,
+		&sha256,
+		archive.as_ref(),
+		&options.http,
+	))?;
+	std::fs::write(&output_path, &manifest_bytes).map_err(Error::file(&output_path))?;
+	Ok(())
 }
 
-pub fn download_manifest(manifest_url: Url, sha256: String, output_path: PathBuf) {
-	let manifest_bytes = reqwest::blocking::get(manifest_url)
-		.unwrap()
+// With an archive, a manifest it already holds is read from it instead of downloaded, so
+// manifests the channel no longer serves can still be used, and every manifest downloaded
+// is added to it.
+async fn fetch_manifest(
+	manifest_url: Url,
+	sha256: &[u8],
+	archive: Option<&ManifestArchive>,
+	http: &HttpOptions,
+) -> Result<Vec<u8>> {
+	if let Some(archive) = archive {
+		if let Some(manifest_bytes) = archive.read(sha256)? {
+			archive.record(&manifest_url, sha256, None)?;
+			return Ok(manifest_bytes);
+		}
+	}
+	let manifest_bytes = http
+		.get(&http.client()?, manifest_url.clone())
+		.send()
+		.await?
+		.error_for_status()?
 		.bytes()
-		.unwrap();
-	let sha256 = hex::decode(sha256).unwrap();
+		.await?;
 	if Sha256::new_with_prefix(&manifest_bytes)
 		.finalize()
 		.as_slice()
 		!= sha256
 	{
-		panic!("hash did not match");
+		return Err(Error::HashMismatch { url: manifest_url });
+	}
+	if let Some(archive) = archive {
+		archive.store(sha256, &manifest_bytes)?;
+		archive.record(&manifest_url, sha256, None)?;
 	}
-	std::fs::write(output_path, &manifest_bytes).unwrap();
+	Ok(manifest_bytes.to_vec())
 }
 
-pub fn choose_packages(manifest: PathBuf, package_ids: Vec<String>, output_path: PathBuf) {
-	// Load the manifest.
-	let manifest = std::fs::read(manifest).unwrap();
-	let manifest: Manifest = serde_json::from_slice(&manifest).unwrap();
-	// Find the payloads for all recursive dependencies of the requested packages.
-	let mut package_id_queue = package_ids
-		.iter()
-		.map(|package_id| package_id.to_owned())
-		.collect::<Vec<_>>();
-	let mut seen_package_ids = package_ids
-		.iter()
-		.map(|package_id| package_id.to_owned())
-		.collect::<HashSet<_>>();
-	let mut packages = Vec::new();
-	while let Some(package_id) = package_id_queue.pop() {
-		for package in manifest
-			.packages
-			.iter()
-			.filter(|package| package.id.eq_ignore_ascii_case(&package_id))
-		{
-			packages.push(package);
-			for (id, dependency) in package.dependencies.iter() {
-				if !seen_package_ids.contains(&id.to_ascii_lowercase()) && dependency.ty.is_none() {
-					package_id_queue.push(id.to_owned());
-					seen_package_ids.insert(id.to_ascii_lowercase());
-				}
+pub fn list_archived_manifests(archive_path: PathBuf, json: bool) -> Result<()> {
+	let archive = ManifestArchive::new(archive_path);
+	let entries = archive.entries()?;
+	if json {
+		println!("{}", serde_json::to_string_pretty(&entries)?);
+		return Ok(());
+	}
+	for entry in entries.iter() {
+		let stored = archive.read(&entry.sha256)?.is_some();
+		println!(
+			"{} {} {} {}{}",
+			entry.seen,
+			entry.channel_version.as_deref().unwrap_or("-"),
+			hex::encode(&entry.sha256),
+			entry.url,
+			if stored { "" } else { " (not stored)" }
+		);
+	}
+	Ok(())
+}
+
+// Where to write a lockfile for a selection, and where the manifest came from, since a
+// manifest file does not record its own URL or channel version.
+#[derive(Debug)]
+pub struct LockOptions {
+	pub path: PathBuf,
+	pub manifest_url: Option<Url>,
+	pub channel_version: Option<String>,
+}
+
+// What choose-packages writes or prints alongside the selection.
+#[derive(Debug, Default)]
+pub struct SelectionOutputs {
+	pub lock: Option<LockOptions>,
+	pub emit: Option<EmitFormat>,
+	pub sbom: Option<SbomOptions>,
+}
+
+pub fn choose_packages(
+	manifest: PathBuf,
+	package_ids: Vec<String>,
+	options: ChoosePackagesOptions,
+	output_path: PathBuf,
+	explain: bool,
+	outputs: SelectionOutputs,
+) -> Result<()> {
+	paths::check_output_file(&manifest, &output_path)?;
+	let manifest_bytes = std::fs::read(&manifest).map_err(Error::file(&manifest))?;
+	let manifest_sha256 = Sha256::new_with_prefix(&manifest_bytes).finalize().into();
+	let manifest: Manifest = serde_json::from_slice(&manifest_bytes)?;
+	let resolution = stages::Resolve {
+		package_ids,
+		options,
+	}
+	.run(&manifest)?;
+	for warning in resolution.warnings.iter() {
+		tracing::warn!("{}", warning);
+	}
+	if explain {
+		for decision in resolution.decisions.iter() {
+			println!("{}", decision);
+		}
+	}
+	if let Some(lock) = outputs.lock {
+		Lockfile::new(
+			lock.channel_version,
+			lock.manifest_url,
+			manifest_sha256,
+			&resolution.packages,
+		)
+		.write(&lock.path)?;
+	}
+	Package::write_all(&resolution.packages, &output_path)?;
+	if let Some(sbom) = outputs.sbom.as_ref() {
+		write_sbom(&resolution.packages, sbom)?;
+	}
+	if let Some(emit) = outputs.emit {
+		print!("{}", emit.emit(&resolution.packages)?);
+	}
+	Ok(())
+}
+
+// A named package set chosen alongside others from one manifest.
+#[derive(Debug)]
+pub struct PackageSet {
+	pub name: String,
+	pub package_ids: Vec<String>,
+	pub options: ChoosePackagesOptions,
+}
+
+// Choose each package set from the manifest, parsing it only once, and write each
+// selection to `<name>.json` in the output directory, such as to provision the sysroots
+// of several architectures together.
+pub fn choose_package_sets(
+	manifest: PathBuf,
+	package_sets: Vec<PackageSet>,
+	output_path: PathBuf,
+	explain: bool,
+) -> Result<()> {
+	let manifest = Manifest::read(&manifest)?;
+	std::fs::create_dir_all(&output_path)?;
+	for package_set in package_sets {
+		let resolution = stages::Resolve {
+			package_ids: package_set.package_ids,
+			options: package_set.options,
+		}
+		.run(&manifest)?;
+		for warning in resolution.warnings.iter() {
+			tracing::warn!("{}: {}", package_set.name, warning);
+		}
+		if explain {
+			for decision in resolution.decisions.iter() {
+				println!("{}: {}", package_set.name, decision);
 			}
 		}
+		let selection_path = output_path.join(format!("{}.json", package_set.name));
+		Package::write_all(&resolution.packages, &selection_path)?;
+		tracing::info!(
+			"chose {} packages for {} in {}",
+			resolution.packages.len(),
+			package_set.name,
+			selection_path.display()
+		);
+	}
+	Ok(())
+}
+
+// Print how much the packages that choose-packages would select download and take once
+// extracted, and check that the cache has room for the downloads.
+pub fn estimate_size(
+	manifest: PathBuf,
+	package_ids: Vec<String>,
+	options: ChoosePackagesOptions,
+	cache_path: Option<PathBuf>,
+) -> Result<()> {
+	let manifest = Manifest::read(&manifest)?;
+	let resolution = stages::Resolve {
+		package_ids,
+		options,
 	}
-	let packages_bytes = serde_json::to_vec_pretty(&packages).unwrap();
-	std::fs::write(output_path, &packages_bytes).unwrap();
+	.run(&manifest)?;
+	for warning in resolution.warnings.iter() {
+		tracing::warn!("{}", warning);
+	}
+	let cache = cache_path.map(Cache::new);
+	let estimate = SizeEstimate::new(&resolution.packages, cache.as_ref())?;
+	estimate.print();
+	estimate.check_available_space(cache.as_ref().map(Cache::path), None)
 }
 
-pub fn download_packages(packages_path: PathBuf, cache_path: PathBuf) {
-	// Read the packages.
-	let packages_bytes = std::fs::read(packages_path).unwrap();
-	let packages: Vec<Package> = serde_json::from_slice(&packages_bytes).unwrap();
-	// Create the cache path if necessary.
-	if !cache_path.exists() {
-		std::fs::create_dir_all(&cache_path).unwrap();
+// Print the dependency graph of the packages that choose-packages would select.
+pub fn graph_packages(
+	manifest: PathBuf,
+	package_ids: Vec<String>,
+	options: ChoosePackagesOptions,
+	format: GraphFormat,
+	flatten_groups: bool,
+) -> Result<()> {
+	let manifest = Manifest::read(&manifest)?;
+	let resolution = stages::Resolve {
+		package_ids,
+		options,
 	}
-	// Download the payloads from all the packages.
-	let total_size = packages
+	.run(&manifest)?;
+	// The packages for the targets are roots as much as the requested ones.
+	let root_package_ids = resolution
+		.decisions
 		.iter()
-		.flat_map(|package| package.payloads.iter())
-		.map(|payload| payload.size)
-		.sum();
-	let progress_bar_style = ProgressStyle::default_bar()
-		.template("[{wide_bar}] {bytes} / {total_bytes}")
-		.progress_chars("=> ");
-	let progress_bar = ProgressBar::new(total_size).with_style(progress_bar_style);
-	tokio::runtime::Runtime::new()
-		.unwrap()
-		.block_on(join_all(packages.into_iter().map(|package| {
-			let cache_path = cache_path.clone();
-			let progress_bar = progress_bar.clone();
-			async move {
-				for payload in package.payloads {
-					let payload_cache_path = cache_path.join(hex::encode(payload.sha256));
-					if payload_cache_path.exists() {
-						let bytes = tokio::fs::read(payload_cache_path).await.unwrap();
-						progress_bar.inc(payload.size);
-						let mut sha256 = Sha256::new();
-						sha256.update(&bytes);
-						let sha256 = sha256.finalize();
-						if sha256.as_slice() != payload.sha256 {
-							panic!("hash did not match for cached payload {}", payload.url,);
-						}
-					} else {
-						let mut stream = reqwest::get(payload.url.to_owned())
-							.await
-							.unwrap()
-							.bytes_stream();
-						let mut file = tokio::fs::File::create(&payload_cache_path).await.unwrap();
-						let mut sha256 = Sha256::new();
-						while let Some(chunk) = stream.next().await {
-							let chunk = chunk.unwrap();
-							let chunk_size = chunk.len() as u64;
-							sha256.update(&chunk);
-							file.write_all(&chunk).await.unwrap();
-							progress_bar.inc(chunk_size);
-						}
-						let sha256 = sha256.finalize();
-						if sha256.as_slice() != payload.sha256 {
-							panic!("hash did not match for downloaded payload {}", payload.url,);
-						}
-					}
-				}
-			}
-		})));
-	progress_bar.finish();
+		.filter_map(|decision| match decision {
+			stages::Decision::Requested { id }
+			| stages::Decision::Added {
+				id,
+				option: "--target",
+			} => Some(id.to_owned()),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	let mut graph = Graph::new(&resolution.packages, &root_package_ids);
+	if flatten_groups {
+		graph = graph.flatten_groups(&resolution.packages);
+	}
+	match format {
+		GraphFormat::Dot => print!("{}", graph.to_dot()),
+		GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&graph)?),
+	}
+	Ok(())
 }
 
-pub fn extract_packages(packages_path: PathBuf, cache_path: PathBuf, output_path: PathBuf) {
-	// Read the packages.
-	let packages_bytes = std::fs::read(packages_path).unwrap();
-	let packages: Vec<Package> = serde_json::from_slice(&packages_bytes).unwrap();
-	// Clean and create the output path.
-	if output_path.exists() {
-		std::fs::remove_dir_all(&output_path).unwrap();
+pub fn verify_selection(manifest: PathBuf, packages_path: PathBuf) -> Result<()> {
+	let manifest = Manifest::read(&manifest)?;
+	let packages = Package::read_all(&packages_path)?;
+	let drift = stages::selection_drift(&manifest, &packages);
+	for difference in drift.iter() {
+		println!("{}", difference);
+	}
+	if !drift.is_empty() {
+		return Err(Error::StaleSelection { count: drift.len() });
 	}
-	std::fs::create_dir_all(&output_path).unwrap();
-	let total_size = packages
+	Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct ListPackagesOptions {
+	pub filter: Option<PackageFilter>,
+	pub ty: Option<PackageType>,
+	// Leave out the packages that are only groups of other packages.
+	pub flatten_groups: bool,
+	pub json: bool,
+}
+
+// Print the id, version, type, chip, language, and total payload size of each package in
+// the manifest.
+pub fn list_packages(manifest: PathBuf, options: ListPackagesOptions) -> Result<()> {
+	let manifest = Manifest::read(&manifest)?;
+	let packages = manifest
+		.packages
 		.iter()
-		.flat_map(|package| package.payloads.iter())
-		.map(|payload| payload.size)
-		.sum();
-	let progress_bar_style = ProgressStyle::default_bar()
-		.template("[{wide_bar}] {bytes} / {total_bytes}")
-		.progress_chars("=> ");
-	let progress_bar = ProgressBar::new(total_size).with_style(progress_bar_style);
+		.filter(|package| {
+			options
+				.filter
+				.as_ref()
+				.map(|filter| filter.matches(&package.id))
+				.unwrap_or(true)
+		})
+		.filter(|package| options.ty.map(|ty| package.ty == ty).unwrap_or(true))
+		.filter(|package| !options.flatten_groups || !package.is_group())
+		.collect::<Vec<_>>();
+	let chip = |package: &Package| {
+		package
+			.chip
+			.clone()
+			.or_else(|| package.machine_arch.clone())
+	};
+	let size = |package: &Package| {
+		package
+			.payloads
+			.iter()
+			.map(|payload| payload.size)
+			.sum::<u64>()
+	};
+	if options.json {
+		let packages = packages
+			.iter()
+			.map(|package| {
+				serde_json::json!({
+					"id": package.id,
+					"version": package.version,
+					"type": package.ty,
+					"chip": chip(package),
+					"language": package.language,
+					"size": size(package),
+				})
+			})
+			.collect::<Vec<_>>();
+		println!("{}", serde_json::to_string_pretty(&packages)?);
+		return Ok(());
+	}
 	for package in packages {
-		let package_tempdir = tempdir().unwrap();
-		for payload in package.payloads.iter() {
-			let payload_cache_path = cache_path.join(hex::encode(payload.sha256));
-			let payload_tempdir_path = package_tempdir
-				.path()
-				.join(payload.file_name.replace("\\", "/"));
-			std::fs::create_dir_all(payload_tempdir_path.parent().unwrap()).unwrap();
-			std::fs::copy(payload_cache_path, payload_tempdir_path).unwrap();
+		println!(
+			"{} {} {:?} {} {} {}",
+			package.id,
+			package.version,
+			package.ty,
+			chip(package).as_deref().unwrap_or("-"),
+			package.language.as_deref().unwrap_or("-"),
+			size(package)
+		);
+	}
+	Ok(())
+}
+
+pub fn search_packages(
+	manifest: PathBuf,
+	query: Option<String>,
+	category: Option<String>,
+) -> Result<()> {
+	let manifest = Manifest::read(&manifest)?;
+	// Print the packages whose id, title, or description contain the query and whose
+	// category contains the requested category.
+	let contains = |haystack: &str, needle: &str| {
+		haystack
+			.to_lowercase()
+			.contains(needle.to_lowercase().as_str())
+	};
+	for package in manifest.packages.iter() {
+		if let Some(category) = category.as_ref() {
+			match package.category.as_ref() {
+				Some(package_category) if contains(package_category, category) => {}
+				_ => continue,
+			}
 		}
-		for payload in package.payloads.iter() {
-			let payload_tempdir_path = package_tempdir
-				.path()
-				.join(payload.file_name.replace("\\", "/"));
-			enum ExtractionType {
-				Msi,
-				Vsix,
+		if let Some(query) = query.as_ref() {
+			let matches = contains(&package.id, query)
+				|| package.localized_resources.iter().any(|resource| {
+					resource
+						.title
+						.iter()
+						.chain(resource.description.iter())
+						.any(|text| contains(text, query))
+				});
+			if !matches {
+				continue;
 			}
-			let extraction_type = if payload.file_name.ends_with(".msi") {
-				Some(ExtractionType::Msi)
-			} else if payload.file_name.ends_with(".vsix") {
-				Some(ExtractionType::Vsix)
-			} else {
-				None
-			};
-			match extraction_type {
-				None => {}
-				Some(ExtractionType::Msi) => {
-					cmd!("msiextract", "-C", &output_path, &payload_tempdir_path)
-						.stderr_null()
-						.stdout_null()
-						.run()
-						.unwrap();
-				}
-				Some(ExtractionType::Vsix) => {
-					let unzip_tempdir = tempdir().unwrap();
-					cmd!(
-						"unzip",
-						"-qq",
-						&payload_tempdir_path,
-						"-d",
-						unzip_tempdir.path()
-					)
-					.read()
-					.unwrap();
-					if let Ok(contents) = std::fs::read_dir(unzip_tempdir.path().join("Contents")) {
-						for entry in contents {
-							cmd!("cp", "-r", entry.unwrap().path(), &output_path)
-								.run()
-								.unwrap();
-						}
-					}
-				}
+		}
+		let title = package
+			.localized_resource()
+			.and_then(|resource| resource.title.as_deref())
+			.unwrap_or("");
+		println!("{} {} {}", package.id, package.version, title);
+	}
+	Ok(())
+}
+
+#[derive(Debug)]
+pub struct DownloadPackagesOptions {
+	pub namespace: String,
+	pub payload_types: Vec<String>,
+	pub progress: ProgressMode,
+	pub retry: stages::Retry,
+	pub limits: Limits,
+	// Refuse to download packages that do not match this lockfile.
+	pub locked: Option<PathBuf>,
+	pub http: HttpOptions,
+	// Fail instead of downloading payloads that are missing from the cache.
+	pub offline: bool,
+	// Print how much would be downloaded and extracted instead of downloading.
+	pub dry_run: bool,
+	// Download even if the cache or output filesystem looks too small.
+	pub skip_space_check: bool,
+	// Check the Authenticode signatures of MSI and EXE payloads after verifying their hashes.
+	pub verify_signatures: bool,
+	// Certificates to trust for signatures in addition to the Microsoft roots.
+	pub trusted_roots: Vec<PathBuf>,
+}
+
+impl Default for DownloadPackagesOptions {
+	fn default() -> Self {
+		DownloadPackagesOptions {
+			namespace: "default".to_owned(),
+			payload_types: Vec::new(),
+			progress: ProgressMode::default(),
+			retry: stages::Retry::default(),
+			limits: Limits::default(),
+			locked: None,
+			http: HttpOptions::default(),
+			offline: false,
+			dry_run: false,
+			skip_space_check: false,
+			verify_signatures: false,
+			trusted_roots: Vec::new(),
+		}
+	}
+}
+
+pub fn download_packages(
+	packages_path: PathBuf,
+	cache_path: PathBuf,
+	options: DownloadPackagesOptions,
+) -> Result<()> {
+	download_package_sets(vec![packages_path], cache_path, options)
+}
+
+// Download the payloads of several selections at once, sharing the connections and
+// verifying each payload once, however many selections include it.
+pub fn download_package_sets(
+	packages_paths: Vec<PathBuf>,
+	cache_path: PathBuf,
+	options: DownloadPackagesOptions,
+) -> Result<()> {
+	let progress = terminal_progress(options.progress);
+	tokio::runtime::Runtime::new()?.block_on(download_package_sets_async(
+		packages_paths,
+		cache_path,
+		options,
+		progress,
+	))
+}
+
+// Like `download_packages`, but runs on the caller's runtime and reports progress to the
+// sink instead of the terminal, ignoring `options.progress`.
+pub async fn download_packages_async(
+	packages_path: PathBuf,
+	cache_path: PathBuf,
+	options: DownloadPackagesOptions,
+	progress: Arc<dyn ProgressSink>,
+) -> Result<()> {
+	download_package_sets_async(vec![packages_path], cache_path, options, progress).await
+}
+
+// Like `download_package_sets`, but runs on the caller's runtime and reports progress to
+// the sink. A lockfile pins a single selection, so `options.locked` needs exactly one.
+pub async fn download_package_sets_async(
+	packages_paths: Vec<PathBuf>,
+	cache_path: PathBuf,
+	options: DownloadPackagesOptions,
+	progress: Arc<dyn ProgressSink>,
+) -> Result<()> {
+	// Packages in several selections, such as the headers shared by every architecture,
+	// are only counted once.
+	let mut packages = Vec::new();
+	let mut seen = std::collections::HashSet::new();
+	for packages_path in packages_paths.iter() {
+		for package in Package::read_all(packages_path)? {
+			let key = (
+				package.id.to_ascii_lowercase(),
+				package.version.clone(),
+				package.chip.clone(),
+				package.machine_arch.clone(),
+				package.language.clone(),
+			);
+			if seen.insert(key) {
+				packages.push(package);
 			}
-			progress_bar.inc(payload.size);
 		}
 	}
-	progress_bar.finish();
-
-	// Lowercase all header and import library names.
-	let header_paths = || {
-		WalkDir::new(&output_path)
-			.into_iter()
-			.filter_map(|entry| {
-				let entry = entry.unwrap();
-				let extension = entry.path().extension().map(|e| e.to_str().unwrap());
-				match extension {
-					Some("h") => Some(entry.path().to_owned()),
-					_ => None,
-				}
-			})
-			.collect::<Vec<_>>()
+	if let Some(locked) = options.locked.as_ref() {
+		if packages_paths.len() != 1 {
+			return Err(Error::Lockfile {
+				path: locked.clone(),
+				reason: "a lockfile checks exactly one selection".to_owned(),
+			});
+		}
+		Lockfile::read(locked)?.check(&packages)?;
+	}
+	download(packages, &Cache::new(cache_path), None, &options, progress).await
+}
+
+// The space check also counts the extracted size when given the output path the
+// packages will be extracted to.
+async fn download(
+	mut packages: Vec<Package>,
+	cache: &Cache,
+	output_path: Option<&Path>,
+	options: &DownloadPackagesOptions,
+	progress: Arc<dyn ProgressSink>,
+) -> Result<()> {
+	// Skip the payloads whose types were not requested.
+	for package in packages.iter_mut() {
+		package
+			.payloads
+			.retain(|payload| stages::matches_payload_types(payload, &options.payload_types));
+	}
+	if options.dry_run || !options.skip_space_check {
+		let estimate = SizeEstimate::new(&packages, Some(cache))?;
+		if options.dry_run {
+			estimate.print();
+		}
+		estimate.check_available_space(Some(cache.path()), output_path)?;
+		if options.dry_run {
+			return Ok(());
+		}
+	}
+	cache.create()?;
+	cache.record_namespace(&options.namespace, &packages)?;
+	let fetched = stages::Fetch {
+		cache: cache.clone(),
+		progress: progress.clone(),
+		retry: options.retry.clone(),
+		jobs: options.limits.download_jobs,
+		per_host_connections: options.limits.per_host_connections,
+		download_rate: options.limits.download_rate,
+		http: options.http.clone(),
+		offline: options.offline,
+	}
+	.fetch(packages)
+	.await?;
+	let verify = stages::Verify {
+		cache: cache.clone(),
+		progress: progress.clone(),
+		jobs: options.limits.hash_jobs,
 	};
-	let import_library_paths = || {
-		WalkDir::new(&output_path)
-			.into_iter()
-			.filter_map(|entry| {
-				let entry = entry.unwrap();
-				let extension = entry.path().extension().map(|e| e.to_str().unwrap());
-				match extension {
-					Some("lib") | Some("Lib") => Some(entry.path().to_owned()),
-					_ => None,
-				}
-			})
-			.collect::<Vec<_>>()
+	let packages = blocking(move || verify.run(fetched)).await?;
+	if options.verify_signatures {
+		let verify_signatures = stages::VerifySignatures {
+			cache: cache.clone(),
+			progress,
+			jobs: options.limits.hash_jobs,
+			trusted_roots: options.trusted_roots.clone(),
+		};
+		blocking(move || verify_signatures.run(packages)).await?;
+	}
+	Ok(())
+}
+
+fn terminal_progress(mode: ProgressMode) -> Arc<dyn ProgressSink> {
+	Arc::new(progress::TerminalProgress::new(mode))
+}
+
+// Run hashing and extraction on the blocking thread pool so they do not stall the caller's runtime.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+	T: Send + 'static,
+	F: FnOnce() -> Result<T> + Send + 'static,
+{
+	match tokio::task::spawn_blocking(f).await {
+		Ok(result) => result,
+		Err(error) => std::panic::resume_unwind(error.into_panic()),
+	}
+}
+
+pub fn serve_cache(cache_path: PathBuf, address: std::net::SocketAddr) -> Result<()> {
+	let cache = Cache::new(cache_path);
+	cache.migrate()?;
+	tokio::runtime::Runtime::new()?.block_on(serve::serve(cache, address))
+}
+
+pub fn gc_cache(cache_path: PathBuf, namespace: Option<String>) -> Result<()> {
+	let collected = Cache::new(cache_path).gc(namespace.as_deref())?;
+	println!(
+		"removed {} payloads, freeing {} bytes",
+		collected.count, collected.size
+	);
+	Ok(())
+}
+
+pub fn prune_cache(cache_path: PathBuf, packages_paths: Vec<PathBuf>) -> Result<()> {
+	let mut packages = Vec::new();
+	for packages_path in packages_paths.iter() {
+		packages.extend(Package::read_all(packages_path)?);
+	}
+	let collected = Cache::new(cache_path).prune(&packages)?;
+	println!(
+		"removed {} payloads, freeing {} bytes",
+		collected.count, collected.size
+	);
+	Ok(())
+}
+
+pub fn verify_cache(cache_path: PathBuf, options: VerifyCacheOptions) -> Result<()> {
+	let verified = Cache::new(cache_path).verify(&options)?;
+	println!(
+		"verified {} payloads, skipped {}, removed {} corrupt payloads, freeing {} bytes",
+		verified.count, verified.skipped, verified.removed.count, verified.removed.size
+	);
+	Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractPackagesOptions {
+	pub filters: Vec<ExtractFilter>,
+	pub normalize_permissions: bool,
+	pub umask: Option<u32>,
+	pub owner: Option<String>,
+	pub progress: ProgressMode,
+	pub layout: Layout,
+	pub case_variants: stages::CaseVariants,
+	pub scan_paths: Vec<PathBuf>,
+	pub wine_wrap: bool,
+	// Fixups run in order on the output directory after postprocessing.
+	pub hooks: Vec<stages::Hook>,
+	// Extract every package again rather than only the ones whose payloads changed.
+	pub clean: bool,
+	// Only the extract jobs and the memory budget apply to extraction.
+	pub limits: Limits,
+	// Warn about payloads that cannot be extracted rather than failing.
+	pub skip_unsupported: bool,
+	// Fail on payloads of types that are not extracted, or missing from the cache, rather
+	// than warning and skipping them.
+	pub strict_payloads: bool,
+	// Which subtrees of VSIXs to extract, and where. Without rules for a package, its
+	// `Contents` are extracted into the output directory.
+	pub vsix_subtrees: Vec<stages::VsixSubtree>,
+	// Give up on payloads that take longer than this to extract.
+	pub timeout: Option<std::time::Duration>,
+	// Extract the other packages when a payload fails or times out, and fail once the
+	// output is complete.
+	pub keep_going: bool,
+	// Link payloads from the cache into staging, and identical files in the output to each other.
+	pub dedup: stages::DedupMode,
+	// Copy payloads when the cache and output are on different filesystems instead of failing.
+	pub copy_across_filesystems: bool,
+	// Refuse to extract packages that do not match this lockfile.
+	pub locked: Option<PathBuf>,
+	// Keep this many of the trees that extractions replaced, next to the output, rather
+	// than deleting the previous tree.
+	pub keep_backups: usize,
+	// Record the features, components, and files of each MSI at `.windows_sdk-msi.json`
+	// in the output, for servicing a patched MSI later.
+	pub msi_metadata: bool,
+}
+
+pub fn extract_packages(
+	packages_path: PathBuf,
+	cache_path: PathBuf,
+	output_path: PathBuf,
+	options: ExtractPackagesOptions,
+) -> Result<()> {
+	let progress = terminal_progress(options.progress);
+	let packages = read_extracted_packages(&packages_path, &cache_path, &output_path, &options)?;
+	extract(
+		packages,
+		&Cache::new(cache_path),
+		output_path,
+		options,
+		progress,
+	)
+}
+
+// Like `extract_packages`, but runs on the caller's blocking thread pool and reports
+// progress to the sink instead of the terminal, ignoring `options.progress`.
+pub async fn extract_packages_async(
+	packages_path: PathBuf,
+	cache_path: PathBuf,
+	output_path: PathBuf,
+	options: ExtractPackagesOptions,
+	progress: Arc<dyn ProgressSink>,
+) -> Result<()> {
+	let packages = read_extracted_packages(&packages_path, &cache_path, &output_path, &options)?;
+	blocking(move || {
+		extract(
+			packages,
+			&Cache::new(cache_path),
+			output_path,
+			options,
+			progress,
+		)
+	})
+	.await
+}
+
+fn read_extracted_packages(
+	packages_path: &Path,
+	cache_path: &Path,
+	output_path: &Path,
+	options: &ExtractPackagesOptions,
+) -> Result<Vec<Package>> {
+	paths::check_output_path(output_path, cache_path)?;
+	paths::check_not_inside(packages_path, output_path)?;
+	let packages = Package::read_all(packages_path)?;
+	if let Some(locked) = options.locked.as_ref() {
+		Lockfile::read(locked)?.check(&packages)?;
+	}
+	Ok(packages)
+}
+
+fn extract(
+	packages: Vec<Package>,
+	cache: &Cache,
+	output_path: PathBuf,
+	options: ExtractPackagesOptions,
+	progress: Arc<dyn ProgressSink>,
+) -> Result<()> {
+	// Build the new tree next to the output and swap it in once every stage has run, so
+	// the previous tree stays usable until then.
+	let staged_output = StagedOutput::new(&output_path)?;
+	// Only a flat tree without hooks can be matched back to the files extraction produced.
+	let incremental = !options.clean && options.layout == Layout::Flat && options.hooks.is_empty();
+	let extracted = stages::Extract {
+		cache: cache.clone(),
+		output_path: staged_output.path().to_owned(),
+		filters: options.filters,
+		progress,
+		incremental,
+		previous_output_path: Some(staged_output.output_path().to_owned()),
+		copy_kept_files: options.normalize_permissions
+			|| options.umask.is_some()
+			|| options.owner.is_some(),
+		jobs: options.limits.extract_jobs,
+		memory_budget: options.limits.memory_budget,
+		skip_unsupported: options.skip_unsupported,
+		strict_payloads: options.strict_payloads,
+		vsix_subtrees: options.vsix_subtrees,
+		timeout: options.timeout,
+		keep_going: options.keep_going,
+		dedup: options.dedup,
+		copy_across_filesystems: options.copy_across_filesystems,
+		msi_metadata: options.msi_metadata,
+	}
+	.run(packages)?;
+	let output_path = stages::ResourceTools.run(extracted.output_path)?;
+	let output_path = stages::ToolRuntime.run(output_path)?;
+	let output_path = match options.layout {
+		Layout::Flat => output_path,
+		Layout::Splat => stages::Splat.run(output_path)?,
 	};
-	header_paths()
-		.iter()
-		.chain(import_library_paths().iter())
-		.for_each(|path| {
-			let name = path.file_name().unwrap();
-			let lowercase_name = name.to_ascii_lowercase();
-			if lowercase_name != name {
-				std::fs::rename(&path, path.parent().unwrap().join(lowercase_name)).unwrap();
-			}
+	let output_path = stages::Postprocess {
+		normalize_permissions: options.normalize_permissions,
+		umask: options.umask,
+		owner: options.owner,
+		case_variants: options.case_variants,
+		scan_paths: options.scan_paths,
+		wine_wrap: options.wine_wrap,
+		install_path: Some(staged_output.output_path().to_owned()),
+	}
+	.run(output_path)?;
+	let output_path = stages::Hooks {
+		hooks: options.hooks,
+		install_path: Some(staged_output.output_path().to_owned()),
+	}
+	.run(output_path)?;
+	stages::Dedup {
+		mode: options.dedup,
+	}
+	.run(output_path)?;
+	staged_output.commit(options.keep_backups)?;
+	if !extracted.failures.is_empty() {
+		return Err(Error::IncompleteExtraction {
+			count: extracted.failures.len(),
 		});
+	}
+	Ok(())
+}
 
-	// Copy headers to match references with different casing.
-	let mut headers = HashMap::new();
-	for header_path in header_paths() {
-		let file_name = header_path.file_name().unwrap().to_str().unwrap();
-		let lowercase_file_name = file_name.to_lowercase();
-		let entries = headers
-			.entry(lowercase_file_name)
-			.or_insert_with(HashSet::new);
-		entries.insert(header_path);
-	}
-	let include_regex = regex::bytes::Regex::new(r#"#include(\s+)(["<])([^">]+)([">])"#).unwrap();
-	header_paths().iter().for_each(|header_path| {
-		let header_bytes = std::fs::read(header_path).unwrap();
-		for capture in include_regex.captures_iter(&header_bytes) {
-			let name = std::str::from_utf8(&capture[3]).unwrap();
-			if let Some(paths) = headers.get(&name.to_lowercase()) {
-				for path in paths {
-					let mut path = path.parent().unwrap().to_owned();
-					path.push(name);
-					if !path.exists() {
-						std::fs::write(path, &header_bytes).unwrap();
-					}
-				}
-			}
+// Extract the selection into a temporary directory and pack it, for an archive without
+// keeping an output directory.
+pub fn pack_packages(
+	packages_path: PathBuf,
+	cache_path: PathBuf,
+	archive_path: PathBuf,
+	options: ExtractPackagesOptions,
+) -> Result<()> {
+	let tempdir = tempfile::tempdir()?;
+	let output_path = tempdir.path().join("output");
+	extract_packages(packages_path, cache_path, output_path.clone(), options)?;
+	pack(output_path, archive_path)
+}
+
+#[derive(Debug, Default)]
+pub struct InstallOptions {
+	pub choose: ChoosePackagesOptions,
+	pub download: DownloadPackagesOptions,
+	pub extract: ExtractPackagesOptions,
+	// Write a lockfile for the resolved selection.
+	pub lockfile: Option<PathBuf>,
+	// Resolve against the manifest pinned in this lockfile instead of the channel's
+	// current one, and refuse a selection that does not match it.
+	pub locked: Option<PathBuf>,
+	// Record the manifest in this archive, and read it from there when the archive
+	// already holds it.
+	pub manifest_archive: Option<PathBuf>,
+	pub channel: ChannelSource,
+}
+
+// Run the whole pipeline: fetch the channel's manifest for the major version, resolve the
+// packages, download their payloads, and extract them.
+pub fn install(
+	major_version: String,
+	package_ids: Vec<String>,
+	cache_path: PathBuf,
+	output_path: PathBuf,
+	options: InstallOptions,
+) -> Result<()> {
+	let progress = terminal_progress(options.download.progress);
+	tokio::runtime::Runtime::new()?.block_on(install_async(
+		major_version,
+		package_ids,
+		cache_path,
+		output_path,
+		options,
+		progress,
+	))
+}
+
+// Like `install`, but runs on the caller's runtime and reports the progress of every
+// stage to the sink instead of the terminal.
+pub async fn install_async(
+	major_version: String,
+	package_ids: Vec<String>,
+	cache_path: PathBuf,
+	output_path: PathBuf,
+	options: InstallOptions,
+	progress: Arc<dyn ProgressSink>,
+) -> Result<()> {
+	paths::check_output_path(&output_path, &cache_path)?;
+	let locked = options
+		.locked
+		.as_ref()
+		.map(|locked| Lockfile::read(locked))
+		.transpose()?;
+	let (channel_version, manifest_url, manifest_sha256) = match locked.as_ref() {
+		Some(locked) => {
+			let manifest_url = locked.manifest_url.clone().ok_or_else(|| Error::Lockfile {
+				path: options.locked.clone().unwrap(),
+				reason: "the lockfile does not record a manifest url".to_owned(),
+			})?;
+			(
+				locked.channel_version.clone(),
+				manifest_url,
+				locked.manifest_sha256,
+			)
+		}
+		None => {
+			let channel =
+				get_channel_async(major_version, &options.channel, &options.download.http).await?;
+			let (manifest_channel_item, manifest_payload) = channel.manifest()?;
+			(
+				Some(manifest_channel_item.version.to_owned()),
+				manifest_payload.url.clone(),
+				manifest_payload.sha256,
+			)
 		}
-	});
-
-	// // Lowercase all includes in headers.
-	// let include_regex = regex::bytes::Regex::new(r#"#include(\s+)(["<])([^">]+)([">])"#).unwrap();
-	// for header_path in header_paths() {
-	// 	let header_bytes = std::fs::read(&header_path).unwrap();
-	// 	let header_bytes = include_regex.replace_all(&header_bytes, |captures: &regex::bytes::Captures| {
-	// 		let mut replacement = b"#include".to_vec();
-	// 		replacement.extend(&captures[1]);
-	// 		replacement.extend(&captures[2]);
-	// 		let name = std::str::from_utf8(&captures[3]).unwrap().to_lowercase();
-	// 		replacement.extend(name.as_bytes());
-	// 		replacement.extend(&captures[4]);
-	// 		replacement
-	// 	});
-	// 	std::fs::write(&header_path, &header_bytes).unwrap();
-	// }
+	};
+	let archive = options.manifest_archive.clone().map(ManifestArchive::new);
+	if let Some(archive) = archive.as_ref() {
+		archive.record(&manifest_url, &manifest_sha256, channel_version.as_deref())?;
+	}
+	let manifest_bytes = fetch_manifest(
+		manifest_url.clone(),
+		&manifest_sha256,
+		archive.as_ref(),
+		&options.download.http,
+	)
+	.await?;
+	let manifest: Manifest = serde_json::from_slice(&manifest_bytes)?;
+	let resolution = stages::Resolve {
+		package_ids,
+		options: options.choose,
+	}
+	.run(&manifest)?;
+	for warning in resolution.warnings.iter() {
+		tracing::warn!("{}", warning);
+	}
+	if let Some(locked) = locked.as_ref() {
+		locked.check(&resolution.packages)?;
+	}
+	if let Some(lockfile) = options.lockfile.as_ref() {
+		Lockfile::new(
+			channel_version,
+			Some(manifest_url),
+			manifest_sha256,
+			&resolution.packages,
+		)
+		.write(lockfile)?;
+	}
+	let cache = Cache::new(cache_path);
+	download(
+		resolution.packages.clone(),
+		&cache,
+		Some(&output_path),
+		&options.download,
+		progress.clone(),
+	)
+	.await?;
+	if options.download.dry_run {
+		return Ok(());
+	}
+	blocking(move || {
+		extract(
+			resolution.packages,
+			&cache,
+			output_path,
+			options.extract,
+			progress,
+		)
+	})
+	.await
 }
@@ -0,0 +1,163 @@
+use std::{
+	sync::{Condvar, Mutex},
+	time::{Duration, Instant},
+};
+
+// How much work the pipeline does at once. Jobs of zero mean one per CPU for the stages
+// that hash and extract.
+#[derive(Clone, Debug)]
+pub struct Limits {
+	// The number of payloads to download at once.
+	pub download_jobs: usize,
+	// The number of packages to extract at once.
+	pub extract_jobs: usize,
+	// The number of cached payloads to verify at once.
+	pub hash_jobs: usize,
+	// The number of downloads from each host at once, or zero for no limit beyond the
+	// download jobs.
+	pub per_host_connections: usize,
+	// The total size of the payloads that extraction reads at once. A payload larger than
+	// the budget waits until nothing else is read.
+	pub memory_budget: Option<u64>,
+	// The number of bytes downloaded each second across all downloads, or none for no limit.
+	pub download_rate: Option<u64>,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Limits {
+			download_jobs: 8,
+			extract_jobs: 0,
+			hash_jobs: 0,
+			per_host_connections: 0,
+			memory_budget: None,
+			download_rate: None,
+		}
+	}
+}
+
+// Shares the memory budget between the threads of a stage, each reserving the size of
+// what it is about to read.
+pub struct MemoryBudget {
+	limit: Option<u64>,
+	used: Mutex<u64>,
+	released: Condvar,
+}
+
+pub struct Reservation<'a> {
+	budget: &'a MemoryBudget,
+	size: u64,
+}
+
+impl MemoryBudget {
+	pub fn new(limit: Option<u64>) -> MemoryBudget {
+		MemoryBudget {
+			limit,
+			used: Mutex::new(0),
+			released: Condvar::new(),
+		}
+	}
+
+	// Wait until the size fits in the budget, and reserve it until the reservation is dropped.
+	pub fn reserve(&self, size: u64) -> Reservation<'_> {
+		let limit = match self.limit {
+			Some(limit) => limit,
+			None => {
+				return Reservation {
+					budget: self,
+					size: 0,
+				}
+			}
+		};
+		let size = size.min(limit);
+		let mut used = self.used.lock().unwrap();
+		while *used + size > limit {
+			used = self.released.wait(used).unwrap();
+		}
+		*used += size;
+		Reservation { budget: self, size }
+	}
+}
+
+impl Drop for Reservation<'_> {
+	fn drop(&mut self) {
+		if self.size > 0 {
+			*self.budget.used.lock().unwrap() -= self.size;
+			self.budget.released.notify_all();
+		}
+	}
+}
+
+// A token bucket shared by the downloads, refilled at the rate and holding at most a
+// second of tokens, so bursts after an idle period stay short. Each download takes tokens
+// for the bytes it has just received and sleeps off any debt, which slows the connection
+// down through TCP flow control.
+pub struct RateLimiter {
+	rate: Option<u64>,
+	bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+	tokens: f64,
+	refilled: Instant,
+}
+
+impl RateLimiter {
+	pub fn new(rate: Option<u64>) -> RateLimiter {
+		let rate = rate.filter(|rate| *rate > 0);
+		RateLimiter {
+			rate,
+			bucket: Mutex::new(Bucket {
+				tokens: rate.unwrap_or(0) as f64,
+				refilled: Instant::now(),
+			}),
+		}
+	}
+
+	pub async fn take(&self, size: u64) {
+		let rate = match self.rate {
+			Some(rate) => rate as f64,
+			None => return,
+		};
+		let delay = {
+			let mut bucket = self.bucket.lock().unwrap();
+			let now = Instant::now();
+			let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+			bucket.tokens = (bucket.tokens + elapsed * rate).min(rate) - size as f64;
+			bucket.refilled = now;
+			if bucket.tokens >= 0.0 {
+				return;
+			}
+			Duration::from_secs_f64(-bucket.tokens / rate)
+		};
+		tokio::time::sleep(delay).await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::RateLimiter;
+	use std::time::{Duration, Instant};
+
+	#[tokio::test]
+	async fn test_rate_limiter() {
+		// The bucket starts with a second of tokens, and then sleeps off the debt of
+		// anything taken past them.
+		let rate_limiter = RateLimiter::new(Some(10_000));
+		let start = Instant::now();
+		rate_limiter.take(10_000).await;
+		assert!(start.elapsed() < Duration::from_millis(50));
+		rate_limiter.take(2_000).await;
+		assert!(start.elapsed() >= Duration::from_millis(150));
+	}
+
+	#[tokio::test]
+	async fn test_rate_limiter_unlimited() {
+		for rate in [None, Some(0)] {
+			let rate_limiter = RateLimiter::new(rate);
+			let start = Instant::now();
+			rate_limiter.take(u64::MAX).await;
+			assert!(start.elapsed() < Duration::from_millis(50));
+		}
+	}
+}
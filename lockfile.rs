@@ -0,0 +1,144 @@
+use crate::{Error, Package, Result};
+use std::{collections::BTreeMap, path::Path};
+use url::Url;
+
+// Pins a selection to the manifest it was resolved from and to the exact versions and
+// payloads of its packages, so the same lockfile always produces the same SDK even after
+// the channel moves on.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Lockfile {
+	#[serde(rename = "channelVersion")]
+	pub channel_version: Option<String>,
+	#[serde(rename = "manifestUrl")]
+	pub manifest_url: Option<Url>,
+	#[serde(rename = "manifestSha256", with = "hex::serde")]
+	pub manifest_sha256: [u8; 32],
+	pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LockedPackage {
+	pub id: String,
+	pub version: String,
+	pub chip: Option<String>,
+	pub language: Option<String>,
+	pub payloads: Vec<LockedPayload>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LockedPayload {
+	#[serde(rename = "fileName")]
+	pub file_name: String,
+	#[serde(with = "hex::serde")]
+	pub sha256: [u8; 32],
+	pub size: u64,
+}
+
+impl From<&Package> for LockedPackage {
+	fn from(package: &Package) -> Self {
+		LockedPackage {
+			id: package.id.to_owned(),
+			version: package.version.to_owned(),
+			chip: package.chip.clone(),
+			language: package.language.clone(),
+			payloads: package
+				.payloads
+				.iter()
+				.map(|payload| LockedPayload {
+					file_name: payload.file_name.to_owned(),
+					sha256: payload.sha256,
+					size: payload.size,
+				})
+				.collect(),
+		}
+	}
+}
+
+impl Lockfile {
+	pub fn new(
+		channel_version: Option<String>,
+		manifest_url: Option<Url>,
+		manifest_sha256: [u8; 32],
+		packages: &[Package],
+	) -> Lockfile {
+		Lockfile {
+			channel_version,
+			manifest_url,
+			manifest_sha256,
+			packages: packages.iter().map(LockedPackage::from).collect(),
+		}
+	}
+
+	pub fn read(path: &Path) -> Result<Lockfile> {
+		let lockfile_bytes = std::fs::read(path).map_err(Error::file(path))?;
+		let lockfile = serde_json::from_slice(&lockfile_bytes)?;
+		Ok(lockfile)
+	}
+
+	pub fn write(&self, path: &Path) -> Result<()> {
+		let lockfile_bytes = serde_json::to_vec_pretty(self)?;
+		std::fs::write(path, &lockfile_bytes).map_err(Error::file(path))?;
+		Ok(())
+	}
+
+	// Describe each package that is missing from the selection, was not locked, or
+	// differs from its locked version or payloads.
+	pub fn differences(&self, packages: &[Package]) -> Vec<String> {
+		let key = |package: &LockedPackage| {
+			(
+				package.id.to_ascii_lowercase(),
+				package.chip.clone(),
+				package.language.clone(),
+			)
+		};
+		let locked = self
+			.packages
+			.iter()
+			.map(|package| (key(package), package))
+			.collect::<BTreeMap<_, _>>();
+		let selected = packages
+			.iter()
+			.map(LockedPackage::from)
+			.map(|package| (key(&package), package))
+			.collect::<BTreeMap<_, _>>();
+		let mut differences = Vec::new();
+		for (key, locked_package) in locked.iter() {
+			match selected.get(key) {
+				None => differences.push(format!(
+					"package {} is locked but not selected",
+					locked_package.id
+				)),
+				Some(package) if package.version != locked_package.version => {
+					differences.push(format!(
+						"package {} is version {} but version {} is locked",
+						package.id, package.version, locked_package.version
+					))
+				}
+				Some(package) if package.payloads != locked_package.payloads => differences.push(
+					format!("package {} has different payloads than locked", package.id),
+				),
+				Some(_) => {}
+			}
+		}
+		for (key, package) in selected.iter() {
+			if !locked.contains_key(key) {
+				differences.push(format!("package {} is selected but not locked", package.id));
+			}
+		}
+		differences
+	}
+
+	// Refuse packages that do not match the lockfile, printing each difference.
+	pub fn check(&self, packages: &[Package]) -> Result<()> {
+		let differences = self.differences(packages);
+		for difference in differences.iter() {
+			tracing::error!("{}", difference);
+		}
+		if !differences.is_empty() {
+			return Err(crate::Error::LockfileMismatch {
+				count: differences.len(),
+			});
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,110 @@
+use tracing::{
+	field::{Field, Visit},
+	level_filters::LevelFilter,
+	Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+	filter::Targets,
+	fmt::{format, FmtContext, FormatEvent, FormatFields},
+	layer::SubscriberExt,
+	registry::LookupSpan,
+	util::SubscriberInitExt,
+};
+
+// Log to stderr at a verbosity from `-v` and `-q`, where 0 logs warnings and
+// informational messages. Logs are lines like `warning: ...`, or with json, objects with
+// an `event` of `log` like the json progress events. Dependencies such as the HTTP client
+// only log their warnings and errors.
+pub fn init_logging(verbosity: i64, json: bool) {
+	let level = match verbosity {
+		i64::MIN..=-2 => LevelFilter::ERROR,
+		-1 => LevelFilter::WARN,
+		0 => LevelFilter::INFO,
+		1 => LevelFilter::DEBUG,
+		_ => LevelFilter::TRACE,
+	};
+	let layer = tracing_subscriber::fmt::layer()
+		.with_writer(std::io::stderr)
+		.event_format(LogFormat { json });
+	let targets = Targets::new()
+		.with_target("windows_sdk", level)
+		.with_default(level.min(LevelFilter::WARN));
+	tracing_subscriber::registry()
+		.with(layer)
+		.with(targets)
+		.init();
+}
+
+struct LogFormat {
+	json: bool,
+}
+
+impl<S, N> FormatEvent<S, N> for LogFormat
+where
+	S: Subscriber + for<'a> LookupSpan<'a>,
+	N: for<'a> FormatFields<'a> + 'static,
+{
+	fn format_event(
+		&self,
+		_ctx: &FmtContext<'_, S, N>,
+		mut writer: format::Writer<'_>,
+		event: &Event<'_>,
+	) -> std::fmt::Result {
+		let mut fields = Fields::default();
+		event.record(&mut fields);
+		let level = *event.metadata().level();
+		let level_name = match level {
+			Level::ERROR => "error",
+			Level::WARN => "warning",
+			Level::INFO => "info",
+			Level::DEBUG => "debug",
+			_ => "trace",
+		};
+		if self.json {
+			let mut line = serde_json::json!({
+				"event": "log",
+				"level": level_name,
+				"message": fields.message,
+			});
+			for (name, value) in fields.fields {
+				line[name] = value.into();
+			}
+			return writeln!(writer, "{}", line);
+		}
+		// Informational messages are the ones users see by default, so they are not labeled.
+		if level != Level::INFO {
+			write!(writer, "{}: ", level_name)?;
+		}
+		write!(writer, "{}", fields.message)?;
+		for (name, value) in fields.fields {
+			write!(writer, " {}={}", name, value)?;
+		}
+		writeln!(writer)
+	}
+}
+
+#[derive(Default)]
+struct Fields {
+	message: String,
+	fields: Vec<(&'static str, String)>,
+}
+
+impl Visit for Fields {
+	fn record_str(&mut self, field: &Field, value: &str) {
+		self.record(field, value.to_owned());
+	}
+
+	fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+		self.record(field, format!("{:?}", value));
+	}
+}
+
+impl Fields {
+	fn record(&mut self, field: &Field, value: String) {
+		if field.name() == "message" {
+			self.message = value;
+		} else {
+			self.fields.push((field.name(), value));
+		}
+	}
+}
@@ -1,5 +1,5 @@
 use clap::Parser;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use url::Url;
 
 #[derive(Parser)]
@@ -8,6 +8,65 @@ use url::Url;
 	setting = clap::AppSettings::DisableHelpSubcommand,
 )]
 struct Args {
+	#[clap(long, global = true, default_value = "auto", value_name = "MODE")]
+	progress: windows_sdk::ProgressMode,
+	#[clap(short, long, global = true, parse(from_occurrences))]
+	verbose: i64,
+	#[clap(short, long, global = true, parse(from_occurrences))]
+	quiet: i64,
+	#[clap(long, global = true, default_value = "8", value_name = "JOBS")]
+	download_jobs: usize,
+	#[clap(long, global = true, default_value = "0", value_name = "JOBS")]
+	extract_jobs: usize,
+	#[clap(long, global = true, default_value = "0", value_name = "JOBS")]
+	hash_jobs: usize,
+	#[clap(long, global = true, default_value = "0", value_name = "CONNECTIONS")]
+	per_host_connections: usize,
+	#[clap(long, global = true, parse(try_from_str = parse_size), value_name = "SIZE")]
+	memory_budget: Option<u64>,
+	#[clap(long, global = true, parse(try_from_str = parse_size), value_name = "SIZE")]
+	limit_rate: Option<u64>,
+	#[clap(long, global = true, env = "WINDOWS_SDK_PROXY", value_name = "URL")]
+	proxy: Option<Url>,
+	#[clap(
+		long = "ca-cert",
+		global = true,
+		env = "WINDOWS_SDK_CA_CERTS",
+		use_delimiter = true,
+		value_name = "PEM"
+	)]
+	ca_certs: Vec<PathBuf>,
+	#[clap(
+		long = "header",
+		global = true,
+		env = "WINDOWS_SDK_HEADERS",
+		use_delimiter = true,
+		value_name = "HOST=NAME:VALUE",
+		hide_env_values = true
+	)]
+	headers: Vec<windows_sdk::HostHeader>,
+	#[clap(
+		long,
+		global = true,
+		env = "WINDOWS_SDK_MIRROR_BASE_URL",
+		value_name = "URL"
+	)]
+	mirror_base_url: Option<Url>,
+	#[clap(
+		long,
+		global = true,
+		env = "WINDOWS_SDK_CACHE_SERVER",
+		value_name = "URL"
+	)]
+	cache_server: Option<Url>,
+	#[clap(
+		long = "alternate-mirror-base-url",
+		global = true,
+		env = "WINDOWS_SDK_ALTERNATE_MIRROR_BASE_URLS",
+		use_delimiter = true,
+		value_name = "URL"
+	)]
+	alternate_mirror_base_urls: Vec<Url>,
 	#[clap(subcommand)]
 	subcommand: Subcommand,
 }
@@ -16,77 +75,1423 @@ struct Args {
 enum Subcommand {
 	#[clap(name = "get-manifest-urls")]
 	GetManifestUrls(GetManifestUrlsArgs),
+	#[clap(name = "show-channel")]
+	ShowChannel(ShowChannelArgs),
 	#[clap(name = "download-manifest")]
 	DownloadManifest(DownloadManifestArgs),
+	#[clap(name = "list-archived-manifests")]
+	ListArchivedManifests(ListArchivedManifestsArgs),
+	#[clap(name = "search")]
+	Search(SearchArgs),
+	#[clap(name = "list-packages")]
+	ListPackages(ListPackagesArgs),
 	#[clap(name = "choose-packages")]
 	ChoosePackages(ChoosePackagesArgs),
+	#[clap(name = "graph")]
+	Graph(GraphArgs),
+	#[clap(name = "verify-selection")]
+	VerifySelection(VerifySelectionArgs),
+	#[clap(name = "validate-manifest")]
+	ValidateManifest(ValidateManifestArgs),
+	#[clap(name = "diff-manifests")]
+	DiffManifests(DiffManifestsArgs),
 	#[clap(name = "download-packages")]
 	DownloadPackages(DownloadPackagesArgs),
 	#[clap(name = "extract-packages")]
 	ExtractPackages(ExtractPackagesArgs),
+	#[clap(name = "install")]
+	Install(Box<InstallArgs>),
+	#[clap(name = "cache", subcommand)]
+	Cache(CacheSubcommand),
+	#[clap(name = "serve")]
+	Serve(ServeArgs),
+	#[clap(name = "generate-vfs-overlay")]
+	GenerateVfsOverlay(GenerateVfsOverlayArgs),
+	#[clap(name = "generate-env")]
+	GenerateEnv(GenerateEnvArgs),
+	#[clap(name = "pack")]
+	Pack(PackArgs),
+	#[clap(name = "unpack")]
+	Unpack(UnpackArgs),
+	#[clap(name = "owner-of")]
+	OwnerOf(OwnerOfArgs),
+	#[clap(name = "doctor")]
+	Doctor(DoctorArgs),
+	#[clap(name = "bench")]
+	Bench(BenchArgs),
+	#[clap(name = "verify-output")]
+	VerifyOutput(VerifyOutputArgs),
+	#[clap(name = "print-layout")]
+	PrintLayout(PrintLayoutArgs),
 }
 
 #[derive(Parser)]
 struct GetManifestUrlsArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
 	#[clap(long)]
-	major_version: String,
+	major_version: Option<String>,
+	#[clap(long)]
+	channel: Option<windows_sdk::ChannelSource>,
+	#[clap(long, value_name = "DIR")]
+	manifest_archive: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct ShowChannelArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	major_version: Option<String>,
+	#[clap(long)]
+	channel: Option<windows_sdk::ChannelSource>,
+	#[clap(long)]
+	json: bool,
 }
 
 #[derive(Parser)]
 struct DownloadManifestArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long, conflicts_with = "major-version", requires = "sha256")]
+	manifest_url: Option<Url>,
+	#[clap(long, requires = "manifest-url")]
+	sha256: Option<String>,
 	#[clap(long)]
-	manifest_url: Url,
+	major_version: Option<String>,
 	#[clap(long)]
-	sha256: String,
+	channel: Option<windows_sdk::ChannelSource>,
+	#[clap(long, value_name = "SHA256", conflicts_with = "manifest-url")]
+	expected_sha256: Option<String>,
+	#[clap(long, value_name = "PATH", conflicts_with = "manifest-url")]
+	channel_output: Option<PathBuf>,
 	#[clap(long)]
 	output: PathBuf,
+	#[clap(long, value_name = "DIR")]
+	manifest_archive: Option<PathBuf>,
+	#[clap(long)]
+	force_refresh: bool,
+}
+
+#[derive(Parser)]
+struct ListArchivedManifestsArgs {
+	#[clap(long, value_name = "DIR")]
+	manifest_archive: PathBuf,
+	#[clap(long)]
+	json: bool,
+}
+
+#[derive(Parser)]
+struct SearchArgs {
+	#[clap(long)]
+	manifest: PathBuf,
+	#[clap(long)]
+	category: Option<String>,
+	query: Option<String>,
+}
+
+#[derive(Parser)]
+struct ListPackagesArgs {
+	#[clap(long)]
+	manifest: PathBuf,
+	#[clap(long)]
+	filter: Option<windows_sdk::PackageFilter>,
+	#[clap(long = "type", value_name = "TYPE")]
+	ty: Option<windows_sdk::PackageType>,
+	#[clap(long)]
+	flatten_groups: bool,
+	#[clap(long)]
+	json: bool,
 }
 
 #[derive(Parser)]
 struct ChoosePackagesArgs {
 	#[clap(long)]
 	manifest: PathBuf,
-	#[clap(long = "package", value_name = "PACKAGE", required = true)]
+	#[clap(long = "package", value_name = "PACKAGE")]
 	packages: Vec<String>,
+	#[clap(long = "package-regex", value_name = "REGEX")]
+	package_regexes: Vec<String>,
 	#[clap(long)]
-	output: PathBuf,
+	all_versions: bool,
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(
+		long = "profile",
+		value_name = "NAME",
+		conflicts_with_all = &["dry-run", "lockfile", "emit", "sbom"]
+	)]
+	profiles: Vec<String>,
+	#[clap(long)]
+	with_asan: bool,
+	#[clap(long)]
+	with_crt_source: bool,
+	#[clap(long)]
+	without_tool_runtime: bool,
+	#[clap(long)]
+	with_resource_tools: bool,
+	#[clap(long = "chip", value_name = "CHIP")]
+	chips: Vec<windows_sdk::DependencyChip>,
+	#[clap(long = "arch", value_name = "ARCH")]
+	arches: Vec<windows_sdk::DependencyChip>,
+	#[clap(long = "language", value_name = "LANGUAGE")]
+	languages: Vec<String>,
+	#[clap(long = "target", value_name = "TRIPLE")]
+	targets: Vec<windows_sdk::Target>,
+	#[clap(long, requires = "targets")]
+	sdk_version: Option<String>,
+	#[clap(long, requires = "targets")]
+	crt_version: Option<String>,
+	#[clap(long, requires = "targets")]
+	with_spectre: bool,
+	#[clap(long, required_unless_present = "dry-run")]
+	output: Option<PathBuf>,
+	#[clap(long)]
+	dry_run: bool,
+	#[clap(long, requires = "dry-run")]
+	cache: Option<PathBuf>,
+	#[clap(long)]
+	explain: bool,
+	#[clap(long, conflicts_with = "dry-run")]
+	lockfile: Option<PathBuf>,
+	#[clap(long, requires = "lockfile")]
+	manifest_url: Option<Url>,
+	#[clap(long, requires = "lockfile")]
+	channel_version: Option<String>,
+	#[clap(long, value_name = "FORMAT", conflicts_with = "dry-run")]
+	emit: Option<windows_sdk::EmitFormat>,
+	#[clap(long, value_name = "PATH", conflicts_with = "dry-run")]
+	sbom: Option<PathBuf>,
+	#[clap(long, value_name = "FORMAT", requires = "sbom")]
+	sbom_format: Option<windows_sdk::SbomFormat>,
+	#[clap(long, requires = "sbom")]
+	without_license_text: bool,
 }
 
 #[derive(Parser)]
-struct DownloadPackagesArgs {
+struct GraphArgs {
+	#[clap(long)]
+	manifest: PathBuf,
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long = "package", value_name = "PACKAGE")]
+	packages: Vec<String>,
+	#[clap(long = "package-regex", value_name = "REGEX")]
+	package_regexes: Vec<String>,
+	#[clap(long)]
+	all_versions: bool,
+	#[clap(long)]
+	with_asan: bool,
+	#[clap(long)]
+	with_crt_source: bool,
+	#[clap(long)]
+	without_tool_runtime: bool,
+	#[clap(long)]
+	with_resource_tools: bool,
+	#[clap(long = "chip", value_name = "CHIP")]
+	chips: Vec<windows_sdk::DependencyChip>,
+	#[clap(long = "arch", value_name = "ARCH")]
+	arches: Vec<windows_sdk::DependencyChip>,
+	#[clap(long = "language", value_name = "LANGUAGE")]
+	languages: Vec<String>,
+	#[clap(long = "target", value_name = "TRIPLE")]
+	targets: Vec<windows_sdk::Target>,
+	#[clap(long, requires = "targets")]
+	sdk_version: Option<String>,
+	#[clap(long, requires = "targets")]
+	crt_version: Option<String>,
+	#[clap(long, requires = "targets")]
+	with_spectre: bool,
+	#[clap(long, default_value = "dot")]
+	format: windows_sdk::GraphFormat,
+	#[clap(long)]
+	flatten_groups: bool,
+}
+
+#[derive(Parser)]
+struct VerifySelectionArgs {
+	#[clap(long)]
+	manifest: PathBuf,
 	#[clap(long)]
 	packages: PathBuf,
+}
+
+#[derive(Parser)]
+struct ValidateManifestArgs {
+	#[clap(long)]
+	manifest: PathBuf,
+	#[clap(long)]
+	deny_unknown_fields: bool,
 	#[clap(long)]
-	cache: PathBuf,
+	json: bool,
+}
+
+#[derive(Parser)]
+struct DiffManifestsArgs {
+	old: PathBuf,
+	new: PathBuf,
+	#[clap(long)]
+	package_filter: Option<windows_sdk::PackageFilter>,
+	#[clap(long)]
+	json: bool,
+}
+
+#[derive(Parser)]
+struct DownloadPackagesArgs {
+	#[clap(long, required = true)]
+	packages: Vec<PathBuf>,
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	cache: Option<PathBuf>,
+	#[clap(long, default_value = "default")]
+	namespace: String,
+	#[clap(long, value_name = "EXTENSION")]
+	filter_payload_type: Vec<String>,
+	#[clap(long, default_value = "5")]
+	retries: u32,
+	#[clap(long, default_value = "1", value_name = "SECONDS")]
+	retry_delay: u64,
+	#[clap(long)]
+	jobs: Option<usize>,
+	#[clap(long, value_name = "LOCKFILE")]
+	locked: Option<PathBuf>,
+	#[clap(long)]
+	offline: bool,
+	#[clap(long)]
+	dry_run: bool,
+	#[clap(long)]
+	skip_space_check: bool,
+	#[clap(long)]
+	verify_signatures: bool,
+	#[clap(
+		long = "trusted-root",
+		value_name = "CERTIFICATE",
+		requires = "verify-signatures"
+	)]
+	trusted_roots: Vec<PathBuf>,
 }
 
 #[derive(Parser)]
 struct ExtractPackagesArgs {
 	#[clap(long)]
 	packages: PathBuf,
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
 	#[clap(long)]
-	cache: PathBuf,
+	cache: Option<PathBuf>,
 	#[clap(long)]
-	output: PathBuf,
+	output: Option<PathBuf>,
+	#[clap(long = "only", value_name = "FILTER")]
+	only: Vec<windows_sdk::ExtractFilter>,
+	#[clap(long)]
+	normalize_permissions: bool,
+	#[clap(long, parse(try_from_str = parse_umask))]
+	umask: Option<u32>,
+	#[clap(long, value_name = "USER[:GROUP]")]
+	chown: Option<String>,
+	#[clap(long)]
+	layout: Option<windows_sdk::Layout>,
+	#[clap(long, default_value = "copy", value_name = "MODE")]
+	case_variants: windows_sdk::stages::CaseVariants,
+	#[clap(long = "case-scan", value_name = "PATH")]
+	case_scan: Vec<PathBuf>,
+	#[clap(long)]
+	wine_wrap: bool,
+	#[clap(long = "hook", value_name = "HOOK")]
+	hooks: Vec<windows_sdk::stages::Hook>,
+	#[clap(long)]
+	clean: bool,
+	#[clap(long)]
+	skip_unsupported: bool,
+	#[clap(long)]
+	strict_payloads: bool,
+	#[clap(long = "vsix-subtree", value_name = "[PACKAGES:]SUBTREE=DESTINATION")]
+	vsix_subtrees: Vec<windows_sdk::stages::VsixSubtree>,
+	#[clap(long, value_name = "SECONDS")]
+	extract_timeout: Option<u64>,
+	#[clap(long)]
+	keep_going: bool,
+	#[clap(long, value_name = "MODE", default_value = "off")]
+	dedup: windows_sdk::stages::DedupMode,
+	#[clap(long)]
+	copy_across_filesystems: bool,
+	#[clap(long, value_name = "N", default_value = "0")]
+	keep_backups: usize,
+	#[clap(long)]
+	msi_metadata: bool,
+	#[clap(long)]
+	jobs: Option<usize>,
+	#[clap(long, value_name = "LOCKFILE")]
+	locked: Option<PathBuf>,
+}
+
+fn parse_umask(value: &str) -> Result<u32, std::num::ParseIntError> {
+	u32::from_str_radix(value, 8)
+}
+
+fn parse_size(value: &str) -> Result<u64, String> {
+	let uppercase_value = value.to_ascii_uppercase();
+	let number = uppercase_value
+		.strip_suffix("IB")
+		.or_else(|| uppercase_value.strip_suffix('B'))
+		.unwrap_or(&uppercase_value);
+	let (number, shift) = match number.chars().last() {
+		Some('K') => (&number[..number.len() - 1], 10),
+		Some('M') => (&number[..number.len() - 1], 20),
+		Some('G') => (&number[..number.len() - 1], 30),
+		_ => (number, 0),
+	};
+	number
+		.parse::<u64>()
+		.ok()
+		.and_then(|number| number.checked_mul(1 << shift))
+		.ok_or_else(|| {
+			format!(
+				"invalid size \"{}\", expected a number of bytes with an optional K, M, or G suffix, such as 10M or 10MiB",
+				value
+			)
+		})
+}
+
+#[derive(Parser)]
+struct InstallArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long, value_name = "NAME")]
+	profile: Option<String>,
+	#[clap(long)]
+	print_config: bool,
+	#[clap(long)]
+	major_version: Option<String>,
+	#[clap(long)]
+	channel: Option<windows_sdk::ChannelSource>,
+	#[clap(long = "package", value_name = "PACKAGE")]
+	packages: Vec<String>,
+	#[clap(long)]
+	all_versions: bool,
+	#[clap(long)]
+	cache: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+	#[clap(long)]
+	with_asan: bool,
+	#[clap(long)]
+	with_crt_source: bool,
+	#[clap(long)]
+	without_tool_runtime: bool,
+	#[clap(long)]
+	with_resource_tools: bool,
+	#[clap(long = "chip", value_name = "CHIP")]
+	chips: Vec<windows_sdk::DependencyChip>,
+	#[clap(long = "arch", value_name = "ARCH")]
+	arches: Vec<windows_sdk::DependencyChip>,
+	#[clap(long = "language", value_name = "LANGUAGE")]
+	languages: Vec<String>,
+	#[clap(long = "target", value_name = "TRIPLE")]
+	targets: Vec<windows_sdk::Target>,
+	#[clap(long, requires = "targets")]
+	sdk_version: Option<String>,
+	#[clap(long, requires = "targets")]
+	crt_version: Option<String>,
+	#[clap(long, requires = "targets")]
+	with_spectre: bool,
+	#[clap(long, default_value = "default")]
+	namespace: String,
+	#[clap(long, value_name = "EXTENSION")]
+	filter_payload_type: Vec<String>,
+	#[clap(long, default_value = "5")]
+	retries: u32,
+	#[clap(long, default_value = "1", value_name = "SECONDS")]
+	retry_delay: u64,
+	#[clap(long)]
+	jobs: Option<usize>,
+	#[clap(long, value_name = "LOCKFILE")]
+	locked: Option<PathBuf>,
+	#[clap(long)]
+	lockfile: Option<PathBuf>,
+	#[clap(long, value_name = "DIR")]
+	manifest_archive: Option<PathBuf>,
+	#[clap(long)]
+	offline: bool,
+	#[clap(long)]
+	dry_run: bool,
+	#[clap(long)]
+	skip_space_check: bool,
+	#[clap(long)]
+	verify_signatures: bool,
+	#[clap(
+		long = "trusted-root",
+		value_name = "CERTIFICATE",
+		requires = "verify-signatures"
+	)]
+	trusted_roots: Vec<PathBuf>,
+	#[clap(long = "only", value_name = "FILTER")]
+	only: Vec<windows_sdk::ExtractFilter>,
+	#[clap(long)]
+	normalize_permissions: bool,
+	#[clap(long, parse(try_from_str = parse_umask))]
+	umask: Option<u32>,
+	#[clap(long, value_name = "USER[:GROUP]")]
+	chown: Option<String>,
+	#[clap(long)]
+	layout: Option<windows_sdk::Layout>,
+	#[clap(long, default_value = "copy", value_name = "MODE")]
+	case_variants: windows_sdk::stages::CaseVariants,
+	#[clap(long = "case-scan", value_name = "PATH")]
+	case_scan: Vec<PathBuf>,
+	#[clap(long)]
+	wine_wrap: bool,
+	#[clap(long = "hook", value_name = "HOOK")]
+	hooks: Vec<windows_sdk::stages::Hook>,
+	#[clap(long)]
+	clean: bool,
+	#[clap(long)]
+	skip_unsupported: bool,
+	#[clap(long)]
+	strict_payloads: bool,
+	#[clap(long = "vsix-subtree", value_name = "[PACKAGES:]SUBTREE=DESTINATION")]
+	vsix_subtrees: Vec<windows_sdk::stages::VsixSubtree>,
+	#[clap(long, value_name = "SECONDS")]
+	extract_timeout: Option<u64>,
+	#[clap(long)]
+	keep_going: bool,
+	#[clap(long, value_name = "MODE", default_value = "off")]
+	dedup: windows_sdk::stages::DedupMode,
+	#[clap(long)]
+	copy_across_filesystems: bool,
+	#[clap(long, value_name = "N", default_value = "0")]
+	keep_backups: usize,
+	#[clap(long)]
+	msi_metadata: bool,
+}
+
+#[derive(Parser)]
+enum CacheSubcommand {
+	#[clap(name = "gc")]
+	Gc(CacheGcArgs),
+	#[clap(name = "prune")]
+	Prune(CachePruneArgs),
+	#[clap(name = "verify")]
+	Verify(CacheVerifyArgs),
+	#[clap(name = "path")]
+	Path,
+}
+
+#[derive(Parser)]
+struct ServeArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	cache: Option<PathBuf>,
+	#[clap(long, default_value = "127.0.0.1:8080", value_name = "ADDRESS")]
+	bind: std::net::SocketAddr,
+}
+
+#[derive(Parser)]
+struct CacheGcArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	cache: Option<PathBuf>,
+	#[clap(long)]
+	namespace: Option<String>,
+}
+
+#[derive(Parser)]
+struct CachePruneArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	cache: Option<PathBuf>,
+	#[clap(long = "packages", value_name = "PACKAGES", required = true)]
+	packages: Vec<PathBuf>,
+}
+
+#[derive(Parser)]
+struct CacheVerifyArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	cache: Option<PathBuf>,
+	#[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+	since: Option<std::time::Duration>,
+	#[clap(long, value_name = "PERCENT")]
+	sample: Option<f64>,
+}
+
+fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+	let invalid = || {
+		format!(
+			"invalid duration \"{}\", expected a number followed by s, m, h, d, or w",
+			value
+		)
+	};
+	let split = value
+		.find(|c: char| !c.is_ascii_digit())
+		.unwrap_or(value.len());
+	let (count, unit) = value.split_at(split);
+	let count = count.parse::<u64>().map_err(|_| invalid())?;
+	let seconds = match unit {
+		"" | "s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 24 * 60 * 60,
+		"w" => 7 * 24 * 60 * 60,
+		_ => return Err(invalid()),
+	};
+	let seconds = count.checked_mul(seconds).ok_or_else(invalid)?;
+	Ok(std::time::Duration::from_secs(seconds))
+}
+
+#[derive(Parser)]
+struct GenerateVfsOverlayArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+	#[clap(long)]
+	overlay: PathBuf,
+}
+
+#[derive(Parser)]
+struct GenerateEnvArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+	#[clap(long = "chip", value_name = "CHIP")]
+	chips: Vec<windows_sdk::DependencyChip>,
+	#[clap(long)]
+	cmake: bool,
+	#[clap(long)]
+	msbuild: bool,
+}
+
+#[derive(Parser)]
+struct PackArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+	#[clap(long, conflicts_with = "output")]
+	packages: Option<PathBuf>,
+	#[clap(long, requires = "packages")]
+	cache: Option<PathBuf>,
+	#[clap(long, requires = "packages")]
+	layout: Option<windows_sdk::Layout>,
+	#[clap(long)]
+	archive: PathBuf,
+}
+
+#[derive(Parser)]
+struct UnpackArgs {
+	#[clap(long)]
+	archive: PathBuf,
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+	#[clap(long, value_name = "N", default_value = "0")]
+	keep_backups: usize,
+}
+
+#[derive(Parser)]
+struct PrintLayoutArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+	#[clap(long)]
+	json: bool,
+}
+
+#[derive(Parser)]
+struct OwnerOfArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+	path: PathBuf,
+	#[clap(long)]
+	json: bool,
+}
+
+#[derive(Parser)]
+struct DoctorArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	major_version: Option<String>,
+	#[clap(long)]
+	channel: Option<windows_sdk::ChannelSource>,
+	#[clap(long)]
+	cache: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct BenchArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	cache: Option<PathBuf>,
+	#[clap(long, conflicts_with = "url")]
+	packages: Option<PathBuf>,
+	#[clap(long)]
+	url: Option<Url>,
+	#[clap(long, default_value = "256M", parse(try_from_str = parse_size), value_name = "SIZE")]
+	sample_size: u64,
+}
+
+#[derive(Parser)]
+struct VerifyOutputArgs {
+	#[clap(long, value_name = "PATH")]
+	config: Option<PathBuf>,
+	#[clap(long)]
+	output: Option<PathBuf>,
+	#[clap(long = "chip", value_name = "CHIP")]
+	chips: Vec<windows_sdk::DependencyChip>,
+	#[clap(long)]
+	require_tools: bool,
 }
 
 fn main() {
 	let args = Args::parse();
-	match args.subcommand {
+	let progress = args.progress;
+	windows_sdk::init_logging(
+		args.verbose - args.quiet,
+		progress == windows_sdk::ProgressMode::Json,
+	);
+	let http = windows_sdk::HttpOptions {
+		proxy: args.proxy,
+		ca_certificates: args.ca_certs,
+		headers: args.headers,
+		mirror_base_url: args.mirror_base_url,
+		cache_server: args.cache_server,
+		alternate_mirror_base_urls: args.alternate_mirror_base_urls,
+	};
+	let limits = windows_sdk::Limits {
+		download_jobs: args.download_jobs,
+		extract_jobs: args.extract_jobs,
+		hash_jobs: args.hash_jobs,
+		per_host_connections: args.per_host_connections,
+		memory_budget: args.memory_budget,
+		download_rate: args.limit_rate,
+	};
+	let result = match args.subcommand {
 		Subcommand::GetManifestUrls(args) => {
-			windows_sdk::get_manifest_urls(args.major_version);
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					major_version: args.major_version,
+					channel: args.channel,
+					..Default::default()
+				},
+			);
+			windows_sdk::get_manifest_urls(
+				required(config.major_version, "major-version", "major-version"),
+				config.channel.unwrap_or_default(),
+				args.manifest_archive,
+				http,
+			)
+		}
+		Subcommand::ShowChannel(args) => {
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					major_version: args.major_version,
+					channel: args.channel,
+					..Default::default()
+				},
+			);
+			windows_sdk::show_channel(
+				required(config.major_version, "major-version", "major-version"),
+				config.channel.unwrap_or_default(),
+				args.json,
+				http,
+			)
 		}
 		Subcommand::DownloadManifest(args) => {
-			windows_sdk::download_manifest(args.manifest_url, args.sha256, args.output);
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					major_version: args.major_version,
+					channel: args.channel,
+					..Default::default()
+				},
+			);
+			let source = match (args.manifest_url, args.sha256) {
+				(Some(url), Some(sha256)) => windows_sdk::ManifestSource::Url { url, sha256 },
+				_ => windows_sdk::ManifestSource::Channel {
+					major_version: config.major_version.unwrap_or_else(|| {
+						usage_error(format!(
+							"--manifest-url or --major-version is required, pass one or set major-version in {}",
+							windows_sdk::CONFIG_FILE_NAME
+						))
+					}),
+					channel: config.channel.unwrap_or_default(),
+				},
+			};
+			let options = windows_sdk::DownloadManifestOptions {
+				expected_sha256: args.expected_sha256,
+				channel_output: args.channel_output,
+				archive_path: args.manifest_archive,
+				force_refresh: args.force_refresh,
+				http,
+			};
+			windows_sdk::download_manifest(source, args.output, options)
+		}
+		Subcommand::ListArchivedManifests(args) => {
+			windows_sdk::list_archived_manifests(args.manifest_archive, args.json)
+		}
+		Subcommand::Search(args) => {
+			windows_sdk::search_packages(args.manifest, args.query, args.category)
+		}
+		Subcommand::ListPackages(args) => {
+			let options = windows_sdk::ListPackagesOptions {
+				filter: args.filter,
+				ty: args.ty,
+				flatten_groups: args.flatten_groups,
+				json: args.json,
+			};
+			windows_sdk::list_packages(args.manifest, options)
 		}
 		Subcommand::ChoosePackages(args) => {
-			windows_sdk::choose_packages(args.manifest, args.packages, args.output);
+			let overrides = windows_sdk::Config {
+				packages: package_ids(args.packages, args.package_regexes),
+				chips: args.chips,
+				languages: args.languages,
+				cache: args.cache,
+				..Default::default()
+			};
+			let config = read_config(args.config.as_deref(), None, overrides.clone());
+			if config.packages.is_empty() && args.targets.is_empty() && args.profiles.is_empty() {
+				usage_error(format!(
+					"--package, --package-regex, --target, or --profile is required, pass one or set packages in {}",
+					windows_sdk::CONFIG_FILE_NAME
+				));
+			}
+			let options = windows_sdk::ChoosePackagesOptions {
+				with_asan: args.with_asan,
+				with_crt_source: args.with_crt_source,
+				without_tool_runtime: args.without_tool_runtime,
+				with_resource_tools: args.with_resource_tools,
+				chips: config.chips,
+				arches: args.arches,
+				languages: config.languages,
+				targets: args.targets,
+				sdk_version: args.sdk_version,
+				crt_version: args.crt_version,
+				with_spectre: args.with_spectre,
+				all_versions: args.all_versions,
+			};
+			match args.output {
+				Some(output) if !args.profiles.is_empty() => {
+					let package_sets = args
+						.profiles
+						.iter()
+						.map(|name| {
+							let profile =
+								read_config(args.config.as_deref(), Some(name), overrides.clone());
+							windows_sdk::PackageSet {
+								name: name.clone(),
+								package_ids: profile.packages,
+								options: windows_sdk::ChoosePackagesOptions {
+									chips: profile.chips,
+									languages: profile.languages,
+									..options.clone()
+								},
+							}
+						})
+						.collect();
+					windows_sdk::choose_package_sets(
+						args.manifest,
+						package_sets,
+						output,
+						args.explain,
+					)
+				}
+				Some(output) if !args.dry_run => windows_sdk::choose_packages(
+					args.manifest,
+					config.packages,
+					options,
+					output,
+					args.explain,
+					windows_sdk::SelectionOutputs {
+						lock: args.lockfile.map(|path| windows_sdk::LockOptions {
+							path,
+							manifest_url: args.manifest_url,
+							channel_version: args.channel_version,
+						}),
+						emit: args.emit,
+						sbom: args.sbom.map(|path| windows_sdk::SbomOptions {
+							path,
+							format: args.sbom_format.unwrap_or_default(),
+							license_text: !args.without_license_text,
+							http,
+						}),
+					},
+				),
+				_ => windows_sdk::estimate_size(
+					args.manifest,
+					config.packages,
+					options,
+					config.cache,
+				),
+			}
+		}
+		Subcommand::Graph(args) => {
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					packages: package_ids(args.packages, args.package_regexes),
+					chips: args.chips,
+					languages: args.languages,
+					..Default::default()
+				},
+			);
+			if config.packages.is_empty() && args.targets.is_empty() {
+				usage_error(format!(
+					"--package, --package-regex, or --target is required, pass one or set packages in {}",
+					windows_sdk::CONFIG_FILE_NAME
+				));
+			}
+			let options = windows_sdk::ChoosePackagesOptions {
+				with_asan: args.with_asan,
+				with_crt_source: args.with_crt_source,
+				without_tool_runtime: args.without_tool_runtime,
+				with_resource_tools: args.with_resource_tools,
+				chips: config.chips,
+				arches: args.arches,
+				languages: config.languages,
+				targets: args.targets,
+				sdk_version: args.sdk_version,
+				crt_version: args.crt_version,
+				with_spectre: args.with_spectre,
+				all_versions: args.all_versions,
+			};
+			windows_sdk::graph_packages(
+				args.manifest,
+				config.packages,
+				options,
+				args.format,
+				args.flatten_groups,
+			)
+		}
+		Subcommand::VerifySelection(args) => {
+			windows_sdk::verify_selection(args.manifest, args.packages)
+		}
+		Subcommand::ValidateManifest(args) => {
+			let options = windows_sdk::ValidateManifestOptions {
+				deny_unknown_fields: args.deny_unknown_fields,
+				json: args.json,
+			};
+			windows_sdk::validate_manifest(args.manifest, options)
+		}
+		Subcommand::DiffManifests(args) => {
+			let options = windows_sdk::DiffManifestsOptions {
+				filter: args.package_filter,
+				json: args.json,
+			};
+			windows_sdk::diff_manifests(args.old, args.new, options)
 		}
 		Subcommand::DownloadPackages(args) => {
-			windows_sdk::download_packages(args.packages, args.cache);
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					cache: args.cache,
+					..Default::default()
+				},
+			);
+			let options = windows_sdk::DownloadPackagesOptions {
+				namespace: args.namespace,
+				payload_types: args.filter_payload_type,
+				progress,
+				retry: windows_sdk::stages::Retry {
+					attempts: args.retries,
+					initial_delay: std::time::Duration::from_secs(args.retry_delay),
+				},
+				limits: windows_sdk::Limits {
+					download_jobs: args.jobs.unwrap_or(limits.download_jobs),
+					..limits
+				},
+				locked: args.locked,
+				http,
+				offline: args.offline,
+				dry_run: args.dry_run,
+				skip_space_check: args.skip_space_check,
+				verify_signatures: args.verify_signatures,
+				trusted_roots: args.trusted_roots,
+			};
+			windows_sdk::download_package_sets(args.packages, cache_path(config.cache), options)
 		}
 		Subcommand::ExtractPackages(args) => {
-			windows_sdk::extract_packages(args.packages, args.cache, args.output);
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					cache: args.cache,
+					output: args.output,
+					layout: args.layout,
+					..Default::default()
+				},
+			);
+			let options = windows_sdk::ExtractPackagesOptions {
+				filters: args.only,
+				normalize_permissions: args.normalize_permissions,
+				umask: args.umask,
+				owner: args.chown,
+				progress,
+				layout: config.layout.unwrap_or_default(),
+				case_variants: args.case_variants,
+				scan_paths: args.case_scan,
+				wine_wrap: args.wine_wrap,
+				hooks: args.hooks,
+				clean: args.clean,
+				limits: windows_sdk::Limits {
+					extract_jobs: args.jobs.unwrap_or(limits.extract_jobs),
+					..limits
+				},
+				skip_unsupported: args.skip_unsupported,
+				strict_payloads: args.strict_payloads,
+				vsix_subtrees: args.vsix_subtrees,
+				timeout: args.extract_timeout.map(std::time::Duration::from_secs),
+				keep_going: args.keep_going,
+				dedup: args.dedup,
+				copy_across_filesystems: args.copy_across_filesystems,
+				keep_backups: args.keep_backups,
+				msi_metadata: args.msi_metadata,
+				locked: args.locked,
+			};
+			windows_sdk::extract_packages(
+				args.packages,
+				cache_path(config.cache),
+				required(config.output, "output", "output"),
+				options,
+			)
+		}
+		Subcommand::Install(args) => {
+			let mut config = read_config(
+				args.config.as_deref(),
+				args.profile.as_deref(),
+				windows_sdk::Config {
+					major_version: args.major_version,
+					channel: args.channel,
+					packages: args.packages,
+					chips: args.chips,
+					languages: args.languages,
+					cache: args.cache,
+					output: args.output,
+					layout: args.layout,
+					profiles: Default::default(),
+				},
+			);
+			config.cache = config.cache.or_else(windows_sdk::Cache::default_path);
+			if args.print_config {
+				match config.to_toml() {
+					Ok(config) => print!("{}", config),
+					Err(error) => {
+						tracing::error!("{}", error);
+						std::process::exit(exit_code(&error));
+					}
+				}
+				return;
+			}
+			let (major_version, output) = match (config.major_version, config.output) {
+				(Some(major_version), Some(output)) => (major_version, output),
+				_ => usage_error(format!(
+					"--major-version and --output are required, pass them or set them in {}",
+					windows_sdk::CONFIG_FILE_NAME
+				)),
+			};
+			if config.packages.is_empty() && args.targets.is_empty() {
+				usage_error(format!(
+					"--package or --target is required, pass it or set packages in {}",
+					windows_sdk::CONFIG_FILE_NAME
+				));
+			}
+			let cache = cache_path(config.cache);
+			let options = windows_sdk::InstallOptions {
+				choose: windows_sdk::ChoosePackagesOptions {
+					with_asan: args.with_asan,
+					with_crt_source: args.with_crt_source,
+					without_tool_runtime: args.without_tool_runtime,
+					with_resource_tools: args.with_resource_tools,
+					chips: config.chips,
+					arches: args.arches,
+					languages: config.languages,
+					targets: args.targets,
+					sdk_version: args.sdk_version,
+					crt_version: args.crt_version,
+					with_spectre: args.with_spectre,
+					all_versions: args.all_versions,
+				},
+				download: windows_sdk::DownloadPackagesOptions {
+					namespace: args.namespace,
+					payload_types: args.filter_payload_type,
+					progress,
+					retry: windows_sdk::stages::Retry {
+						attempts: args.retries,
+						initial_delay: std::time::Duration::from_secs(args.retry_delay),
+					},
+					limits: windows_sdk::Limits {
+						download_jobs: args.jobs.unwrap_or(limits.download_jobs),
+						..limits.clone()
+					},
+					locked: None,
+					http,
+					offline: args.offline,
+					dry_run: args.dry_run,
+					skip_space_check: args.skip_space_check,
+					verify_signatures: args.verify_signatures,
+					trusted_roots: args.trusted_roots,
+				},
+				extract: windows_sdk::ExtractPackagesOptions {
+					filters: args.only,
+					normalize_permissions: args.normalize_permissions,
+					umask: args.umask,
+					owner: args.chown,
+					progress,
+					layout: config.layout.unwrap_or_default(),
+					case_variants: args.case_variants,
+					scan_paths: args.case_scan,
+					wine_wrap: args.wine_wrap,
+					hooks: args.hooks,
+					clean: args.clean,
+					limits: windows_sdk::Limits {
+						extract_jobs: args.jobs.unwrap_or(limits.extract_jobs),
+						..limits
+					},
+					skip_unsupported: args.skip_unsupported,
+					strict_payloads: args.strict_payloads,
+					vsix_subtrees: args.vsix_subtrees,
+					timeout: args.extract_timeout.map(std::time::Duration::from_secs),
+					keep_going: args.keep_going,
+					dedup: args.dedup,
+					copy_across_filesystems: args.copy_across_filesystems,
+					keep_backups: args.keep_backups,
+					msi_metadata: args.msi_metadata,
+					locked: None,
+				},
+				lockfile: args.lockfile,
+				locked: args.locked,
+				manifest_archive: args.manifest_archive,
+				channel: config.channel.unwrap_or_default(),
+			};
+			windows_sdk::install(major_version, config.packages, cache, output, options)
+		}
+		Subcommand::Cache(CacheSubcommand::Path) => {
+			println!("{}", config_cache(None, None).display());
+			Ok(())
+		}
+		Subcommand::Cache(CacheSubcommand::Gc(args)) => windows_sdk::gc_cache(
+			config_cache(args.config.as_deref(), args.cache),
+			args.namespace,
+		),
+		Subcommand::Cache(CacheSubcommand::Prune(args)) => windows_sdk::prune_cache(
+			config_cache(args.config.as_deref(), args.cache),
+			args.packages,
+		),
+		Subcommand::Cache(CacheSubcommand::Verify(args)) => {
+			let options = windows_sdk::VerifyCacheOptions {
+				since: args.since,
+				sample: args.sample,
+			};
+			windows_sdk::verify_cache(config_cache(args.config.as_deref(), args.cache), options)
+		}
+		Subcommand::Serve(args) => {
+			windows_sdk::serve_cache(config_cache(args.config.as_deref(), args.cache), args.bind)
+		}
+		Subcommand::GenerateVfsOverlay(args) => windows_sdk::generate_vfs_overlay(
+			config_output(args.config.as_deref(), args.output),
+			args.overlay,
+		),
+		Subcommand::GenerateEnv(args) => {
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					output: args.output,
+					chips: args.chips,
+					..Default::default()
+				},
+			);
+			let options = windows_sdk::GenerateEnvOptions {
+				chips: config.chips,
+				cmake: args.cmake,
+				msbuild: args.msbuild,
+			};
+			windows_sdk::generate_env(required(config.output, "output", "output"), options)
+		}
+		Subcommand::Pack(args) => {
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					cache: args.cache,
+					output: args.output,
+					layout: args.layout,
+					..Default::default()
+				},
+			);
+			match (args.packages, config.output) {
+				(Some(packages), _) => {
+					let options = windows_sdk::ExtractPackagesOptions {
+						layout: config.layout.unwrap_or_default(),
+						progress,
+						limits,
+						..Default::default()
+					};
+					windows_sdk::pack_packages(
+						packages,
+						cache_path(config.cache),
+						args.archive,
+						options,
+					)
+				}
+				(None, Some(output)) => windows_sdk::pack(output, args.archive),
+				(None, None) => usage_error(format!(
+					"--output or --packages is required, pass one or set output in {}",
+					windows_sdk::CONFIG_FILE_NAME
+				)),
+			}
 		}
+		Subcommand::Unpack(args) => windows_sdk::unpack(
+			args.archive,
+			config_output(args.config.as_deref(), args.output),
+			args.keep_backups,
+		),
+		Subcommand::OwnerOf(args) => windows_sdk::owner_of(
+			config_output(args.config.as_deref(), args.output),
+			args.path,
+			args.json,
+		),
+		Subcommand::PrintLayout(args) => windows_sdk::print_layout(
+			config_output(args.config.as_deref(), args.output),
+			args.json,
+		),
+		Subcommand::Doctor(args) => {
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					major_version: args.major_version,
+					channel: args.channel,
+					cache: args.cache,
+					output: args.output,
+					..Default::default()
+				},
+			);
+			let options = windows_sdk::DoctorOptions {
+				major_version: config.major_version.unwrap_or_else(|| "17".to_owned()),
+				channel: config.channel.unwrap_or_default(),
+				cache_path: config.cache,
+				output_path: config.output,
+				http,
+			};
+			windows_sdk::doctor(options)
+		}
+		Subcommand::Bench(args) => {
+			let options = windows_sdk::BenchOptions {
+				cache_path: config_cache(args.config.as_deref(), args.cache),
+				packages_path: args.packages,
+				url: args.url,
+				sample_size: args.sample_size,
+				http,
+			};
+			windows_sdk::bench(options)
+		}
+		Subcommand::VerifyOutput(args) => {
+			let config = read_config(
+				args.config.as_deref(),
+				None,
+				windows_sdk::Config {
+					output: args.output,
+					chips: args.chips,
+					..Default::default()
+				},
+			);
+			let options = windows_sdk::VerifyOutputOptions {
+				chips: config.chips,
+				require_tools: args.require_tools,
+			};
+			windows_sdk::verify_output(required(config.output, "output", "output"), options)
+		}
+	};
+	if let Err(error) = result {
+		tracing::error!("{}", error);
+		std::process::exit(exit_code(&error));
+	}
+}
+
+// Arguments that are required unless a default or the config file provides them.
+fn usage_error(message: String) -> ! {
+	use clap::IntoApp;
+	Args::into_app()
+		.error(clap::ErrorKind::MissingRequiredArgument, message)
+		.exit()
+}
+
+// The settings of the config file, or of one of its profiles, with the flags in place of
+// the settings they set. Every subcommand that takes one of the settings' flags reads
+// them here, so they all agree on the cache, the output, and what to install.
+fn read_config(
+	path: Option<&Path>,
+	profile: Option<&str>,
+	overrides: windows_sdk::Config,
+) -> windows_sdk::Config {
+	let config = windows_sdk::Config::discover(path).and_then(|config| match profile {
+		Some(profile) => config.profile(profile),
+		None => Ok(config),
+	});
+	match config {
+		Ok(config) => config.merge(overrides),
+		Err(error) => {
+			tracing::error!("{}", error);
+			std::process::exit(exit_code(&error));
+		}
+	}
+}
+
+fn required<T>(value: Option<T>, flag: &str, key: &str) -> T {
+	value.unwrap_or_else(|| {
+		usage_error(format!(
+			"--{} is required, pass it or set {} in {}",
+			flag,
+			key,
+			windows_sdk::CONFIG_FILE_NAME
+		))
+	})
+}
+
+// The cache or the output of a subcommand that only takes that setting's flag.
+fn config_cache(config: Option<&Path>, cache: Option<PathBuf>) -> PathBuf {
+	let config = read_config(
+		config,
+		None,
+		windows_sdk::Config {
+			cache,
+			..Default::default()
+		},
+	);
+	cache_path(config.cache)
+}
+
+fn config_output(config: Option<&Path>, output: Option<PathBuf>) -> PathBuf {
+	let config = read_config(
+		config,
+		None,
+		windows_sdk::Config {
+			output,
+			..Default::default()
+		},
+	);
+	required(config.output, "output", "output")
+}
+
+fn cache_path(cache: Option<PathBuf>) -> PathBuf {
+	cache
+		.or_else(windows_sdk::Cache::default_path)
+		.unwrap_or_else(|| {
+			usage_error(
+				"--cache is required because there is no home directory for the default cache"
+					.to_owned(),
+			)
+		})
+}
+
+// Regexes are passed on as package ids between slashes, the way the resolver tells them
+// from globs and exact ids.
+fn package_ids(packages: Vec<String>, package_regexes: Vec<String>) -> Vec<String> {
+	packages
+		.into_iter()
+		.chain(
+			package_regexes
+				.into_iter()
+				.map(|regex| format!("/{}/", regex)),
+		)
+		.collect()
+}
+
+fn exit_code(error: &windows_sdk::Error) -> i32 {
+	match error {
+		windows_sdk::Error::Io(_)
+		| windows_sdk::Error::File { .. }
+		| windows_sdk::Error::Json(_)
+		| windows_sdk::Error::UnsupportedArchive { .. }
+		| windows_sdk::Error::UnsupportedSchemaVersion { .. }
+		| windows_sdk::Error::InvalidConfig { .. } => 1,
+		windows_sdk::Error::InvalidPath { .. } | windows_sdk::Error::InvalidNamespace { .. } => 2,
+		windows_sdk::Error::Network(_) => 3,
+		windows_sdk::Error::HashMismatch { .. }
+		| windows_sdk::Error::InvalidHash(_)
+		| windows_sdk::Error::ArchiveVerificationFailed { .. }
+		| windows_sdk::Error::InvalidSignatures { .. } => 4,
+		windows_sdk::Error::MissingPayload { .. }
+		| windows_sdk::Error::MissingChannelItem { .. }
+		| windows_sdk::Error::MissingChannelUrl { .. }
+		| windows_sdk::Error::MissingPackage { .. }
+		| windows_sdk::Error::MissingNamespace { .. }
+		| windows_sdk::Error::MissingCachedPayloads { .. }
+		| windows_sdk::Error::MissingOwner { .. }
+		| windows_sdk::Error::MissingVersion { .. }
+		| windows_sdk::Error::MissingProfile { .. } => 5,
+		windows_sdk::Error::Extraction { .. }
+		| windows_sdk::Error::ExtractionTimeout { .. }
+		| windows_sdk::Error::IncompleteExtraction { .. } => 6,
+		windows_sdk::Error::StaleSelection { .. } => 7,
+		windows_sdk::Error::LockfileMismatch { .. } => 8,
+		windows_sdk::Error::DoctorFailed { .. } => 9,
+		windows_sdk::Error::SchemaDrift { .. } => 10,
+		windows_sdk::Error::InsufficientSpace { .. } => 11,
+		windows_sdk::Error::OutputVerificationFailed { .. } => 12,
+		windows_sdk::Error::Lockfile { .. } => 13,
+		windows_sdk::Error::InvalidArgument { .. } => 14,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_duration, parse_size};
+	use std::time::Duration;
+
+	#[test]
+	fn test_parse_size() {
+		assert_eq!(parse_size("512"), Ok(512));
+		assert_eq!(parse_size("512B"), Ok(512));
+		assert_eq!(parse_size("10k"), Ok(10 << 10));
+		assert_eq!(parse_size("10M"), Ok(10 << 20));
+		assert_eq!(parse_size("10MiB"), Ok(10 << 20));
+		assert_eq!(parse_size("2gb"), Ok(2 << 30));
+		assert!(parse_size("").is_err());
+		assert!(parse_size("M").is_err());
+		assert!(parse_size("1.5G").is_err());
+		assert!(parse_size("10T").is_err());
+		assert!(parse_size(&format!("{}G", u64::MAX)).is_err());
+	}
+
+	#[test]
+	fn test_parse_duration() {
+		assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+		assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+		assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(5 * 60)));
+		assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+		assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+		assert_eq!(
+			parse_duration("1w"),
+			Ok(Duration::from_secs(7 * 24 * 60 * 60))
+		);
+		assert!(parse_duration("").is_err());
+		assert!(parse_duration("m").is_err());
+		assert!(parse_duration("5 m").is_err());
+		assert!(parse_duration("5y").is_err());
+		assert!(parse_duration(&format!("{}w", u64::MAX)).is_err());
 	}
 }
@@ -0,0 +1,531 @@
+use crate::{Error, Result};
+use indexmap::IndexMap;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Channel {
+	#[serde(rename = "channelItems")]
+	pub channel_items: Vec<ChannelItem>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ChannelItem {
+	pub id: String,
+	pub version: String,
+	#[serde(rename = "type")]
+	pub ty: ChannelItemType,
+	pub payloads: Option<Vec<Payload>>,
+}
+
+// Where to read the channel from. The release channel publishes stable releases and the
+// preview channel the previews of the next one, both for a major version. A channel URL
+// or a local channel file, such as an archived snapshot, pins the channel exactly, and
+// ignores the major version.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChannelSource {
+	Release,
+	Preview,
+	Url(Url),
+	File(PathBuf),
+}
+
+impl Default for ChannelSource {
+	fn default() -> Self {
+		ChannelSource::Release
+	}
+}
+
+impl std::str::FromStr for ChannelSource {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"release" => Ok(ChannelSource::Release),
+			"preview" => Ok(ChannelSource::Preview),
+			"" => {
+				Err("invalid channel \"\", expected release, preview, a URL, or a path".to_owned())
+			}
+			_ => match Url::parse(value) {
+				Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+					Ok(ChannelSource::Url(url))
+				}
+				_ => Ok(ChannelSource::File(PathBuf::from(value))),
+			},
+		}
+	}
+}
+
+impl std::fmt::Display for ChannelSource {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			ChannelSource::Release => write!(f, "release"),
+			ChannelSource::Preview => write!(f, "preview"),
+			ChannelSource::Url(url) => write!(f, "{}", url),
+			ChannelSource::File(path) => write!(f, "{}", path.display()),
+		}
+	}
+}
+
+impl ChannelSource {
+	// The URL of the channel, which a file does not have.
+	pub fn url(&self, major_version: &str) -> Option<Url> {
+		let name = match self {
+			ChannelSource::Release => "release",
+			ChannelSource::Preview => "pre",
+			ChannelSource::Url(url) => return Some(url.clone()),
+			ChannelSource::File(_) => return None,
+		};
+		let url = format!("https://aka.ms/vs/{}/{}/channel", major_version, name);
+		Some(Url::parse(&url).unwrap())
+	}
+}
+
+impl Channel {
+	// The manifest channel item and its payload.
+	pub fn manifest(&self) -> Result<(&ChannelItem, &Payload)> {
+		let manifest_channel_item = self
+			.channel_items
+			.iter()
+			.find(|channel_item| channel_item.ty == ChannelItemType::Manifest)
+			.ok_or(Error::MissingChannelItem {
+				ty: ChannelItemType::Manifest,
+			})?;
+		let manifest_payload = manifest_channel_item
+			.payloads
+			.as_ref()
+			.and_then(|payloads| payloads.first())
+			.ok_or_else(|| Error::MissingPayload {
+				id: manifest_channel_item.id.to_owned(),
+			})?;
+		Ok((manifest_channel_item, manifest_payload))
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ChannelItemType {
+	Bootstrapper,
+	ChannelProduct,
+	Manifest,
+	#[serde(other)]
+	Other,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+	#[serde(rename = "manifestVersion")]
+	pub manifest_version: String,
+	#[serde(rename = "engineVersion")]
+	pub engine_version: String,
+	pub packages: Vec<Package>,
+}
+
+impl Manifest {
+	pub fn read(path: &Path) -> Result<Manifest> {
+		let manifest_bytes = std::fs::read(path).map_err(Error::file(path))?;
+		let manifest = serde_json::from_slice(&manifest_bytes)?;
+		Ok(manifest)
+	}
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Package {
+	pub id: String,
+	pub version: String,
+	#[serde(rename = "type")]
+	pub ty: PackageType,
+	#[serde(default)]
+	pub dependencies: IndexMap<String, Dependency>,
+	#[serde(default)]
+	pub payloads: Vec<Payload>,
+	#[serde(rename = "localizedResources", default)]
+	pub localized_resources: Vec<LocalizedResource>,
+	pub category: Option<String>,
+	pub chip: Option<String>,
+	#[serde(rename = "machineArch")]
+	pub machine_arch: Option<String>,
+	pub language: Option<String>,
+	#[serde(rename = "detectConditions")]
+	pub detect_conditions: Option<DetectConditions>,
+	#[serde(rename = "installParams")]
+	pub install_params: Option<InstallParams>,
+	#[serde(rename = "returnCodes")]
+	pub return_codes: Option<ReturnCodes>,
+	#[serde(rename = "installSizes")]
+	pub install_sizes: Option<InstallSizes>,
+}
+
+// The version of the packages.json schema this version writes. Version 1 is the bare
+// array of packages written before the file had a version, and newer versions are an
+// object with the `schemaVersion` and the `packages`.
+pub const PACKAGES_SCHEMA_VERSION: u64 = 2;
+
+#[derive(serde::Serialize)]
+struct PackagesFile<'a> {
+	#[serde(rename = "schemaVersion")]
+	schema_version: u64,
+	packages: &'a [Package],
+}
+
+impl Package {
+	// Files written by older versions are migrated to the current schema as they are
+	// read. Fields added to the model since have defaults, so only changes to existing
+	// fields need a migration step.
+	pub fn read_all(path: &Path) -> Result<Vec<Package>> {
+		let packages_bytes = std::fs::read(path).map_err(Error::file(path))?;
+		let value: serde_json::Value = serde_json::from_slice(&packages_bytes)?;
+		let (version, packages) = match value {
+			serde_json::Value::Array(_) => (1, value),
+			mut value => (
+				value["schemaVersion"].as_u64().unwrap_or(1),
+				value["packages"].take(),
+			),
+		};
+		if version > PACKAGES_SCHEMA_VERSION {
+			return Err(Error::UnsupportedSchemaVersion {
+				path: path.to_owned(),
+				version,
+			});
+		}
+		// Version 1 is not wrapped in an object, and names the dependency type `ty`,
+		// which `Dependency` reads as an alias of `type`.
+		let packages = serde_json::from_value(packages)?;
+		Ok(packages)
+	}
+
+	pub fn write_all(packages: &[Package], path: &Path) -> Result<()> {
+		std::fs::write(path, Package::to_json_all(packages)?).map_err(Error::file(path))?;
+		Ok(())
+	}
+
+	pub fn to_json_all(packages: &[Package]) -> Result<String> {
+		let packages_file = PackagesFile {
+			schema_version: PACKAGES_SCHEMA_VERSION,
+			packages,
+		};
+		Ok(serde_json::to_string_pretty(&packages_file)?)
+	}
+
+	// Group, product, and workload packages without payloads only exist to depend on
+	// other packages.
+	pub fn is_group(&self) -> bool {
+		matches!(
+			self.ty,
+			PackageType::Group | PackageType::Product | PackageType::Workload
+		) && self.payloads.is_empty()
+	}
+
+	pub fn localized_resource(&self) -> Option<&LocalizedResource> {
+		self.localized_resources
+			.iter()
+			.find(|resource| resource.language.eq_ignore_ascii_case("en-us"))
+			.or_else(|| self.localized_resources.first())
+	}
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct LocalizedResource {
+	pub language: String,
+	pub title: Option<String>,
+	pub description: Option<String>,
+	// A URL of the license terms, which products and workloads usually have and the
+	// packages they pull in usually do not.
+	pub license: Option<String>,
+}
+
+// How the installer detects that a package is already installed. The fields of each
+// condition depend on its type, so the ones not modeled here are kept in `other`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DetectConditions {
+	pub expression: Option<String>,
+	#[serde(default)]
+	pub conditions: Vec<DetectCondition>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DetectCondition {
+	pub id: Option<String>,
+	#[serde(rename = "registryKey")]
+	pub registry_key: Option<String>,
+	#[serde(rename = "registryValue")]
+	pub registry_value: Option<String>,
+	#[serde(rename = "registryData")]
+	pub registry_data: Option<serde_json::Value>,
+	#[serde(rename = "filePath")]
+	pub file_path: Option<String>,
+	#[serde(flatten)]
+	pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+// The bytes the installer writes to the drive Visual Studio is installed on, to the drive
+// of the shared components such as the Windows SDK, and to the system drive. An extracted
+// package holds all of them in one tree.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct InstallSizes {
+	#[serde(rename = "targetDrive")]
+	pub target_drive: Option<u64>,
+	#[serde(rename = "sharedDrive")]
+	pub shared_drive: Option<u64>,
+	#[serde(rename = "systemDrive")]
+	pub system_drive: Option<u64>,
+}
+
+impl InstallSizes {
+	pub fn total(&self) -> u64 {
+		self.target_drive.unwrap_or(0)
+			+ self.shared_drive.unwrap_or(0)
+			+ self.system_drive.unwrap_or(0)
+	}
+}
+
+// The command line the installer runs for an Exe or Msu package.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct InstallParams {
+	#[serde(rename = "fileName")]
+	pub file_name: Option<String>,
+	pub parameters: Option<String>,
+	#[serde(rename = "repairParameters")]
+	pub repair_parameters: Option<String>,
+	#[serde(rename = "uninstallParameters")]
+	pub uninstall_parameters: Option<String>,
+	#[serde(flatten)]
+	pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+// The exit codes of the installer grouped by outcome, such as `success` or `failure`.
+pub type ReturnCodes = IndexMap<String, Vec<ReturnCode>>;
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ReturnCode {
+	Code(i64),
+	Detailed {
+		#[serde(rename = "returnCode")]
+		return_code: i64,
+		#[serde(flatten)]
+		other: serde_json::Map<String, serde_json::Value>,
+	},
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(from = "DependencyRaw")]
+pub struct Dependency {
+	pub version: String,
+	#[serde(rename = "type")]
+	pub ty: Option<DependencyType>,
+	pub chip: Option<DependencyChip>,
+}
+
+impl From<DependencyRaw> for Dependency {
+	fn from(value: DependencyRaw) -> Self {
+		match value {
+			DependencyRaw::String(version) => Dependency {
+				version,
+				ty: Default::default(),
+				chip: Default::default(),
+			},
+			DependencyRaw::Map { version, ty, chip } => Dependency { version, ty, chip },
+		}
+	}
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum DependencyRaw {
+	String(String),
+	Map {
+		version: String,
+		// Selections written before the manifest's key was used call it `ty`.
+		#[serde(rename = "type", alias = "ty")]
+		ty: Option<DependencyType>,
+		chip: Option<DependencyChip>,
+	},
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum DependencyType {
+	Optional,
+	Recommended,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum DependencyChip {
+	#[serde(rename = "x86", alias = "X86")]
+	X86,
+	#[serde(rename = "x64", alias = "X64")]
+	X64,
+	#[serde(rename = "arm")]
+	Arm,
+	#[serde(rename = "arm64")]
+	Arm64,
+}
+
+impl std::fmt::Display for DependencyChip {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let name = match self {
+			DependencyChip::X86 => "x86",
+			DependencyChip::X64 => "x64",
+			DependencyChip::Arm => "arm",
+			DependencyChip::Arm64 => "arm64",
+		};
+		write!(f, "{}", name)
+	}
+}
+
+impl std::str::FromStr for DependencyChip {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value.to_ascii_lowercase().as_str() {
+			"x86" => Ok(DependencyChip::X86),
+			"x64" => Ok(DependencyChip::X64),
+			"arm" => Ok(DependencyChip::Arm),
+			"arm64" => Ok(DependencyChip::Arm64),
+			_ => Err(format!(
+				"invalid chip \"{}\", expected x64, x86, arm, or arm64",
+				value
+			)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum PackageType {
+	Component,
+	Exe,
+	Group,
+	Msi,
+	Msu,
+	Nupkg,
+	Product,
+	Vsix,
+	WindowsFeature,
+	Workload,
+	Zip,
+}
+
+impl std::str::FromStr for PackageType {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value.to_ascii_lowercase().as_str() {
+			"component" => Ok(PackageType::Component),
+			"exe" => Ok(PackageType::Exe),
+			"group" => Ok(PackageType::Group),
+			"msi" => Ok(PackageType::Msi),
+			"msu" => Ok(PackageType::Msu),
+			"nupkg" => Ok(PackageType::Nupkg),
+			"product" => Ok(PackageType::Product),
+			"vsix" => Ok(PackageType::Vsix),
+			"windowsfeature" => Ok(PackageType::WindowsFeature),
+			"workload" => Ok(PackageType::Workload),
+			"zip" => Ok(PackageType::Zip),
+			_ => Err(format!(
+				"invalid package type \"{}\", expected Component, Exe, Group, Msi, Msu, Nupkg, Product, Vsix, WindowsFeature, Workload, or Zip",
+				value
+			)),
+		}
+	}
+}
+
+// Matches package ids case-insensitively, either with a glob such as
+// `Microsoft.VC.*.CRT.*`, where `*` matches any characters and `?` matches one, or with a
+// regex between slashes such as `/^Win1[01]SDK_/`, which may match anywhere in the id.
+#[derive(Clone, Debug)]
+pub struct PackageFilter {
+	regex: regex::Regex,
+}
+
+impl PackageFilter {
+	pub fn matches(&self, package_id: &str) -> bool {
+		self.regex.is_match(package_id)
+	}
+}
+
+impl std::str::FromStr for PackageFilter {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let pattern = match value
+			.strip_prefix('/')
+			.and_then(|value| value.strip_suffix('/'))
+		{
+			Some(pattern) => format!("(?i){}", pattern),
+			None => {
+				let mut pattern = "(?i)^".to_owned();
+				for c in value.chars() {
+					match c {
+						'*' => pattern.push_str(".*"),
+						'?' => pattern.push('.'),
+						c => pattern.push_str(&regex::escape(&c.to_string())),
+					}
+				}
+				pattern.push('$');
+				pattern
+			}
+		};
+		let regex = regex::Regex::new(&pattern)
+			.map_err(|error| format!("invalid filter \"{}\": {}", value, error))?;
+		Ok(PackageFilter { regex })
+	}
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Payload {
+	#[serde(rename = "fileName")]
+	pub file_name: String,
+	#[serde(with = "hex::serde")]
+	pub sha256: [u8; 32],
+	pub size: u64,
+	pub url: Url,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{DependencyType, Package, PackageFilter, PACKAGES_SCHEMA_VERSION};
+
+	#[test]
+	fn test_read_version_1() {
+		let directory = tempfile::tempdir().unwrap();
+		let path = directory.path().join("packages.json");
+		std::fs::write(
+			&path,
+			r#"[{"id":"A","version":"1","type":"Component","dependencies":{"B":{"version":"1","ty":"Optional","chip":null},"C":"1"}}]"#,
+		)
+		.unwrap();
+		let packages = Package::read_all(&path).unwrap();
+		let dependencies = &packages[0].dependencies;
+		assert!(matches!(
+			dependencies["B"].ty,
+			Some(DependencyType::Optional)
+		));
+		assert!(dependencies["C"].ty.is_none());
+		// The type survives writing the current version and reading it again.
+		Package::write_all(&packages, &path).unwrap();
+		let written = std::fs::read_to_string(&path).unwrap();
+		assert!(written.contains(&format!("\"schemaVersion\": {}", PACKAGES_SCHEMA_VERSION)));
+		let packages = Package::read_all(&path).unwrap();
+		assert!(matches!(
+			packages[0].dependencies["B"].ty,
+			Some(DependencyType::Optional)
+		));
+	}
+
+	#[test]
+	fn test_package_filter_glob() {
+		let filter = "Microsoft.VC.*.CRT.?64.*".parse::<PackageFilter>().unwrap();
+		assert!(filter.matches("Microsoft.VC.14.38.17.8.CRT.x64.Desktop.base"));
+		assert!(filter.matches("microsoft.vc.14.38.17.8.crt.X64.store.base"));
+		assert!(!filter.matches("Microsoft.VC.14.38.17.8.CRT.x86.Desktop.base"));
+		// The glob matches the whole id, and its dots are not wildcards.
+		assert!(!filter.matches("Prefix.Microsoft.VC.14.CRT.x64.base"));
+		assert!(!filter.matches("MicrosoftXVC.14.CRT.x64.base"));
+	}
+
+	#[test]
+	fn test_package_filter_regex() {
+		let filter = "/^Win1[01]SDK_/".parse::<PackageFilter>().unwrap();
+		assert!(filter.matches("Win10SDK_10.0.19041"));
+		assert!(filter.matches("win11sdk_10.0.22621"));
+		assert!(!filter.matches("Win12SDK_10.0.22621"));
+		let filter = "/CRT/".parse::<PackageFilter>().unwrap();
+		assert!(filter.matches("Microsoft.VC.14.38.17.8.CRT.Headers.base"));
+		assert!("/(/".parse::<PackageFilter>().is_err());
+	}
+}
@@ -0,0 +1,123 @@
+use crate::Result;
+use digest::Digest;
+use sha2::Sha256;
+use std::{
+	io::Write,
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+use url::Url;
+
+// A local archive of the manifests Microsoft has served, so builds can be reproduced
+// against manifests the channel no longer points to. Every manifest URL and hash seen is
+// appended to `index.jsonl`, one entry per line and never rewritten, and the contents of
+// every manifest downloaded are kept under `manifests/<sha256>.json`.
+#[derive(Clone, Debug)]
+pub struct ManifestArchive {
+	path: PathBuf,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ArchivedManifest {
+	pub url: Url,
+	#[serde(with = "hex::serde")]
+	pub sha256: Vec<u8>,
+	#[serde(rename = "channelVersion")]
+	pub channel_version: Option<String>,
+	// When the manifest was first seen, in seconds since the Unix epoch.
+	pub seen: u64,
+}
+
+impl ManifestArchive {
+	pub fn new(path: PathBuf) -> ManifestArchive {
+		ManifestArchive { path }
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	fn index_path(&self) -> PathBuf {
+		self.path.join("index.jsonl")
+	}
+
+	fn manifest_path(&self, sha256: &[u8]) -> PathBuf {
+		self.path
+			.join("manifests")
+			.join(format!("{}.json", hex::encode(sha256)))
+	}
+
+	pub fn entries(&self) -> Result<Vec<ArchivedManifest>> {
+		let index = match std::fs::read_to_string(self.index_path()) {
+			Ok(index) => index,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(error) => return Err(error.into()),
+		};
+		let entries = index
+			.lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(serde_json::from_str)
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(entries)
+	}
+
+	// Append the manifest to the index unless it was already seen at the same URL.
+	pub fn record(&self, url: &Url, sha256: &[u8], channel_version: Option<&str>) -> Result<()> {
+		let seen = self
+			.entries()?
+			.iter()
+			.any(|entry| entry.url == *url && entry.sha256 == sha256);
+		if seen {
+			return Ok(());
+		}
+		let entry = ArchivedManifest {
+			url: url.clone(),
+			sha256: sha256.to_owned(),
+			channel_version: channel_version.map(|version| version.to_owned()),
+			seen: SystemTime::now()
+				.duration_since(SystemTime::UNIX_EPOCH)
+				.map(|duration| duration.as_secs())
+				.unwrap_or(0),
+		};
+		std::fs::create_dir_all(&self.path)?;
+		let mut line = serde_json::to_vec(&entry)?;
+		line.push(b'\n');
+		std::fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(self.index_path())?
+			.write_all(&line)?;
+		Ok(())
+	}
+
+	// Read an archived manifest, ignoring a copy whose contents no longer match its hash.
+	pub fn read(&self, sha256: &[u8]) -> Result<Option<Vec<u8>>> {
+		let manifest_bytes = match std::fs::read(self.manifest_path(sha256)) {
+			Ok(manifest_bytes) => manifest_bytes,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			Err(error) => return Err(error.into()),
+		};
+		if Sha256::new_with_prefix(&manifest_bytes)
+			.finalize()
+			.as_slice()
+			!= sha256
+		{
+			return Ok(None);
+		}
+		Ok(Some(manifest_bytes))
+	}
+
+	pub fn store(&self, sha256: &[u8], manifest_bytes: &[u8]) -> Result<()> {
+		let path = self.manifest_path(sha256);
+		if path.exists() {
+			return Ok(());
+		}
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		// Write to a temporary file first so an interrupted write never leaves a
+		// truncated manifest behind.
+		let temporary_path = path.with_extension("json.tmp");
+		std::fs::write(&temporary_path, manifest_bytes)?;
+		std::fs::rename(temporary_path, path)?;
+		Ok(())
+	}
+}
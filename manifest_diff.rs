@@ -0,0 +1,267 @@
+use crate::{Manifest, Package, PackageFilter, Payload, Result};
+use indicatif::HumanBytes;
+use std::{collections::BTreeMap, path::PathBuf};
+
+#[derive(Debug, Default)]
+pub struct DiffManifestsOptions {
+	// Only compare the packages whose ids match.
+	pub filter: Option<PackageFilter>,
+	pub json: bool,
+}
+
+// The packages added to, removed from, and changed between two manifests, and how much of
+// the new manifest's payloads are not in the old one.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ManifestDiff {
+	pub added: Vec<PackageSummary>,
+	pub removed: Vec<PackageSummary>,
+	pub changed: Vec<PackageChange>,
+	#[serde(rename = "downloadSize")]
+	pub download_size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PackageSummary {
+	pub id: String,
+	pub version: String,
+	pub chip: Option<String>,
+	pub language: Option<String>,
+	pub size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PackageChange {
+	pub id: String,
+	pub chip: Option<String>,
+	pub language: Option<String>,
+	#[serde(rename = "oldVersion")]
+	pub old_version: String,
+	#[serde(rename = "newVersion")]
+	pub new_version: String,
+	pub payloads: Vec<PayloadChange>,
+}
+
+// A payload only in the old package has no new hash and size, and one only in the new
+// package has no old ones.
+#[derive(Debug, serde::Serialize)]
+pub struct PayloadChange {
+	#[serde(rename = "fileName")]
+	pub file_name: String,
+	#[serde(rename = "oldSha256")]
+	pub old_sha256: Option<String>,
+	#[serde(rename = "newSha256")]
+	pub new_sha256: Option<String>,
+	#[serde(rename = "oldSize")]
+	pub old_size: Option<u64>,
+	#[serde(rename = "newSize")]
+	pub new_size: Option<u64>,
+}
+
+// Packages are the same package in both manifests if their id, compared
+// case-insensitively, chip, machine architecture, and language match, as when checking a
+// selection against a manifest.
+type PackageKey = (String, Option<String>, Option<String>, Option<String>);
+
+impl ManifestDiff {
+	pub fn new(old: &Manifest, new: &Manifest, filter: Option<&PackageFilter>) -> ManifestDiff {
+		let old_packages = packages_by_key(old, filter);
+		let new_packages = packages_by_key(new, filter);
+		let mut diff = ManifestDiff::default();
+		for (key, old_package) in old_packages.iter() {
+			if !new_packages.contains_key(key) {
+				diff.removed.push(PackageSummary::new(old_package));
+			}
+		}
+		for (key, new_package) in new_packages.iter() {
+			let old_package = match old_packages.get(key) {
+				Some(old_package) => old_package,
+				None => {
+					let summary = PackageSummary::new(new_package);
+					diff.download_size += summary.size;
+					diff.added.push(summary);
+					continue;
+				}
+			};
+			let payloads = payload_changes(old_package, new_package);
+			if old_package.version == new_package.version && payloads.is_empty() {
+				continue;
+			}
+			diff.download_size += payloads
+				.iter()
+				.filter(|payload| payload.new_sha256.is_some())
+				.filter_map(|payload| payload.new_size)
+				.sum::<u64>();
+			diff.changed.push(PackageChange {
+				id: new_package.id.clone(),
+				chip: new_package.chip.clone(),
+				language: new_package.language.clone(),
+				old_version: old_package.version.clone(),
+				new_version: new_package.version.clone(),
+				payloads,
+			});
+		}
+		diff
+	}
+
+	pub fn print(&self) {
+		for package in self.removed.iter() {
+			println!(
+				"- {} {}{}",
+				package.id,
+				package.version,
+				describe(&package.chip, &package.language)
+			);
+		}
+		for package in self.added.iter() {
+			println!(
+				"+ {} {}{}",
+				package.id,
+				package.version,
+				describe(&package.chip, &package.language)
+			);
+		}
+		for package in self.changed.iter() {
+			if package.old_version == package.new_version {
+				println!(
+					"~ {} {}{}",
+					package.id,
+					package.new_version,
+					describe(&package.chip, &package.language)
+				);
+			} else {
+				println!(
+					"~ {} {} -> {}{}",
+					package.id,
+					package.old_version,
+					package.new_version,
+					describe(&package.chip, &package.language)
+				);
+			}
+			for payload in package.payloads.iter() {
+				match (payload.old_size, payload.new_size) {
+					(None, Some(new_size)) => {
+						println!("\t+ {} {}", payload.file_name, HumanBytes(new_size))
+					}
+					(Some(old_size), None) => {
+						println!("\t- {} {}", payload.file_name, HumanBytes(old_size))
+					}
+					(old_size, new_size) => println!(
+						"\t~ {} {} -> {}, {} -> {}",
+						payload.file_name,
+						payload.old_sha256.as_deref().unwrap_or("-"),
+						payload.new_sha256.as_deref().unwrap_or("-"),
+						HumanBytes(old_size.unwrap_or(0)),
+						HumanBytes(new_size.unwrap_or(0))
+					),
+				}
+			}
+		}
+		println!(
+			"{} added, {} removed, {} changed, {} to download",
+			self.added.len(),
+			self.removed.len(),
+			self.changed.len(),
+			HumanBytes(self.download_size)
+		);
+	}
+}
+
+impl PackageSummary {
+	fn new(package: &Package) -> PackageSummary {
+		PackageSummary {
+			id: package.id.clone(),
+			version: package.version.clone(),
+			chip: package.chip.clone(),
+			language: package.language.clone(),
+			size: package.payloads.iter().map(|payload| payload.size).sum(),
+		}
+	}
+}
+
+fn packages_by_key<'a>(
+	manifest: &'a Manifest,
+	filter: Option<&PackageFilter>,
+) -> BTreeMap<PackageKey, &'a Package> {
+	manifest
+		.packages
+		.iter()
+		.filter(|package| filter.map_or(true, |filter| filter.matches(&package.id)))
+		.map(|package| {
+			let key = (
+				package.id.to_ascii_lowercase(),
+				package.chip.clone(),
+				package.machine_arch.clone(),
+				package.language.clone(),
+			);
+			(key, package)
+		})
+		.collect()
+}
+
+// Payloads are matched by file name, compared case-insensitively.
+fn payloads_by_name(package: &Package) -> BTreeMap<String, &Payload> {
+	package
+		.payloads
+		.iter()
+		.map(|payload| (payload.file_name.to_lowercase(), payload))
+		.collect()
+}
+
+fn payload_changes(old_package: &Package, new_package: &Package) -> Vec<PayloadChange> {
+	let old_payloads = payloads_by_name(old_package);
+	let new_payloads = payloads_by_name(new_package);
+	let mut changes = Vec::new();
+	for (file_name, old_payload) in old_payloads.iter() {
+		if !new_payloads.contains_key(file_name) {
+			changes.push(PayloadChange::new(Some(old_payload), None));
+		}
+	}
+	for (file_name, new_payload) in new_payloads.iter() {
+		match old_payloads.get(file_name) {
+			Some(old_payload)
+				if old_payload.sha256 == new_payload.sha256
+					&& old_payload.size == new_payload.size => {}
+			old_payload => {
+				changes.push(PayloadChange::new(old_payload.copied(), Some(new_payload)))
+			}
+		}
+	}
+	changes
+}
+
+impl PayloadChange {
+	fn new(old: Option<&Payload>, new: Option<&Payload>) -> PayloadChange {
+		PayloadChange {
+			file_name: new.or(old).unwrap().file_name.clone(),
+			old_sha256: old.map(|payload| hex::encode(payload.sha256)),
+			new_sha256: new.map(|payload| hex::encode(payload.sha256)),
+			old_size: old.map(|payload| payload.size),
+			new_size: new.map(|payload| payload.size),
+		}
+	}
+}
+
+fn describe(chip: &Option<String>, language: &Option<String>) -> String {
+	let mut description = String::new();
+	if let Some(chip) = chip {
+		description.push_str(&format!(" chip {}", chip));
+	}
+	if let Some(language) = language {
+		description.push_str(&format!(" language {}", language));
+	}
+	description
+}
+
+// Report what changed between two manifests, such as two snapshots in a manifest archive,
+// before downloading the payloads of the new one.
+pub fn diff_manifests(old: PathBuf, new: PathBuf, options: DiffManifestsOptions) -> Result<()> {
+	let old = Manifest::read(&old)?;
+	let new = Manifest::read(&new)?;
+	let diff = ManifestDiff::new(&old, &new, options.filter.as_ref());
+	if options.json {
+		println!("{}", serde_json::to_string_pretty(&diff)?);
+	} else {
+		diff.print();
+	}
+	Ok(())
+}
@@ -0,0 +1,175 @@
+use crate::stages::extract::{msi_directory_path, msi_long_name, msi_string};
+use std::{collections::HashMap, path::Path};
+
+// Recorded at `.windows_sdk-msi.json` when extracting with MSI metadata: the features,
+// components, and files of every MSI payload in the selection, so a patched MSI can later
+// be applied by replacing the components that changed rather than extracting everything
+// again. Paths are the ones extraction produced, like those of the ownership record.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct MsiMetadataRecord {
+	pub msis: Vec<MsiMetadata>,
+}
+
+pub const MSI_METADATA_FILE_NAME: &str = ".windows_sdk-msi.json";
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MsiMetadata {
+	pub package: String,
+	// The sha256 of the payload.
+	pub payload: String,
+	pub file_name: String,
+	pub product_code: Option<String>,
+	pub product_version: Option<String>,
+	pub upgrade_code: Option<String>,
+	pub features: Vec<MsiFeature>,
+	pub components: Vec<MsiComponent>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MsiFeature {
+	pub id: String,
+	pub parent: Option<String>,
+	pub title: Option<String>,
+	pub components: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MsiComponent {
+	pub id: String,
+	// The component GUID, which stays the same across versions of a component that
+	// install to the same place.
+	pub guid: Option<String>,
+	pub directory: String,
+	// The file or registry key whose presence marks the component as installed.
+	pub key_path: Option<String>,
+	pub files: Vec<MsiFile>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MsiFile {
+	pub id: String,
+	pub path: String,
+	pub size: Option<i32>,
+	pub version: Option<String>,
+}
+
+impl MsiMetadataRecord {
+	pub fn read(output_path: &Path) -> crate::Result<Option<MsiMetadataRecord>> {
+		match std::fs::read(output_path.join(MSI_METADATA_FILE_NAME)) {
+			Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+			Err(error) => Err(error.into()),
+		}
+	}
+
+	pub fn write(&self, output_path: &Path) -> crate::Result<()> {
+		std::fs::write(
+			output_path.join(MSI_METADATA_FILE_NAME),
+			serde_json::to_vec_pretty(self)?,
+		)?;
+		Ok(())
+	}
+}
+
+// Read the tables of the MSI that describe what it installs, without reading its cabinets.
+pub(crate) fn read_msi_metadata(
+	msi_path: &Path,
+	package: String,
+	payload: String,
+	file_name: String,
+) -> std::io::Result<MsiMetadata> {
+	let mut msi = msi::open(msi_path)?;
+	let mut properties = HashMap::new();
+	if msi.has_table("Property") {
+		for row in msi.select_rows(msi::Select::table("Property"))? {
+			properties.insert(msi_string(&row, "Property")?, msi_string(&row, "Value")?);
+		}
+	}
+	let directories = msi
+		.select_rows(msi::Select::table("Directory"))?
+		.map(|row| {
+			let id = msi_string(&row, "Directory")?;
+			let parent = row["Directory_Parent"]
+				.as_str()
+				.map(|parent| parent.to_owned());
+			let name = msi_long_name(&msi_string(&row, "DefaultDir")?);
+			Ok((id, (parent, name)))
+		})
+		.collect::<std::io::Result<HashMap<_, _>>>()?;
+	let path = |path: std::path::PathBuf| path.to_string_lossy().replace('\\', "/");
+	let mut components = Vec::new();
+	let mut component_indices = HashMap::new();
+	for row in msi.select_rows(msi::Select::table("Component"))? {
+		let id = msi_string(&row, "Component")?;
+		component_indices.insert(id.clone(), components.len());
+		components.push(MsiComponent {
+			id,
+			guid: row["ComponentId"].as_str().map(|guid| guid.to_owned()),
+			directory: path(msi_directory_path(
+				&directories,
+				&msi_string(&row, "Directory_")?,
+			)),
+			key_path: row["KeyPath"].as_str().map(|key_path| key_path.to_owned()),
+			files: Vec::new(),
+		});
+	}
+	for row in msi.select_rows(msi::Select::table("File"))? {
+		let component = msi_string(&row, "Component_")?;
+		let component = match component_indices.get(&component) {
+			Some(index) => &mut components[*index],
+			None => continue,
+		};
+		let name = msi_long_name(&msi_string(&row, "FileName")?);
+		let file_path = if component.directory.is_empty() {
+			name
+		} else {
+			format!("{}/{}", component.directory, name)
+		};
+		component.files.push(MsiFile {
+			id: msi_string(&row, "File")?,
+			path: file_path,
+			size: row["FileSize"].as_int(),
+			version: row["Version"].as_str().map(|version| version.to_owned()),
+		});
+	}
+	let mut features = Vec::new();
+	let mut feature_indices = HashMap::new();
+	if msi.has_table("Feature") {
+		for row in msi.select_rows(msi::Select::table("Feature"))? {
+			let id = msi_string(&row, "Feature")?;
+			feature_indices.insert(id.clone(), features.len());
+			features.push(MsiFeature {
+				id,
+				parent: row["Feature_Parent"]
+					.as_str()
+					.filter(|parent| !parent.is_empty())
+					.map(|parent| parent.to_owned()),
+				title: row["Title"].as_str().map(|title| title.to_owned()),
+				components: Vec::new(),
+			});
+		}
+	}
+	if msi.has_table("FeatureComponents") {
+		for row in msi.select_rows(msi::Select::table("FeatureComponents"))? {
+			if let Some(index) = feature_indices.get(&msi_string(&row, "Feature_")?) {
+				features[*index]
+					.components
+					.push(msi_string(&row, "Component_")?);
+			}
+		}
+	}
+	Ok(MsiMetadata {
+		package,
+		payload,
+		file_name,
+		product_code: properties.remove("ProductCode"),
+		product_version: properties.remove("ProductVersion"),
+		upgrade_code: properties.remove("UpgradeCode"),
+		features,
+		components,
+	})
+}
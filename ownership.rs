@@ -0,0 +1,140 @@
+use crate::{paths::absolute_path, stages::extract::ExtractFilter, Error, Package, Result};
+use std::path::{Path, PathBuf};
+
+// Records which package and payload each file in the output directory was extracted from,
+// at `.windows_sdk-extraction.json`, along with the options of the extraction. Paths are
+// the ones extraction produced, before postprocessing lowercases headers and import
+// libraries or a layout moves them.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct OwnershipDatabase {
+	pub filters: Vec<ExtractFilter>,
+	#[serde(default)]
+	pub skip_unsupported: bool,
+	#[serde(default)]
+	pub vsix_subtrees: Vec<String>,
+	// Whether the output holds the files as extracted, so a later extraction can keep them.
+	#[serde(default)]
+	pub incremental: bool,
+	// In the order they were extracted, so when several packages extracted a file, the
+	// last one's copy is in the output.
+	pub packages: Vec<OwnedPackage>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct OwnedPackage {
+	pub id: String,
+	pub chip: Option<String>,
+	pub language: Option<String>,
+	pub payloads: Vec<String>,
+	pub files: Vec<OwnedFile>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct OwnedFile {
+	// Relative to the output directory, with forward slashes.
+	pub path: String,
+	// The sha256 of the payload it was extracted from.
+	pub payload: String,
+}
+
+pub const OWNERSHIP_FILE_NAME: &str = ".windows_sdk-extraction.json";
+
+impl OwnedPackage {
+	pub fn new(package: &Package, files: Vec<OwnedFile>) -> OwnedPackage {
+		OwnedPackage {
+			id: package.id.to_ascii_lowercase(),
+			chip: package.chip.clone(),
+			language: package.language.clone(),
+			payloads: package
+				.payloads
+				.iter()
+				.map(|payload| hex::encode(payload.sha256))
+				.collect(),
+			files,
+		}
+	}
+
+	// Whether the package is the same one, with the same payloads.
+	pub fn matches(&self, package: &Package) -> bool {
+		let other = OwnedPackage::new(package, Vec::new());
+		self.id == other.id
+			&& self.chip == other.chip
+			&& self.language == other.language
+			&& self.payloads == other.payloads
+	}
+}
+
+impl OwnershipDatabase {
+	// Records that do not parse, such as ones written by older versions, are treated as missing.
+	pub fn read(output_path: &Path) -> Result<Option<OwnershipDatabase>> {
+		match std::fs::read(output_path.join(OWNERSHIP_FILE_NAME)) {
+			Ok(bytes) => Ok(serde_json::from_slice(&bytes).ok()),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+			Err(error) => Err(error.into()),
+		}
+	}
+
+	pub fn write(&self, output_path: &Path) -> Result<()> {
+		std::fs::write(
+			output_path.join(OWNERSHIP_FILE_NAME),
+			serde_json::to_vec(self)?,
+		)?;
+		Ok(())
+	}
+
+	// The packages that extracted the file, in order, compared case-insensitively so the
+	// lowercased names and case variants postprocessing creates are found too.
+	pub fn owners(&self, relative_path: &str) -> Vec<(&OwnedPackage, &OwnedFile)> {
+		let relative_path = relative_path.replace('\\', "/").to_lowercase();
+		self.packages
+			.iter()
+			.flat_map(|package| package.files.iter().map(move |file| (package, file)))
+			.filter(|(_, file)| file.path.to_lowercase() == relative_path)
+			.collect()
+	}
+}
+
+// Print the packages that extracted a file in the output directory, with the payload each
+// extracted it from. The path is either inside the output directory or relative to it.
+pub fn owner_of(output_path: PathBuf, path: PathBuf, json: bool) -> Result<()> {
+	let database = OwnershipDatabase::read(&output_path)?.ok_or_else(|| Error::InvalidPath {
+		path: output_path.clone(),
+		reason: "it has no record of the files extracted into it".to_owned(),
+	})?;
+	let absolute_output_path = absolute_path(&output_path)?;
+	let relative_path = match absolute_path(&path)?.strip_prefix(&absolute_output_path) {
+		Ok(relative_path) => relative_path.to_owned(),
+		Err(_) => path.clone(),
+	};
+	let owners = database.owners(&relative_path.to_string_lossy());
+	if owners.is_empty() {
+		return Err(Error::MissingOwner { path });
+	}
+	if json {
+		let owners = owners
+			.iter()
+			.map(|(package, file)| {
+				serde_json::json!({
+					"id": package.id,
+					"chip": package.chip,
+					"language": package.language,
+					"path": file.path,
+					"payload": file.payload,
+				})
+			})
+			.collect::<Vec<_>>();
+		println!("{}", serde_json::to_string_pretty(&owners)?);
+		return Ok(());
+	}
+	let last = owners.len() - 1;
+	for (index, (package, file)) in owners.iter().enumerate() {
+		println!(
+			"{} {} {}{}",
+			package.id,
+			file.payload,
+			file.path,
+			if index < last { " (overwritten)" } else { "" }
+		);
+	}
+	Ok(())
+}
@@ -0,0 +1,148 @@
+use crate::{Error, Result};
+use std::path::{Component, Path, PathBuf};
+
+// The output directory is deleted before extracting, so refuse output paths that would
+// take anything else with them.
+pub fn check_output_path(output_path: &Path, cache_path: &Path) -> Result<()> {
+	let output = absolute_path(output_path)?;
+	let cache = absolute_path(cache_path)?;
+	let invalid = |reason: &str| {
+		Err(Error::InvalidPath {
+			path: output_path.to_owned(),
+			reason: reason.to_owned(),
+		})
+	};
+	if output.parent().is_none() {
+		return invalid("the output directory is the filesystem root");
+	}
+	if output == absolute_path(&std::env::current_dir()?)? {
+		return invalid("the output directory is the current directory");
+	}
+	if home_dir()
+		.map(|home| absolute_path(&home))
+		.transpose()?
+		.map(|home| home.starts_with(&output))
+		.unwrap_or(false)
+	{
+		return invalid("the output directory contains the home directory");
+	}
+	if cache.starts_with(&output) {
+		return invalid("the output directory contains the cache");
+	}
+	if output.starts_with(&cache) {
+		return invalid("the output directory is inside the cache");
+	}
+	Ok(())
+}
+
+pub fn check_output_file(input_path: &Path, output_path: &Path) -> Result<()> {
+	if absolute_path(input_path)? == absolute_path(output_path)? {
+		return Err(Error::InvalidPath {
+			path: output_path.to_owned(),
+			reason: format!("the output would overwrite {}", input_path.display()),
+		});
+	}
+	Ok(())
+}
+
+pub fn check_not_inside(path: &Path, directory_path: &Path) -> Result<()> {
+	if absolute_path(path)?.starts_with(absolute_path(directory_path)?) {
+		return Err(Error::InvalidPath {
+			path: path.to_owned(),
+			reason: format!(
+				"it is inside {}, which is deleted before extracting",
+				directory_path.display()
+			),
+		});
+	}
+	Ok(())
+}
+
+// Resolve the path against the current directory and the symlinks of its longest existing
+// ancestor, so paths that do not exist yet can still be compared.
+pub fn absolute_path(path: &Path) -> Result<PathBuf> {
+	let path = std::env::current_dir()?.join(path);
+	let mut existing = path.as_path();
+	let mut rest = Vec::new();
+	loop {
+		match existing.canonicalize() {
+			Ok(canonical) => {
+				let mut absolute = canonical;
+				for component in rest.into_iter().rev() {
+					match component {
+						Component::ParentDir => {
+							absolute.pop();
+						}
+						Component::CurDir => {}
+						component => absolute.push(component),
+					}
+				}
+				return Ok(absolute);
+			}
+			Err(_) => match (existing.parent(), existing.components().next_back()) {
+				(Some(parent), Some(component)) => {
+					rest.push(component);
+					existing = parent;
+				}
+				_ => return Ok(path),
+			},
+		}
+	}
+}
+
+fn home_dir() -> Option<PathBuf> {
+	std::env::var_os("HOME")
+		.or_else(|| std::env::var_os("USERPROFILE"))
+		.map(PathBuf::from)
+}
+
+// The longest ancestor of the path that exists, which is where a directory that does not
+// exist yet would be created.
+pub fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+	let path = std::env::current_dir().ok()?.join(path);
+	path.ancestors()
+		.find(|ancestor| ancestor.is_dir())
+		.map(Path::to_owned)
+}
+
+// The space available to the current user on the filesystem that holds the path, or would
+// hold it once created.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> Option<u64> {
+	use std::os::unix::ffi::OsStrExt;
+	let path = existing_ancestor(path)?;
+	let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+	let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+	if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+		return None;
+	}
+	let stat = unsafe { stat.assume_init() };
+	#[allow(clippy::unnecessary_cast)]
+	Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> Option<u64> {
+	None
+}
+
+// Whether the paths are, or would be created, on the same filesystem. Paths whose
+// filesystem cannot be found are assumed to be on different ones.
+#[cfg(unix)]
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	let device = |path: &Path| {
+		existing_ancestor(path)
+			.and_then(|path| std::fs::metadata(path).ok())
+			.map(|metadata| metadata.dev())
+	};
+	match (device(a), device(b)) {
+		(Some(a), Some(b)) => a == b,
+		_ => false,
+	}
+}
+
+#[cfg(not(unix))]
+pub fn same_filesystem(_a: &Path, _b: &Path) -> bool {
+	false
+}
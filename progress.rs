@@ -0,0 +1,317 @@
+use crate::Payload;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::{
+	collections::HashMap,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+	},
+	time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProgressMode {
+	Auto,
+	Bar,
+	Plain,
+	Json,
+}
+
+impl Default for ProgressMode {
+	fn default() -> Self {
+		ProgressMode::Auto
+	}
+}
+
+impl std::str::FromStr for ProgressMode {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"auto" => Ok(ProgressMode::Auto),
+			"bar" => Ok(ProgressMode::Bar),
+			"plain" => Ok(ProgressMode::Plain),
+			"json" => Ok(ProgressMode::Json),
+			_ => Err(format!(
+				"invalid progress mode \"{}\", expected auto, bar, plain, or json",
+				value
+			)),
+		}
+	}
+}
+
+// Receives the bytes processed by each stage of the pipeline. Embedders implement it to
+// drive their own UI, while the CLI uses `TerminalProgress`. Stages run one after
+// another, but `inc` is called concurrently from the downloads of a single stage.
+pub trait ProgressSink: Send + Sync {
+	fn start(&self, stage: &'static str, total: u64);
+	fn inc(&self, stage: &'static str, delta: u64);
+	fn finish(&self, stage: &'static str);
+	// The progress of each payload the download stage downloads, for sinks that show more
+	// than the totals.
+	fn payload(&self, _progress: &PayloadProgress) {}
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct PayloadProgress {
+	#[serde(rename = "payload")]
+	pub file_name: String,
+	pub sha256: String,
+	pub done: u64,
+	pub total: u64,
+	pub state: DownloadState,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadState {
+	Started,
+	Downloading,
+	// The download failed or did not match its sha256, and starts over from the same or
+	// the next URL.
+	Retrying,
+	Done,
+	Failed,
+}
+
+// Ignores all progress.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+	fn start(&self, _stage: &'static str, _total: u64) {}
+	fn inc(&self, _stage: &'static str, _delta: u64) {}
+	fn finish(&self, _stage: &'static str) {}
+}
+
+// The progress of one stage, reported to a sink.
+#[derive(Clone)]
+pub struct Progress {
+	sink: Arc<dyn ProgressSink>,
+	stage: &'static str,
+}
+
+impl Progress {
+	pub fn new(sink: &Arc<dyn ProgressSink>, stage: &'static str, total: u64) -> Progress {
+		sink.start(stage, total);
+		Progress {
+			sink: sink.clone(),
+			stage,
+		}
+	}
+
+	pub fn inc(&self, delta: u64) {
+		self.sink.inc(self.stage, delta);
+	}
+
+	pub fn finish(&self) {
+		self.sink.finish(self.stage);
+	}
+
+	// Batch the increments of one payload, as downloads make many small increments from
+	// many tasks at once.
+	pub fn batched(&self, payload: &Payload) -> BatchedProgress {
+		self.sink.payload(&PayloadProgress {
+			file_name: payload.file_name.clone(),
+			sha256: hex::encode(payload.sha256),
+			done: 0,
+			total: payload.size,
+			state: DownloadState::Started,
+		});
+		BatchedProgress {
+			progress: self.clone(),
+			file_name: payload.file_name.clone(),
+			sha256: hex::encode(payload.sha256),
+			total: payload.size,
+			done: 0,
+			pending: 0,
+			flushed_at: Instant::now(),
+		}
+	}
+}
+
+const BATCH_BYTES: u64 = 1 << 20;
+const BATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+// Reports the increments to the sink once they add up to a mebibyte or a tenth of a
+// second has passed, and the remainder when it is flushed or dropped.
+pub struct BatchedProgress {
+	progress: Progress,
+	file_name: String,
+	sha256: String,
+	total: u64,
+	done: u64,
+	pending: u64,
+	flushed_at: Instant,
+}
+
+impl BatchedProgress {
+	pub fn inc(&mut self, delta: u64) {
+		self.pending += delta;
+		if self.pending >= BATCH_BYTES || self.flushed_at.elapsed() >= BATCH_INTERVAL {
+			self.flush();
+		}
+	}
+
+	pub fn flush(&mut self) {
+		if self.pending > 0 {
+			self.progress.inc(self.pending);
+			self.done += self.pending;
+			self.pending = 0;
+			self.report(DownloadState::Downloading);
+		}
+		self.flushed_at = Instant::now();
+	}
+
+	pub fn report(&self, state: DownloadState) {
+		self.progress.sink.payload(&PayloadProgress {
+			file_name: self.file_name.clone(),
+			sha256: self.sha256.clone(),
+			done: self.done,
+			total: self.total,
+			state,
+		});
+	}
+}
+
+impl Drop for BatchedProgress {
+	fn drop(&mut self) {
+		self.flush();
+	}
+}
+
+// Reports progress to stderr, either with a progress bar or, on terminals that cannot
+// draw one and in CI logs, with a line every ten percent.
+pub struct TerminalProgress {
+	mode: ProgressMode,
+	reporters: Mutex<HashMap<&'static str, Arc<Reporter>>>,
+}
+
+enum Reporter {
+	Bar(ProgressBar),
+	Lines {
+		stage: &'static str,
+		total: u64,
+		json: bool,
+		state: LinesState,
+	},
+}
+
+#[derive(Default)]
+struct LinesState {
+	done: AtomicU64,
+	reported_tenths: AtomicU64,
+}
+
+impl TerminalProgress {
+	pub fn new(mode: ProgressMode) -> TerminalProgress {
+		let mode = match mode {
+			ProgressMode::Auto if can_draw_bar() => ProgressMode::Bar,
+			ProgressMode::Auto => ProgressMode::Plain,
+			mode => mode,
+		};
+		TerminalProgress {
+			mode,
+			reporters: Default::default(),
+		}
+	}
+
+	fn reporter(&self, stage: &'static str) -> Option<Arc<Reporter>> {
+		self.reporters.lock().unwrap().get(stage).cloned()
+	}
+}
+
+impl ProgressSink for TerminalProgress {
+	fn start(&self, stage: &'static str, total: u64) {
+		let reporter = match self.mode {
+			ProgressMode::Bar => {
+				let progress_bar_style = ProgressStyle::default_bar()
+					.template("[{wide_bar}] {bytes} / {total_bytes}")
+					.progress_chars("=> ");
+				Reporter::Bar(ProgressBar::new(total).with_style(progress_bar_style))
+			}
+			_ => Reporter::Lines {
+				stage,
+				total,
+				json: self.mode == ProgressMode::Json,
+				state: Default::default(),
+			},
+		};
+		self.reporters
+			.lock()
+			.unwrap()
+			.insert(stage, Arc::new(reporter));
+	}
+
+	fn inc(&self, stage: &'static str, delta: u64) {
+		let reporter = match self.reporter(stage) {
+			Some(reporter) => reporter,
+			None => return,
+		};
+		match reporter.as_ref() {
+			Reporter::Bar(progress_bar) => progress_bar.inc(delta),
+			Reporter::Lines { total, state, .. } => {
+				let done = state.done.fetch_add(delta, Ordering::SeqCst) + delta;
+				let tenths = if *total == 0 {
+					10
+				} else {
+					(done.min(*total) * 10 / total).min(10)
+				};
+				// Only the caller that crosses a new multiple of ten percent prints it.
+				if tenths > state.reported_tenths.fetch_max(tenths, Ordering::SeqCst) {
+					reporter.report(done);
+				}
+			}
+		}
+	}
+
+	fn finish(&self, stage: &'static str) {
+		let reporter = match self.reporters.lock().unwrap().remove(stage) {
+			Some(reporter) => reporter,
+			None => return,
+		};
+		match reporter.as_ref() {
+			Reporter::Bar(progress_bar) => progress_bar.finish(),
+			Reporter::Lines { state, .. } => {
+				if state.reported_tenths.swap(10, Ordering::SeqCst) < 10 {
+					reporter.report(state.done.load(Ordering::SeqCst));
+				}
+			}
+		}
+	}
+
+	// Only json output shows each payload, as one line per payload per batch is too much to read.
+	fn payload(&self, progress: &PayloadProgress) {
+		if self.mode == ProgressMode::Json {
+			let mut line = serde_json::to_value(progress).unwrap();
+			line["event"] = "payload".into();
+			eprintln!("{}", line);
+		}
+	}
+}
+
+impl Reporter {
+	fn report(&self, done: u64) {
+		if let Reporter::Lines {
+			stage, total, json, ..
+		} = self
+		{
+			if *json {
+				let line = serde_json::json!({
+					"event": "stage",
+					"stage": stage,
+					"done": done,
+					"total": total,
+				});
+				eprintln!("{}", line);
+			} else {
+				eprintln!("{}: {} / {}", stage, HumanBytes(done), HumanBytes(*total));
+			}
+		}
+	}
+}
+
+fn can_draw_bar() -> bool {
+	let dumb = std::env::var("TERM")
+		.map(|term| term == "dumb")
+		.unwrap_or(false);
+	console::user_attended_stderr() && !dumb
+}
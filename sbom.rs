@@ -0,0 +1,299 @@
+use crate::{Error, HttpOptions, Package, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	path::PathBuf,
+	time::SystemTime,
+};
+use url::Url;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SbomFormat {
+	// SPDX 2.3 in JSON.
+	Spdx,
+	// CycloneDX 1.4 in JSON.
+	CycloneDx,
+}
+
+impl Default for SbomFormat {
+	fn default() -> Self {
+		SbomFormat::Spdx
+	}
+}
+
+impl std::str::FromStr for SbomFormat {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"spdx" => Ok(SbomFormat::Spdx),
+			"cyclonedx" => Ok(SbomFormat::CycloneDx),
+			_ => Err(format!(
+				"invalid sbom format \"{}\", expected spdx or cyclonedx",
+				value
+			)),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct SbomOptions {
+	pub path: PathBuf,
+	pub format: SbomFormat,
+	// Download the license documents and include their text, rather than only their URLs.
+	pub license_text: bool,
+	pub http: HttpOptions,
+}
+
+// A license document, as downloaded from the URL in the manifest.
+struct LicenseText {
+	content: String,
+	content_type: Option<String>,
+}
+
+// Write a bill of materials listing every selected package with its version, the license
+// in its localized resources, and the hash and URL of each of its payloads. Packages
+// without a license of their own are listed without one rather than guessing from the
+// products that include them. The creation time is `SOURCE_DATE_EPOCH` when set, so the
+// bill is reproducible.
+pub fn write_sbom(packages: &[Package], options: &SbomOptions) -> Result<()> {
+	let licenses = packages
+		.iter()
+		.filter_map(license_url)
+		.collect::<BTreeSet<_>>();
+	let mut texts = BTreeMap::new();
+	if options.license_text {
+		let runtime = tokio::runtime::Runtime::new()?;
+		let client = options.http.client()?;
+		for license in licenses.iter() {
+			texts.insert(
+				*license,
+				runtime.block_on(download_license(&options.http, &client, license))?,
+			);
+		}
+	}
+	let created = created_time();
+	let sbom = match options.format {
+		SbomFormat::Spdx => spdx(packages, &licenses, &texts, &created)?,
+		SbomFormat::CycloneDx => cyclonedx(packages, &texts, &created),
+	};
+	std::fs::write(&options.path, serde_json::to_vec_pretty(&sbom)?)
+		.map_err(Error::file(&options.path))?;
+	Ok(())
+}
+
+fn license_url(package: &Package) -> Option<&str> {
+	package
+		.localized_resource()
+		.and_then(|resource| resource.license.as_deref())
+}
+
+async fn download_license(
+	http: &HttpOptions,
+	client: &reqwest::Client,
+	license: &str,
+) -> Result<LicenseText> {
+	let url = Url::parse(license).map_err(|error| {
+		std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			format!("invalid license url {}: {}", license, error),
+		)
+	})?;
+	let response = http.get(client, url).send().await?.error_for_status()?;
+	let content_type = response
+		.headers()
+		.get(reqwest::header::CONTENT_TYPE)
+		.and_then(|content_type| content_type.to_str().ok())
+		.map(|content_type| content_type.to_owned());
+	Ok(LicenseText {
+		content: response.text().await?,
+		content_type,
+	})
+}
+
+fn spdx(
+	packages: &[Package],
+	licenses: &BTreeSet<&str>,
+	texts: &BTreeMap<&str, LicenseText>,
+	created: &str,
+) -> Result<Value> {
+	// SPDX only has identifiers for licenses on its list, so each license document gets a
+	// reference of its own.
+	let license_refs = licenses
+		.iter()
+		.enumerate()
+		.map(|(index, license)| (*license, format!("LicenseRef-{}", index + 1)))
+		.collect::<BTreeMap<_, _>>();
+	let mut spdx_packages = Vec::new();
+	let mut relationships = Vec::new();
+	for (index, package) in packages.iter().enumerate() {
+		let package_ref = format!("SPDXRef-Package-{}", index + 1);
+		let license = license_url(package)
+			.map(|license| license_refs[license].as_str())
+			.unwrap_or("NOASSERTION");
+		spdx_packages.push(json!({
+			"SPDXID": package_ref,
+			"name": package.id,
+			"versionInfo": package.version,
+			"downloadLocation": "NOASSERTION",
+			"filesAnalyzed": false,
+			"licenseConcluded": "NOASSERTION",
+			"licenseDeclared": license,
+			"copyrightText": "NOASSERTION",
+		}));
+		relationships.push(json!({
+			"spdxElementId": "SPDXRef-DOCUMENT",
+			"relationshipType": "DESCRIBES",
+			"relatedSpdxElement": package_ref,
+		}));
+		for (payload_index, payload) in package.payloads.iter().enumerate() {
+			let payload_ref = format!("{}-Payload-{}", package_ref, payload_index + 1);
+			spdx_packages.push(json!({
+				"SPDXID": payload_ref,
+				"name": payload.file_name,
+				"packageFileName": payload.file_name,
+				"downloadLocation": payload.url,
+				"filesAnalyzed": false,
+				"checksums": [{"algorithm": "SHA256", "checksumValue": hex::encode(payload.sha256)}],
+				"licenseConcluded": "NOASSERTION",
+				"licenseDeclared": license,
+				"copyrightText": "NOASSERTION",
+			}));
+			relationships.push(json!({
+				"spdxElementId": package_ref,
+				"relationshipType": "CONTAINS",
+				"relatedSpdxElement": payload_ref,
+			}));
+		}
+	}
+	let extracted_licenses = license_refs
+		.iter()
+		.map(|(license, license_ref)| {
+			let text = match texts.get(license) {
+				Some(text) => text.content.clone(),
+				None => format!("See {}", license),
+			};
+			json!({
+				"licenseId": license_ref,
+				"extractedText": text,
+				"seeAlsos": [license],
+			})
+		})
+		.collect::<Vec<_>>();
+	// The namespace has to be unique to the document, so it is derived from the selection.
+	let namespace = hex::encode(Sha256::digest(serde_json::to_vec(packages)?));
+	Ok(json!({
+		"spdxVersion": "SPDX-2.3",
+		"dataLicense": "CC0-1.0",
+		"SPDXID": "SPDXRef-DOCUMENT",
+		"name": "windows_sdk selection",
+		"documentNamespace": format!("https://spdx.org/spdxdocs/windows_sdk-{}", namespace),
+		"creationInfo": {
+			"created": created,
+			"creators": [format!("Tool: windows_sdk-{}", env!("CARGO_PKG_VERSION"))],
+		},
+		"packages": spdx_packages,
+		"hasExtractedLicensingInfos": extracted_licenses,
+		"relationships": relationships,
+	}))
+}
+
+fn cyclonedx(packages: &[Package], texts: &BTreeMap<&str, LicenseText>, created: &str) -> Value {
+	let components = packages
+		.iter()
+		.enumerate()
+		.map(|(index, package)| {
+			let package_ref = format!("package-{}", index + 1);
+			let licenses = license_url(package)
+				.map(|license| {
+					let mut license_json = json!({"name": license, "url": license});
+					if let Some(text) = texts.get(license) {
+						license_json["text"] = json!({
+							"content": text.content,
+							"contentType": text.content_type.as_deref().unwrap_or("text/plain"),
+						});
+					}
+					vec![json!({ "license": license_json })]
+				})
+				.unwrap_or_default();
+			let payloads = package
+				.payloads
+				.iter()
+				.enumerate()
+				.map(|(payload_index, payload)| {
+					json!({
+						"type": "file",
+						"bom-ref": format!("{}-payload-{}", package_ref, payload_index + 1),
+						"name": payload.file_name,
+						"hashes": [{"alg": "SHA-256", "content": hex::encode(payload.sha256)}],
+						"externalReferences": [{"type": "distribution", "url": payload.url}],
+					})
+				})
+				.collect::<Vec<_>>();
+			let properties = [("chip", &package.chip), ("language", &package.language)]
+				.iter()
+				.filter_map(|(name, value)| {
+					let value = value.as_ref()?;
+					Some(json!({"name": format!("windows_sdk:{}", name), "value": value}))
+				})
+				.collect::<Vec<_>>();
+			json!({
+				"type": "library",
+				"bom-ref": package_ref,
+				"name": package.id,
+				"version": package.version,
+				"licenses": licenses,
+				"properties": properties,
+				"components": payloads,
+			})
+		})
+		.collect::<Vec<_>>();
+	json!({
+		"bomFormat": "CycloneDX",
+		"specVersion": "1.4",
+		"version": 1,
+		"metadata": {
+			"timestamp": created,
+			"tools": [{"name": "windows_sdk", "version": env!("CARGO_PKG_VERSION")}],
+		},
+		"components": components,
+	})
+}
+
+// The creation time as an RFC 3339 timestamp in UTC.
+fn created_time() -> String {
+	let seconds = std::env::var("SOURCE_DATE_EPOCH")
+		.ok()
+		.and_then(|seconds| seconds.parse::<u64>().ok())
+		.unwrap_or_else(|| {
+			SystemTime::now()
+				.duration_since(SystemTime::UNIX_EPOCH)
+				.map(|duration| duration.as_secs())
+				.unwrap_or(0)
+		});
+	let (days, time) = (seconds / 86400, seconds % 86400);
+	// Convert days since the epoch to a civil date, per Howard Hinnant's `civil_from_days`.
+	let z = days as i64 + 719468;
+	let era = z.div_euclid(146097);
+	let day_of_era = z.rem_euclid(146097);
+	let year_of_era =
+		(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_part = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month_part + 2) / 5 + 1;
+	let month = if month_part < 10 {
+		month_part + 3
+	} else {
+		month_part - 9
+	};
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!(
+		"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+		year,
+		month,
+		day,
+		time / 3600,
+		time % 3600 / 60,
+		time % 60
+	)
+}
@@ -0,0 +1,151 @@
+use crate::{Error, Manifest, Package, Result};
+use serde_json::Value;
+use std::{collections::BTreeMap, path::PathBuf};
+
+#[derive(Debug, Default)]
+pub struct ValidateManifestOptions {
+	// Fail on fields the manifest types do not model, rather than only reporting them.
+	pub deny_unknown_fields: bool,
+	pub json: bool,
+}
+
+// The fields that `Manifest` and `Package` do not model, and the packages that do not
+// parse, such as ones with a package type or chip that is not known.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct SchemaReport {
+	#[serde(rename = "unknownFields")]
+	pub unknown_fields: Vec<UnknownField>,
+	#[serde(rename = "invalidPackages")]
+	pub invalid_packages: Vec<InvalidPackage>,
+}
+
+// A field path such as `packages[].payloads[].signer`, with `[]` for the elements of
+// arrays and `{}` for the values of maps, and how many times it appears.
+#[derive(Debug, serde::Serialize)]
+pub struct UnknownField {
+	pub path: String,
+	pub count: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct InvalidPackage {
+	pub id: String,
+	pub error: String,
+}
+
+// The fields whose values are maps keyed by data, such as package ids, rather than structs.
+const MAP_PATHS: &[&str] = &["packages[].dependencies", "packages[].returnCodes"];
+
+impl SchemaReport {
+	// Every part of the manifest is parsed into the manifest types and serialized again,
+	// and the fields that did not survive are the ones the types do not model. Each
+	// package is parsed on its own, so one invalid package does not hide the others.
+	pub fn new(manifest: &Value) -> Result<SchemaReport> {
+		let mut unknown_fields = BTreeMap::new();
+		let mut invalid_packages = Vec::new();
+		let mut root = manifest.clone();
+		let packages = match root.get_mut("packages") {
+			Some(packages) => packages.take(),
+			None => Value::Null,
+		};
+		if let Some(root) = root.as_object_mut() {
+			root.insert("packages".to_owned(), Value::Array(Vec::new()));
+		}
+		let parsed_root: Manifest = serde_json::from_value(root.clone())?;
+		diff(
+			&root,
+			&serde_json::to_value(&parsed_root)?,
+			"",
+			&mut unknown_fields,
+		);
+		for package in packages.as_array().into_iter().flatten() {
+			match serde_json::from_value::<Package>(package.clone()) {
+				Ok(parsed) => diff(
+					package,
+					&serde_json::to_value(&parsed)?,
+					"packages[]",
+					&mut unknown_fields,
+				),
+				Err(error) => invalid_packages.push(InvalidPackage {
+					id: package
+						.get("id")
+						.and_then(Value::as_str)
+						.unwrap_or("<no id>")
+						.to_owned(),
+					error: error.to_string(),
+				}),
+			}
+		}
+		let unknown_fields = unknown_fields
+			.into_iter()
+			.map(|(path, count)| UnknownField { path, count })
+			.collect();
+		Ok(SchemaReport {
+			unknown_fields,
+			invalid_packages,
+		})
+	}
+}
+
+fn diff(
+	original: &Value,
+	parsed: &Value,
+	path: &str,
+	unknown_fields: &mut BTreeMap<String, usize>,
+) {
+	match (original, parsed) {
+		(Value::Object(original), Value::Object(parsed)) => {
+			let is_map = MAP_PATHS.contains(&path);
+			for (key, value) in original.iter() {
+				let field_path = match (path.is_empty(), is_map) {
+					(_, true) => format!("{}{{}}", path),
+					(true, false) => key.to_owned(),
+					(false, false) => format!("{}.{}", path, key),
+				};
+				match parsed.get(key) {
+					Some(parsed_value) => diff(value, parsed_value, &field_path, unknown_fields),
+					None => *unknown_fields.entry(field_path).or_default() += 1,
+				}
+			}
+		}
+		(Value::Array(original), Value::Array(parsed)) => {
+			let element_path = format!("{}[]", path);
+			for (value, parsed_value) in original.iter().zip(parsed.iter()) {
+				diff(value, parsed_value, &element_path, unknown_fields);
+			}
+		}
+		_ => {}
+	}
+}
+
+// Report how the manifest differs from the schema the manifest types model, so changes to
+// the manifest format are noticed before they break resolution.
+pub fn validate_manifest(manifest: PathBuf, options: ValidateManifestOptions) -> Result<()> {
+	let manifest: Value =
+		serde_json::from_slice(&std::fs::read(&manifest).map_err(Error::file(&manifest))?)?;
+	let report = SchemaReport::new(&manifest)?;
+	if options.json {
+		println!("{}", serde_json::to_string_pretty(&report)?);
+	} else {
+		for unknown_field in report.unknown_fields.iter() {
+			println!(
+				"unknown field {} ({} occurrences)",
+				unknown_field.path, unknown_field.count
+			);
+		}
+		for invalid_package in report.invalid_packages.iter() {
+			println!(
+				"invalid package {}: {}",
+				invalid_package.id, invalid_package.error
+			);
+		}
+	}
+	let mut count = report.invalid_packages.len();
+	if options.deny_unknown_fields {
+		count += report.unknown_fields.len();
+	}
+	if count > 0 {
+		return Err(Error::SchemaDrift { count });
+	}
+	Ok(())
+}
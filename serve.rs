@@ -0,0 +1,102 @@
+use crate::{Cache, Result};
+use hyper::{
+	header,
+	service::{make_service_fn, service_fn},
+	Body, Method, Request, Response, StatusCode,
+};
+use std::{convert::Infallible, net::SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+// Serve the cached payloads over HTTP at `/<sha256>`, so machines without access to the
+// CDN can fill their caches from this one with `--cache-server`. Clients verify every
+// payload against their manifest, so the server does not need to be trusted. `Range:
+// bytes=<start>-` requests are answered with the rest of the payload, so interrupted
+// downloads resume.
+pub async fn serve(cache: Cache, address: SocketAddr) -> Result<()> {
+	let cache_path = cache.path().to_owned();
+	let make_service = make_service_fn(move |_| {
+		let cache = cache.clone();
+		async move {
+			Ok::<_, Infallible>(service_fn(move |request| {
+				let cache = cache.clone();
+				async move { Ok::<_, Infallible>(respond(&cache, request).await) }
+			}))
+		}
+	});
+	let server = hyper::Server::try_bind(&address)
+		.map_err(|error| std::io::Error::new(std::io::ErrorKind::AddrInUse, error))?
+		.serve(make_service);
+	tracing::info!(
+		"serving {} at http://{}",
+		cache_path.display(),
+		server.local_addr()
+	);
+	server
+		.await
+		.map_err(|error| std::io::Error::new(std::io::ErrorKind::Other, error))?;
+	Ok(())
+}
+
+async fn respond(cache: &Cache, request: Request<Body>) -> Response<Body> {
+	match payload_response(cache, &request).await {
+		Ok(response) => response,
+		Err(error) => {
+			tracing::error!("{} {}: {}", request.method(), request.uri(), error);
+			status_response(StatusCode::INTERNAL_SERVER_ERROR)
+		}
+	}
+}
+
+async fn payload_response(
+	cache: &Cache,
+	request: &Request<Body>,
+) -> std::io::Result<Response<Body>> {
+	if request.method() != Method::GET && request.method() != Method::HEAD {
+		return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+	}
+	let hash = request.uri().path().trim_start_matches('/');
+	let path = match cache.cached_path(hash) {
+		Some(path) => path,
+		None => return Ok(status_response(StatusCode::NOT_FOUND)),
+	};
+	let mut file = tokio::fs::File::open(&path).await?;
+	let size = file.metadata().await?.len();
+	let start = request
+		.headers()
+		.get(header::RANGE)
+		.and_then(|range| range.to_str().ok())
+		.and_then(|range| range.strip_prefix("bytes="))
+		.and_then(|range| range.strip_suffix('-'))
+		.and_then(|start| start.parse::<u64>().ok());
+	let mut response = Response::builder()
+		.header(header::CONTENT_TYPE, "application/octet-stream")
+		.header(header::ACCEPT_RANGES, "bytes");
+	let length = match start {
+		Some(start) if start >= size => {
+			return Ok(status_response(StatusCode::RANGE_NOT_SATISFIABLE));
+		}
+		Some(start) => {
+			file.seek(std::io::SeekFrom::Start(start)).await?;
+			response = response.status(StatusCode::PARTIAL_CONTENT).header(
+				header::CONTENT_RANGE,
+				format!("bytes {}-{}/{}", start, size - 1, size),
+			);
+			size - start
+		}
+		None => size,
+	};
+	let response = response.header(header::CONTENT_LENGTH, length);
+	let body = if request.method() == Method::HEAD {
+		Body::empty()
+	} else {
+		Body::wrap_stream(tokio_util::io::ReaderStream::new(file.take(length)))
+	};
+	Ok(response.body(body).unwrap())
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+	Response::builder()
+		.status(status)
+		.body(Body::empty())
+		.unwrap()
+}
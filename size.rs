@@ -0,0 +1,109 @@
+use crate::{
+	cache::Cache,
+	paths::{available_space, same_filesystem},
+	stages::prefetch_packages,
+	Error, Package, Result,
+};
+use indicatif::HumanBytes;
+use std::{collections::HashSet, path::Path};
+
+// How much a selection downloads, and how much it takes once extracted.
+#[derive(Debug, Default)]
+pub struct SizeEstimate {
+	pub packages: usize,
+	// The size of every payload, and of the ones that are not in the cache yet.
+	pub payload_size: u64,
+	pub download_size: u64,
+	// The install sizes from the manifest, counting the payload size of the packages that
+	// have none.
+	pub extracted_size: u64,
+	pub packages_without_install_sizes: usize,
+}
+
+impl SizeEstimate {
+	// Without a cache, every payload is counted as a download.
+	pub fn new(packages: &[Package], cache: Option<&Cache>) -> Result<SizeEstimate> {
+		let mut estimate = SizeEstimate {
+			packages: packages.len(),
+			..Default::default()
+		};
+		let mut seen = HashSet::new();
+		for payload in packages.iter().flat_map(|package| package.payloads.iter()) {
+			if seen.insert(payload.sha256) {
+				estimate.payload_size += payload.size;
+			}
+		}
+		estimate.download_size = match cache {
+			Some(cache) => prefetch_packages(packages, cache)?.download_size,
+			None => estimate.payload_size,
+		};
+		for package in packages.iter() {
+			match package.install_sizes.as_ref() {
+				Some(install_sizes) => estimate.extracted_size += install_sizes.total(),
+				None if package.payloads.is_empty() => {}
+				None => {
+					estimate.extracted_size += package
+						.payloads
+						.iter()
+						.map(|payload| payload.size)
+						.sum::<u64>();
+					estimate.packages_without_install_sizes += 1;
+				}
+			}
+		}
+		Ok(estimate)
+	}
+
+	pub fn print(&self) {
+		println!("packages: {}", self.packages);
+		println!(
+			"download: {} of {} payloads",
+			HumanBytes(self.download_size),
+			HumanBytes(self.payload_size)
+		);
+		print!("extracted: about {}", HumanBytes(self.extracted_size));
+		if self.packages_without_install_sizes > 0 {
+			print!(
+				", counting {} packages without install sizes at their payload size",
+				self.packages_without_install_sizes
+			);
+		}
+		println!();
+	}
+
+	// Fail before downloading if the cache cannot hold the downloads, or the output the
+	// extracted files. A cache and output on one filesystem have to hold both.
+	pub fn check_available_space(
+		&self,
+		cache_path: Option<&Path>,
+		output_path: Option<&Path>,
+	) -> Result<()> {
+		let mut requirements = Vec::new();
+		if let Some(cache_path) = cache_path {
+			requirements.push((cache_path, self.download_size));
+		}
+		if let Some(output_path) = output_path {
+			match requirements.first_mut() {
+				Some((cache_path, required)) if same_filesystem(cache_path, output_path) => {
+					*required += self.extracted_size
+				}
+				_ => requirements.push((output_path, self.extracted_size)),
+			}
+		}
+		for (path, required) in requirements {
+			// Filesystems whose free space cannot be found are not checked.
+			let available = match available_space(path) {
+				Some(available) => available,
+				None => continue,
+			};
+			if available < required {
+				return Err(Error::InsufficientSpace {
+					path: path.to_owned(),
+					required,
+					available,
+				});
+			}
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,156 @@
+use crate::{paths::absolute_path, Result};
+use std::{
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+use tempfile::TempDir;
+
+// A directory next to the output that a new tree is built in and then swapped with the
+// output, so an interrupted extraction leaves the previous tree in place for whatever is
+// using it. The directory is removed if the extraction fails, and the ones left by
+// interrupted extractions are removed when the next one starts.
+pub(crate) struct StagedOutput {
+	output_path: PathBuf,
+	staging: TempDir,
+}
+
+impl StagedOutput {
+	pub fn new(output_path: &Path) -> Result<StagedOutput> {
+		let output_path = absolute_path(output_path)?;
+		let parent_path = output_path.parent().unwrap();
+		std::fs::create_dir_all(parent_path)?;
+		let prefix = staging_prefix(&output_path);
+		for entry in std::fs::read_dir(parent_path)? {
+			let entry = entry?;
+			if entry.file_name().to_string_lossy().starts_with(&prefix) {
+				tracing::debug!("removing {}", entry.path().display());
+				std::fs::remove_dir_all(entry.path()).ok();
+			}
+		}
+		let staging = tempfile::Builder::new()
+			.prefix(&prefix)
+			.tempdir_in(parent_path)?;
+		// Temporary directories are only accessible to their owner, and this one becomes
+		// the output.
+		let permissions = match std::fs::metadata(&output_path) {
+			Ok(metadata) => metadata.permissions(),
+			Err(_) => std::fs::metadata(parent_path)?.permissions(),
+		};
+		std::fs::set_permissions(staging.path(), permissions)?;
+		Ok(StagedOutput {
+			output_path,
+			staging,
+		})
+	}
+
+	pub fn path(&self) -> &Path {
+		self.staging.path()
+	}
+
+	// The absolute path the staged tree will be moved to, which paths written into it
+	// should refer to.
+	pub fn output_path(&self) -> &Path {
+		&self.output_path
+	}
+
+	// Put the staged tree at the output path. The tree it replaces is kept as a backup
+	// next to it when `keep_backups` is more than zero, and only the newest
+	// `keep_backups` backups are kept. On Linux the trees are exchanged in one rename, so
+	// the output path always holds a complete tree. Elsewhere it is missing between two
+	// renames.
+	pub fn commit(self, keep_backups: usize) -> Result<()> {
+		let output_path = self.output_path;
+		let staging_path = self.staging.into_path();
+		if std::fs::symlink_metadata(&output_path).is_err() {
+			std::fs::rename(&staging_path, &output_path)?;
+			return Ok(());
+		}
+		let millis = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.map(|duration| duration.as_millis())
+			.unwrap_or(0);
+		let backup_path =
+			output_path.with_file_name(format!("{}{}", backup_prefix(&output_path), millis));
+		match exchange(&staging_path, &output_path) {
+			Ok(()) => std::fs::rename(&staging_path, &backup_path)?,
+			Err(error) => {
+				tracing::debug!(
+					"failed to exchange {} with {}: {}",
+					staging_path.display(),
+					output_path.display(),
+					error
+				);
+				std::fs::rename(&output_path, &backup_path)?;
+				if let Err(error) = std::fs::rename(&staging_path, &output_path) {
+					std::fs::rename(&backup_path, &output_path).ok();
+					std::fs::remove_dir_all(&staging_path).ok();
+					return Err(error.into());
+				}
+			}
+		}
+		remove_old_backups(&output_path, keep_backups)
+	}
+}
+
+fn staging_prefix(output_path: &Path) -> String {
+	format!(
+		".{}.windows_sdk-staging",
+		output_path.file_name().unwrap().to_string_lossy()
+	)
+}
+
+fn backup_prefix(output_path: &Path) -> String {
+	format!(
+		"{}.windows_sdk-backup-",
+		output_path.file_name().unwrap().to_string_lossy()
+	)
+}
+
+// Backups are named for the time they were replaced, in milliseconds since the epoch.
+fn remove_old_backups(output_path: &Path, keep_backups: usize) -> Result<()> {
+	let prefix = backup_prefix(output_path);
+	let mut backups = Vec::new();
+	for entry in std::fs::read_dir(output_path.parent().unwrap())? {
+		let entry = entry?;
+		let time = entry
+			.file_name()
+			.to_string_lossy()
+			.strip_prefix(&prefix)
+			.and_then(|time| time.parse::<u128>().ok());
+		if let Some(time) = time {
+			backups.push((time, entry.path()));
+		}
+	}
+	backups.sort_by(|a, b| b.cmp(a));
+	for (_, path) in backups.into_iter().skip(keep_backups) {
+		tracing::debug!("removing {}", path.display());
+		std::fs::remove_dir_all(&path)?;
+	}
+	Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> std::io::Result<()> {
+	use std::os::unix::ffi::OsStrExt;
+	let a = std::ffi::CString::new(a.as_os_str().as_bytes())?;
+	let b = std::ffi::CString::new(b.as_os_str().as_bytes())?;
+	let result = unsafe {
+		libc::syscall(
+			libc::SYS_renameat2,
+			libc::AT_FDCWD,
+			a.as_ptr(),
+			libc::AT_FDCWD,
+			b.as_ptr(),
+			libc::RENAME_EXCHANGE,
+		)
+	};
+	if result != 0 {
+		return Err(std::io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange(_a: &Path, _b: &Path) -> std::io::Result<()> {
+	Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
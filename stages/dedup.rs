@@ -0,0 +1,193 @@
+use super::{hash_file, Stage};
+use crate::Result;
+use indicatif::HumanBytes;
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+// How payloads are placed in staging and identical extracted files share their storage.
+// Hardlinks need the cache and output on one filesystem, and reflinks also need a
+// filesystem that supports them, such as Btrfs, XFS, or APFS, falling back to copies
+// elsewhere.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DedupMode {
+	Off,
+	Hardlink,
+	Reflink,
+}
+
+impl Default for DedupMode {
+	fn default() -> Self {
+		DedupMode::Off
+	}
+}
+
+impl std::str::FromStr for DedupMode {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"off" => Ok(DedupMode::Off),
+			"hardlink" => Ok(DedupMode::Hardlink),
+			"reflink" => Ok(DedupMode::Reflink),
+			_ => Err(format!(
+				"invalid dedup mode \"{}\", expected off, hardlink, or reflink",
+				value
+			)),
+		}
+	}
+}
+
+// Replaces each file in the output directory with a link to the first file, in path
+// order, with the same contents and permissions. Hardlinked files change together, so
+// this runs after every stage that edits files in place.
+#[derive(Debug, Default)]
+pub struct Dedup {
+	pub mode: DedupMode,
+}
+
+impl Stage<PathBuf> for Dedup {
+	type Output = PathBuf;
+
+	fn run(&self, output_path: PathBuf) -> Result<PathBuf> {
+		if self.mode == DedupMode::Off {
+			return Ok(output_path);
+		}
+		// Only files of the same size and permissions can be identical, so only those are hashed.
+		let mut candidates = HashMap::<_, Vec<_>>::new();
+		for entry in WalkDir::new(&output_path).sort_by_file_name() {
+			let entry = entry?;
+			if !entry.file_type().is_file() {
+				continue;
+			}
+			let metadata = entry.metadata()?;
+			if metadata.len() == 0 {
+				continue;
+			}
+			candidates
+				.entry((metadata.len(), mode(&metadata)))
+				.or_default()
+				.push(entry.into_path());
+		}
+		let mut count = 0;
+		let mut size = 0;
+		for ((file_size, _), paths) in candidates.into_iter().filter(|(_, paths)| paths.len() > 1) {
+			let mut canonical_paths = HashMap::new();
+			for path in paths {
+				let hash = hash_file(&path)?;
+				let canonical_path = match canonical_paths.get(&hash) {
+					Some(canonical_path) => canonical_path,
+					None => {
+						canonical_paths.insert(hash, path);
+						continue;
+					}
+				};
+				if is_same_file(canonical_path, &path) {
+					continue;
+				}
+				// Link to a temporary name first, so the file is replaced atomically, and
+				// keep it when it cannot be linked.
+				let temporary_path = path.with_file_name(format!(
+					".windows_sdk-dedup-{}",
+					path.file_name().unwrap().to_string_lossy()
+				));
+				std::fs::remove_file(&temporary_path).ok();
+				if link_file(canonical_path, &temporary_path, self.mode)? {
+					std::fs::rename(&temporary_path, &path)?;
+					count += 1;
+					size += file_size;
+				}
+			}
+		}
+		tracing::info!("deduplicated {} files, saving {}", count, HumanBytes(size));
+		Ok(output_path)
+	}
+}
+
+// Link the destination to the source with the mode, returning whether it was linked.
+// Reflinks that the filesystem does not support are not an error, so the caller can copy
+// instead.
+pub(crate) fn link_file(source: &Path, destination: &Path, mode: DedupMode) -> Result<bool> {
+	match mode {
+		DedupMode::Off => Ok(false),
+		DedupMode::Hardlink => {
+			std::fs::hard_link(source, destination)?;
+			Ok(true)
+		}
+		DedupMode::Reflink => match reflink(source, destination) {
+			Ok(()) => Ok(true),
+			Err(error) => {
+				tracing::debug!("failed to reflink {}: {}", destination.display(), error);
+				std::fs::remove_file(destination).ok();
+				Ok(false)
+			}
+		},
+	}
+}
+
+// Place a copy of the source at the destination, linking it when the mode allows.
+pub(crate) fn link_or_copy(source: &Path, destination: &Path, mode: DedupMode) -> Result<()> {
+	if !link_file(source, destination, mode)? {
+		std::fs::copy(source, destination)?;
+	}
+	Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, destination: &Path) -> std::io::Result<()> {
+	use std::os::unix::io::AsRawFd;
+	// `FICLONE` from `linux/fs.h`.
+	const FICLONE: u32 = 0x4004_9409;
+	let source = std::fs::File::open(source)?;
+	let destination = std::fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(destination)?;
+	if unsafe { libc::ioctl(destination.as_raw_fd(), FICLONE as _, source.as_raw_fd()) } != 0 {
+		return Err(std::io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn reflink(source: &Path, destination: &Path) -> std::io::Result<()> {
+	use std::os::unix::ffi::OsStrExt;
+	let source = std::ffi::CString::new(source.as_os_str().as_bytes())?;
+	let destination = std::ffi::CString::new(destination.as_os_str().as_bytes())?;
+	if unsafe { libc::clonefile(source.as_ptr(), destination.as_ptr(), 0) } != 0 {
+		return Err(std::io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn reflink(_source: &Path, _destination: &Path) -> std::io::Result<()> {
+	Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+#[cfg(unix)]
+fn mode(metadata: &std::fs::Metadata) -> u32 {
+	use std::os::unix::fs::PermissionsExt;
+	metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &std::fs::Metadata) -> u32 {
+	metadata.permissions().readonly() as u32
+}
+
+// Files hardlinked by an earlier run are already deduplicated.
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	match (std::fs::metadata(a), std::fs::metadata(b)) {
+		(Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+		_ => false,
+	}
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_a: &Path, _b: &Path) -> bool {
+	false
+}
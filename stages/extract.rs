@@ -0,0 +1,1400 @@
+use super::{
+	dedup::{link_file, link_or_copy, DedupMode},
+	Stage,
+};
+use crate::{
+	limits::MemoryBudget,
+	msi_metadata::{read_msi_metadata, MsiMetadataRecord},
+	ownership::{OwnedFile, OwnedPackage, OwnershipDatabase},
+	paths::same_filesystem,
+	progress::{Progress, ProgressSink},
+	Cache, Error, Package, PackageFilter, Result,
+};
+use rayon::prelude::*;
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	io::{Cursor, Read, Seek, Write},
+	path::{Component, Path, PathBuf},
+	sync::{
+		mpsc::{sync_channel, RecvTimeoutError},
+		Arc,
+	},
+	time::{Duration, Instant},
+};
+use tempfile::{tempdir, TempDir};
+use walkdir::WalkDir;
+
+// Extracts the payloads of each package from the cache into a clean output directory,
+// recording which package and payload each file came from. When incremental, packages
+// whose payloads are unchanged since the last incremental extraction into the output
+// directory are kept rather than extracted again.
+pub struct Extract {
+	pub cache: Cache,
+	pub output_path: PathBuf,
+	pub filters: Vec<ExtractFilter>,
+	pub progress: Arc<dyn ProgressSink>,
+	pub incremental: bool,
+	// The tree of the last extraction, which an incremental extraction takes the files of
+	// the kept packages from. It must not be the output path, which is cleaned first.
+	pub previous_output_path: Option<PathBuf>,
+	// Copy rather than hardlink the kept files when reflinks are not supported, since a
+	// later stage changes their permissions or owner, which hardlinks share with the
+	// previous tree.
+	pub copy_kept_files: bool,
+	// The number of packages to extract at once, or zero for one per CPU.
+	pub jobs: usize,
+	// The total size of the payloads of the packages extracted at once, which extraction
+	// may read into memory.
+	pub memory_budget: Option<u64>,
+	// Warn about payloads that cannot be extracted, such as installers without embedded
+	// cabinets, rather than failing.
+	pub skip_unsupported: bool,
+	// Fail on payloads of types that are not extracted, and on payloads missing from the
+	// cache, such as those filtered out by type when downloading, rather than warning and
+	// skipping them.
+	pub strict_payloads: bool,
+	pub vsix_subtrees: Vec<VsixSubtree>,
+	// Give up on payloads that take longer than this to extract.
+	pub timeout: Option<Duration>,
+	// Leave out the packages whose payloads fail to extract or time out, rather than failing.
+	pub keep_going: bool,
+	// Link the payloads from the cache into staging rather than copying them.
+	pub dedup: DedupMode,
+	// Copy the payloads when the cache and output are on different filesystems, rather
+	// than failing because they cannot be linked.
+	pub copy_across_filesystems: bool,
+	// Record the features, components, and files of every MSI in the selection next to
+	// the ownership record.
+	pub msi_metadata: bool,
+}
+
+pub struct Extracted {
+	pub output_path: PathBuf,
+	// The packages that were left out because a payload failed to extract, with the error.
+	pub failures: Vec<(String, Error)>,
+}
+
+impl Stage<Vec<Package>> for Extract {
+	type Output = Extracted;
+
+	fn run(&self, packages: Vec<Package>) -> Result<Extracted> {
+		// Payloads can only be linked from a cache on the output's filesystem.
+		let link_mode = if self.dedup == DedupMode::Off
+			|| same_filesystem(self.cache.path(), &self.output_path)
+		{
+			self.dedup
+		} else if self.copy_across_filesystems {
+			DedupMode::Off
+		} else {
+			return Err(Error::InvalidPath {
+				path: self.output_path.clone(),
+				reason: "it is on a different filesystem than the cache, so payloads cannot be linked into it, pass --copy-across-filesystems to copy them".to_owned(),
+			});
+		};
+		let previous = match self.previous_output_path.as_ref() {
+			Some(previous_output_path) if self.incremental => {
+				OwnershipDatabase::read(previous_output_path)?.filter(|previous| {
+					previous.incremental
+						&& previous.filters == self.filters
+						&& previous.skip_unsupported == self.skip_unsupported
+						&& previous.vsix_subtrees == self.vsix_subtree_specs()
+				})
+			}
+			_ => None,
+		};
+		self.cache.migrate()?;
+		// Every MSI is read, including those of kept packages, since the record is written again.
+		let msi_metadata = if self.msi_metadata {
+			Some(self.read_msi_metadata(&packages)?)
+		} else {
+			None
+		};
+		// Clean and create the output path.
+		if self.output_path.exists() {
+			std::fs::remove_dir_all(&self.output_path)?;
+		}
+		std::fs::create_dir_all(&self.output_path)?;
+		let mut owned_packages = Vec::new();
+		let packages = match previous {
+			Some(previous) => {
+				let (kept, packages): (Vec<_>, Vec<_>) =
+					packages.into_iter().partition(|package| {
+						previous.packages.iter().any(|owned| owned.matches(package))
+					});
+				owned_packages = previous
+					.packages
+					.into_iter()
+					.filter(|owned| kept.iter().any(|package| owned.matches(package)))
+					.collect();
+				link_kept_files(
+					self.previous_output_path.as_ref().unwrap(),
+					&self.output_path,
+					&owned_packages,
+					self.copy_kept_files,
+				)?;
+				packages
+			}
+			None => packages,
+		};
+		let total_size = packages
+			.iter()
+			.flat_map(|package| package.payloads.iter())
+			.map(|payload| payload.size)
+			.sum();
+		let progress_bar = Progress::new(&self.progress, "extract", total_size);
+		// The payloads are linked and extracted next to the output directory, so they can
+		// be renamed into it, but never end up in it if extraction stops partway. The
+		// name starts with the output's, so a staged output's cleanup also removes the
+		// ones interrupted extractions leave.
+		let work_tempdir = tempfile::Builder::new()
+			.prefix(&format!(
+				"{}.",
+				self.output_path.file_name().unwrap().to_string_lossy()
+			))
+			.tempdir_in(self.output_path.parent().unwrap())?;
+		// Extract the packages concurrently, each into its own staging directories, then
+		// merge them into the output in order, so files that several packages contain end
+		// up as they would when extracting serially.
+		let thread_pool = rayon::ThreadPoolBuilder::new()
+			.num_threads(self.jobs)
+			.build()
+			.map_err(|error| std::io::Error::new(std::io::ErrorKind::Other, error))?;
+		let memory_budget = MemoryBudget::new(self.memory_budget);
+		let staged_packages = thread_pool.install(|| {
+			packages
+				.par_iter()
+				.map(|package| {
+					let size = package.payloads.iter().map(|payload| payload.size).sum();
+					let _reservation = memory_budget.reserve(size);
+					self.stage_package(package, work_tempdir.path(), link_mode, &progress_bar)
+				})
+				.map(|result| match result {
+					Err(error) if self.keep_going && is_payload_failure(&error) => Ok(Err(error)),
+					result => result.map(Ok),
+				})
+				.collect::<Result<Vec<_>>>()
+		})?;
+		let mut failures = Vec::new();
+		// The paths in the output by their lowercased form, which payloads of any type, and
+		// the kept files, must not share.
+		let mut merged_paths = owned_packages
+			.iter()
+			.flat_map(|package| package.files.iter())
+			.map(|file| (file.path.to_lowercase(), file.path.clone()))
+			.collect::<HashMap<_, _>>();
+		for (package, staging_dirs) in packages.iter().zip(staged_packages) {
+			// Failed packages are not recorded, so an incremental extraction tries them again.
+			let staging_dirs = match staging_dirs {
+				Ok(staging_dirs) => staging_dirs,
+				Err(error) => {
+					tracing::error!("skipping {}: {}", package.id, error);
+					failures.push((package.id.to_owned(), error));
+					continue;
+				}
+			};
+			let mut files = Vec::new();
+			for (payload, staging_dir) in staging_dirs {
+				for path in
+					merge_staging_dir(staging_dir.path(), &self.output_path, &mut merged_paths)?
+				{
+					files.push(OwnedFile {
+						path,
+						payload: payload.clone(),
+					});
+				}
+			}
+			owned_packages.push(OwnedPackage::new(package, files));
+		}
+		progress_bar.finish();
+		OwnershipDatabase {
+			filters: self.filters.clone(),
+			skip_unsupported: self.skip_unsupported,
+			vsix_subtrees: self.vsix_subtree_specs(),
+			incremental: self.incremental,
+			packages: owned_packages,
+		}
+		.write(&self.output_path)?;
+		if let Some(msi_metadata) = msi_metadata {
+			msi_metadata.write(&self.output_path)?;
+		}
+		Ok(Extracted {
+			output_path: self.output_path.clone(),
+			failures,
+		})
+	}
+}
+
+impl Extract {
+	fn vsix_subtree_specs(&self) -> Vec<String> {
+		self.vsix_subtrees
+			.iter()
+			.map(|vsix_subtree| vsix_subtree.to_string())
+			.collect()
+	}
+
+	fn read_msi_metadata(&self, packages: &[Package]) -> Result<MsiMetadataRecord> {
+		let mut msis = Vec::new();
+		for package in packages.iter() {
+			for payload in package.payloads.iter() {
+				let path = self.cache.payload_path(payload);
+				if !payload.file_name.to_ascii_lowercase().ends_with(".msi") || !path.exists() {
+					continue;
+				}
+				let metadata = read_msi_metadata(
+					&path,
+					package.id.to_ascii_lowercase(),
+					hex::encode(payload.sha256),
+					payload.file_name.clone(),
+				)
+				.map_err(|source| Error::Extraction {
+					path: path.clone(),
+					source,
+				})?;
+				msis.push(metadata);
+			}
+		}
+		Ok(MsiMetadataRecord { msis })
+	}
+
+	// Extract each payload of the package into its own staging directory, so the files
+	// can be attributed to their payloads.
+	fn stage_package(
+		&self,
+		package: &Package,
+		work_path: &Path,
+		link_mode: DedupMode,
+		progress_bar: &Progress,
+	) -> Result<Vec<(String, TempDir)>> {
+		let mut staging_dirs = Vec::new();
+		// Links need the payloads on the filesystem of the cache, which is the output's.
+		let package_tempdir = if link_mode == DedupMode::Off {
+			tempdir()?
+		} else {
+			tempfile::Builder::new()
+				.prefix(".windows_sdk-payloads")
+				.tempdir_in(work_path)?
+		};
+		let vsix_subtrees = vsix_subtrees_for(&self.vsix_subtrees, package);
+		let mut missing_payloads = HashSet::new();
+		for payload in package.payloads.iter() {
+			let payload_cache_path = self.cache.payload_path(payload);
+			// Payloads may have been filtered out by type when downloading. The ones
+			// whose type is not extracted are reported as they are extracted.
+			if !payload_cache_path.exists() {
+				let message = "the payload is not in the cache, it may have been filtered out by type when downloading";
+				if self.strict_payloads {
+					return Err(Error::Extraction {
+						path: PathBuf::from(&payload.file_name),
+						source: std::io::Error::new(std::io::ErrorKind::NotFound, message),
+					});
+				}
+				if is_extracted(&payload.file_name) {
+					tracing::warn!("skipping {}: {}", payload.file_name, message);
+					missing_payloads.insert(&payload.file_name);
+				}
+				continue;
+			}
+			let payload_tempdir_path = package_tempdir
+				.path()
+				.join(payload.file_name.replace('\\', "/"));
+			std::fs::create_dir_all(payload_tempdir_path.parent().unwrap())?;
+			link_or_copy(&payload_cache_path, &payload_tempdir_path, link_mode)?;
+		}
+		for payload in package.payloads.iter() {
+			if missing_payloads.contains(&payload.file_name) {
+				progress_bar.inc(payload.size);
+				continue;
+			}
+			if self.strict_payloads && !is_extracted(&payload.file_name) {
+				return Err(Error::Extraction {
+					path: PathBuf::from(&payload.file_name),
+					source: std::io::Error::new(
+						std::io::ErrorKind::Unsupported,
+						"the payload type is not supported",
+					),
+				});
+			}
+			let payload_tempdir_path = package_tempdir
+				.path()
+				.join(payload.file_name.replace('\\', "/"));
+			let staging_dir = tempfile::Builder::new()
+				.prefix(".windows_sdk-staging")
+				.tempdir_in(work_path)?;
+			tracing::debug!("extracting {} of {}", payload.file_name, package.id);
+			let deadline = Deadline::after(self.timeout);
+			// The worker owns the staging directory until it returns, so one abandoned at the
+			// deadline removes it once it stops, and its files never reach the output.
+			let (sender, receiver) = sync_channel(1);
+			let filters = self.filters.clone();
+			let worker_vsix_subtrees = vsix_subtrees.clone();
+			std::thread::spawn(move || {
+				let result = extract_payload(
+					&payload_tempdir_path,
+					staging_dir.path(),
+					&filters,
+					&worker_vsix_subtrees,
+					deadline,
+				);
+				sender.send((result, staging_dir)).ok();
+			});
+			let received = match deadline.remaining() {
+				Some(remaining) => receiver.recv_timeout(remaining),
+				None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+			};
+			let (result, staging_dir) = match received {
+				Ok(received) => received,
+				Err(RecvTimeoutError::Timeout) => {
+					return Err(Error::ExtractionTimeout {
+						path: PathBuf::from(&payload.file_name),
+						timeout: self.timeout.unwrap_or_default(),
+					})
+				}
+				Err(RecvTimeoutError::Disconnected) => {
+					return Err(Error::Extraction {
+						path: PathBuf::from(&payload.file_name),
+						source: std::io::Error::new(
+							std::io::ErrorKind::Other,
+							"the extraction panicked",
+						),
+					})
+				}
+			};
+			match result {
+				Ok(()) => {}
+				Err(error) if Deadline::caused(&error) => {
+					return Err(Error::ExtractionTimeout {
+						path: PathBuf::from(&payload.file_name),
+						timeout: self.timeout.unwrap_or_default(),
+					})
+				}
+				Err(error)
+					if self.skip_unsupported && error.kind() == std::io::ErrorKind::Unsupported =>
+				{
+					tracing::warn!("skipping {}: {}", payload.file_name, error);
+				}
+				Err(error) if error.kind() == std::io::ErrorKind::Unsupported => {
+					return Err(Error::Extraction {
+						path: PathBuf::from(&payload.file_name),
+						source: std::io::Error::new(
+							error.kind(),
+							format!("{}, pass --skip-unsupported to skip it", error),
+						),
+					})
+				}
+				Err(source) => {
+					return Err(Error::Extraction {
+						path: PathBuf::from(&payload.file_name),
+						source,
+					})
+				}
+			}
+			progress_bar.inc(payload.size);
+			staging_dirs.push((hex::encode(payload.sha256), staging_dir));
+		}
+		Ok(staging_dirs)
+	}
+}
+
+// When the extraction of a payload gives up. The extraction runs on a worker that is
+// abandoned at the deadline, and checks it before each file and each chunk it writes,
+// so the worker stops soon after.
+#[derive(Clone, Copy)]
+struct Deadline(Option<Instant>);
+
+// The source of the errors the deadline returns, which tells them apart from other
+// timeouts.
+#[derive(Debug)]
+struct DeadlineExpired;
+
+impl std::fmt::Display for DeadlineExpired {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "the extraction timed out")
+	}
+}
+
+impl std::error::Error for DeadlineExpired {}
+
+impl Deadline {
+	// A timeout too long to represent never expires.
+	fn after(timeout: Option<Duration>) -> Deadline {
+		Deadline(timeout.and_then(|timeout| Instant::now().checked_add(timeout)))
+	}
+
+	fn expired(&self) -> bool {
+		self.0.map_or(false, |deadline| Instant::now() >= deadline)
+	}
+
+	// The time left, or `None` without a deadline.
+	fn remaining(&self) -> Option<Duration> {
+		self.0
+			.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+	}
+
+	fn check(&self) -> std::io::Result<()> {
+		if self.expired() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::TimedOut,
+				DeadlineExpired,
+			));
+		}
+		Ok(())
+	}
+
+	// Whether the error was returned by the deadline.
+	fn caused(error: &std::io::Error) -> bool {
+		error
+			.get_ref()
+			.map_or(false, |source| source.is::<DeadlineExpired>())
+	}
+}
+
+// Copy the reader into the writer in chunks, stopping at the deadline.
+fn copy_until(
+	reader: &mut impl Read,
+	writer: &mut impl Write,
+	deadline: Deadline,
+) -> std::io::Result<()> {
+	let mut buffer = vec![0; 1 << 16];
+	loop {
+		deadline.check()?;
+		let length = match reader.read(&mut buffer) {
+			Ok(0) => return Ok(()),
+			Ok(length) => length,
+			Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(error) => return Err(error),
+		};
+		writer.write_all(&buffer[..length])?;
+	}
+}
+
+fn is_payload_failure(error: &Error) -> bool {
+	matches!(
+		error,
+		Error::Extraction { .. } | Error::ExtractionTimeout { .. }
+	)
+}
+
+// Move the staged files into the output directory, returning their paths relative to it
+// with forward slashes.
+// A file whose path differs only in case from one already merged would overwrite it
+// on case insensitive filesystems, so the one merged first is kept.
+fn merge_staging_dir(
+	staging_path: &Path,
+	output_path: &Path,
+	merged_paths: &mut HashMap<String, String>,
+) -> Result<Vec<String>> {
+	let mut files = Vec::new();
+	for entry in WalkDir::new(staging_path).min_depth(1).sort_by_file_name() {
+		let entry = entry?;
+		let relative_path = entry.path().strip_prefix(staging_path).unwrap();
+		let path = output_path.join(relative_path);
+		if entry.file_type().is_dir() {
+			std::fs::create_dir_all(&path)?;
+			continue;
+		}
+		let file = relative_path.to_string_lossy().replace('\\', "/");
+		match merged_paths.get(&file.to_lowercase()) {
+			Some(merged) if *merged != file => {
+				tracing::warn!(
+					"{} and {} differ only in case, keeping {}",
+					merged,
+					file,
+					merged
+				);
+			}
+			_ => {
+				std::fs::rename(entry.path(), &path)?;
+				merged_paths.insert(file.to_lowercase(), file.clone());
+				files.push(file);
+			}
+		}
+	}
+	Ok(files)
+}
+
+// Place the files of the kept packages into the output from the previous tree, which
+// stays untouched until the new one replaces it. Headers and import libraries are
+// taken from the lowercased names postprocessing gave them. Files are reflinked where
+// the filesystem supports it, or else hardlinked unless `copy` is set, and copied
+// otherwise.
+fn link_kept_files(
+	previous_output_path: &Path,
+	output_path: &Path,
+	kept_packages: &[OwnedPackage],
+	copy: bool,
+) -> Result<()> {
+	let mut kept_paths = HashSet::new();
+	for file in kept_packages
+		.iter()
+		.flat_map(|package| package.files.iter())
+	{
+		let path = PathBuf::from(&file.path);
+		let extension = path
+			.extension()
+			.and_then(|extension| extension.to_str())
+			.map(|extension| extension.to_ascii_lowercase());
+		if let Some("h" | "lib") = extension.as_deref() {
+			let file_name = path.file_name().unwrap().to_ascii_lowercase();
+			kept_paths.insert(path.with_file_name(file_name));
+		} else {
+			kept_paths.insert(path);
+		}
+	}
+	for relative_path in kept_paths {
+		let previous_path = previous_output_path.join(&relative_path);
+		// Files that a later package or postprocessing removed are not in the previous tree.
+		let metadata = match std::fs::symlink_metadata(&previous_path) {
+			Ok(metadata) if metadata.is_file() => metadata,
+			_ => continue,
+		};
+		let path = output_path.join(&relative_path);
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		if link_file(&previous_path, &path, DedupMode::Reflink)? {
+			std::fs::set_permissions(&path, metadata.permissions())?;
+		} else if copy {
+			std::fs::copy(&previous_path, &path)?;
+		} else if let Err(error) = std::fs::hard_link(&previous_path, &path) {
+			tracing::debug!("failed to hardlink {}: {}", path.display(), error);
+			std::fs::copy(&previous_path, &path)?;
+		}
+	}
+	Ok(())
+}
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractFilter {
+	Include,
+	Lib,
+}
+
+impl std::str::FromStr for ExtractFilter {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"include" => Ok(ExtractFilter::Include),
+			"lib" => Ok(ExtractFilter::Lib),
+			_ => Err(format!(
+				"invalid filter \"{}\", expected include or lib",
+				value
+			)),
+		}
+	}
+}
+
+impl ExtractFilter {
+	fn matches(&self, path: &Path) -> bool {
+		let directory_name = match self {
+			ExtractFilter::Include => "include",
+			ExtractFilter::Lib => "lib",
+		};
+		path.parent()
+			.into_iter()
+			.flat_map(|parent| parent.components())
+			.any(|component| {
+				component
+					.as_os_str()
+					.to_str()
+					.map(|component| component.eq_ignore_ascii_case(directory_name))
+					.unwrap_or(false)
+			})
+	}
+}
+
+// Which subtree of a VSIX to extract and where to put it, written
+// `[PACKAGES:]SUBTREE=DESTINATION`, such as `$MSBuild=MSBuild` or
+// `Microsoft.VisualCpp.*:.=vsix`. The subtree `.` is the whole archive, including its
+// manifests, and the destination is relative to the output directory. The rules that
+// match a package replace the default `Contents=.` for it, and packages may be given as a
+// glob or a regex between slashes.
+#[derive(Clone, Debug)]
+pub struct VsixSubtree {
+	spec: String,
+	packages: Option<PackageFilter>,
+	subtree: String,
+	destination: PathBuf,
+}
+
+impl std::str::FromStr for VsixSubtree {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let invalid = |reason: &str| format!("invalid vsix subtree \"{}\", {}", value, reason);
+		let (source, destination) = value
+			.split_once('=')
+			.ok_or_else(|| invalid("expected [PACKAGES:]SUBTREE=DESTINATION"))?;
+		let (packages, subtree) = match source.split_once(':') {
+			Some((packages, subtree)) => (Some(packages.parse::<PackageFilter>()?), subtree),
+			None => (None, source),
+		};
+		let is_relative = |path: &str| {
+			Path::new(path)
+				.components()
+				.all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+		};
+		if subtree.is_empty() || !is_relative(subtree) {
+			return Err(invalid(
+				"the subtree must be a relative path inside the archive",
+			));
+		}
+		if destination.is_empty() || !is_relative(destination) {
+			return Err(invalid(
+				"the destination must be a relative path inside the output directory",
+			));
+		}
+		let subtree = subtree.replace('\\', "/").trim_matches('/').to_owned();
+		Ok(VsixSubtree {
+			spec: value.to_owned(),
+			packages,
+			subtree: if subtree == "." {
+				String::new()
+			} else {
+				subtree
+			},
+			destination: PathBuf::from(destination),
+		})
+	}
+}
+
+impl std::fmt::Display for VsixSubtree {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.spec)
+	}
+}
+
+impl Default for VsixSubtree {
+	fn default() -> Self {
+		"Contents=.".parse().unwrap()
+	}
+}
+
+impl VsixSubtree {
+	fn matches(&self, package: &Package) -> bool {
+		self.packages
+			.as_ref()
+			.map(|packages| packages.matches(&package.id))
+			.unwrap_or(true)
+	}
+}
+
+// The rules for the package's VSIXs, or the default if none match it.
+fn vsix_subtrees_for(vsix_subtrees: &[VsixSubtree], package: &Package) -> Vec<VsixSubtree> {
+	let matching = vsix_subtrees
+		.iter()
+		.filter(|vsix_subtree| vsix_subtree.matches(package))
+		.cloned()
+		.collect::<Vec<_>>();
+	if matching.is_empty() {
+		vec![VsixSubtree::default()]
+	} else {
+		matching
+	}
+}
+
+fn matches_filters(filters: &[ExtractFilter], path: &Path) -> bool {
+	filters.is_empty() || filters.iter().any(|filter| filter.matches(path))
+}
+
+// MSIs, VSIXs, nupkgs, zips, EXEs, and MSUs are extracted, and cabinets are read by the
+// MSIs that reference them.
+fn is_extracted(file_name: &str) -> bool {
+	let file_name = file_name.to_ascii_lowercase();
+	[".msi", ".vsix", ".nupkg", ".zip", ".exe", ".msu", ".cab"]
+		.iter()
+		.any(|extension| file_name.ends_with(extension))
+}
+
+fn extract_payload(
+	payload_path: &Path,
+	output_path: &Path,
+	filters: &[ExtractFilter],
+	vsix_subtrees: &[VsixSubtree],
+	deadline: Deadline,
+) -> std::io::Result<()> {
+	enum ExtractionType {
+		Cab,
+		Exe,
+		Msi,
+		Msu,
+		Nupkg,
+		Vsix,
+		Zip,
+	}
+	let file_name = payload_path.to_string_lossy().to_ascii_lowercase();
+	let extraction_type = if file_name.ends_with(".msi") {
+		Some(ExtractionType::Msi)
+	} else if file_name.ends_with(".cab") {
+		Some(ExtractionType::Cab)
+	} else if file_name.ends_with(".exe") {
+		Some(ExtractionType::Exe)
+	} else if file_name.ends_with(".msu") {
+		Some(ExtractionType::Msu)
+	} else if file_name.ends_with(".nupkg") {
+		Some(ExtractionType::Nupkg)
+	} else if file_name.ends_with(".vsix") {
+		Some(ExtractionType::Vsix)
+	} else if file_name.ends_with(".zip") {
+		Some(ExtractionType::Zip)
+	} else {
+		None
+	};
+	match extraction_type {
+		None => {
+			tracing::warn!(
+				"skipping {}: the payload type is not supported",
+				payload_path.file_name().unwrap().to_string_lossy()
+			);
+		}
+		// Cabinets are read by the MSIs that reference them.
+		Some(ExtractionType::Cab) => {}
+		Some(ExtractionType::Msi) => {
+			extract_msi(payload_path, output_path, filters, deadline)?;
+		}
+		// Self-extracting installers carry their files in cabinets appended to the
+		// executable, and MSUs are cabinets holding the cabinets of the update.
+		Some(ExtractionType::Exe | ExtractionType::Msu) => {
+			let unpacked_tempdir = tempdir()?;
+			let bytes = std::fs::read(payload_path)?;
+			if !unpack_embedded_cabinets(&bytes, unpacked_tempdir.path(), deadline)? {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::Unsupported,
+					"the payload has no embedded cabinets",
+				));
+			}
+			extract_unpacked(unpacked_tempdir.path(), output_path, filters, deadline)?;
+		}
+		Some(ExtractionType::Nupkg) => {
+			// Targeting packs only need their reference assemblies, which go under
+			// `dotnet/<package>/ref` to keep them apart from the native files. Other
+			// packages, such as tools, are extracted whole into `dotnet/<package>`.
+			let package_name = payload_path.file_stem().unwrap();
+			let package_path = output_path.join("dotnet").join(package_name);
+			if zip_has_directory(payload_path, "ref")? {
+				extract_zip(
+					payload_path,
+					"ref",
+					&package_path.join("ref"),
+					filters,
+					deadline,
+				)?;
+			} else {
+				extract_zip(payload_path, "", &package_path, filters, deadline)?;
+				// Drop the packaging metadata that every nupkg carries.
+				std::fs::remove_file(package_path.join("[Content_Types].xml")).ok();
+				std::fs::remove_dir_all(package_path.join("_rels")).ok();
+				std::fs::remove_dir_all(package_path.join("package")).ok();
+			}
+		}
+		Some(ExtractionType::Vsix) => {
+			for vsix_subtree in vsix_subtrees {
+				let destination_path = output_path.join(&vsix_subtree.destination);
+				extract_zip(
+					payload_path,
+					&vsix_subtree.subtree,
+					&destination_path,
+					filters,
+					deadline,
+				)?;
+			}
+		}
+		Some(ExtractionType::Zip) => {
+			extract_zip(payload_path, "", output_path, filters, deadline)?;
+		}
+	}
+	Ok(())
+}
+
+const CABINET_SIGNATURE: &[u8] = b"MSCF\0\0\0\0";
+const MSI_SIGNATURE: &[u8] = &[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+const WIM_SIGNATURE: &[u8] = b"MSWIM\0\0\0";
+
+// Find the cabinets in the bytes and write their files into the output directory,
+// returning whether there were any. The header of a cabinet records its size, so the
+// search resumes after each one.
+fn unpack_embedded_cabinets(
+	bytes: &[u8],
+	output_path: &Path,
+	deadline: Deadline,
+) -> std::io::Result<bool> {
+	let mut found = false;
+	let mut offset = 0;
+	while let Some(position) = find(&bytes[offset..], CABINET_SIGNATURE) {
+		deadline.check()?;
+		let start = offset + position;
+		let size = bytes
+			.get(start + 8..start + 12)
+			.map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+			.unwrap_or(0);
+		// The header's version is always 1.3, which rules out most false matches.
+		let version = bytes.get(start + 24..start + 26);
+		let is_cabinet = matches!(version, Some([3, 1])) && size > 0;
+		// A match that fails to unpack is not a cabinet, unless it failed because the
+		// deadline passed.
+		let unpacked = match bytes.get(start..start + size) {
+			Some(cabinet_bytes) if is_cabinet => {
+				let result = unpack_cabinet(cabinet_bytes, output_path, deadline);
+				deadline.check()?;
+				result.is_ok()
+			}
+			_ => false,
+		};
+		if unpacked {
+			found = true;
+			offset = start + size;
+		} else {
+			offset = start + 1;
+		}
+	}
+	Ok(found)
+}
+
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+	bytes
+		.windows(pattern.len())
+		.position(|window| window == pattern)
+}
+
+fn unpack_cabinet(bytes: &[u8], output_path: &Path, deadline: Deadline) -> std::io::Result<()> {
+	let mut cabinet = cab::Cabinet::new(Cursor::new(bytes))?;
+	let names = cabinet
+		.folder_entries()
+		.flat_map(|folder| folder.file_entries())
+		.map(|file| file.name().to_owned())
+		.collect::<Vec<_>>();
+	for name in names {
+		// Skip entries whose names would escape the output directory.
+		let relative_path = PathBuf::from(name.replace('\\', "/"));
+		if relative_path
+			.components()
+			.any(|component| !matches!(component, std::path::Component::Normal(_)))
+		{
+			continue;
+		}
+		let path = output_path.join(relative_path);
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		let mut reader = cabinet.read_file(&name)?;
+		let mut file = std::fs::File::create(path)?;
+		copy_until(&mut reader, &mut file, deadline)?;
+	}
+	Ok(())
+}
+
+// Extract the files unpacked from an installer's cabinets into the output directory.
+// Installers often name their files arbitrarily, so they are told apart by their
+// contents: MSIs are extracted, reading their cabinets from beside them, the cabinets of
+// installers without MSIs are extracted in turn, and other files are copied.
+fn extract_unpacked(
+	unpacked_path: &Path,
+	output_path: &Path,
+	filters: &[ExtractFilter],
+	deadline: Deadline,
+) -> std::io::Result<()> {
+	let mut msi_paths = Vec::new();
+	let mut cabinet_paths = Vec::new();
+	let mut file_paths = Vec::new();
+	for entry in WalkDir::new(unpacked_path).sort_by_file_name() {
+		let entry = entry?;
+		if !entry.file_type().is_file() {
+			continue;
+		}
+		let mut signature = [0; 8];
+		let length = std::fs::File::open(entry.path())?.read(&mut signature)?;
+		let signature = &signature[..length];
+		if signature == MSI_SIGNATURE {
+			msi_paths.push(entry.into_path());
+		} else if signature == CABINET_SIGNATURE {
+			cabinet_paths.push(entry.into_path());
+		} else if signature == WIM_SIGNATURE {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::Unsupported,
+				"the payload holds a WIM image, which is not supported",
+			));
+		} else {
+			file_paths.push(entry.into_path());
+		}
+	}
+	for msi_path in msi_paths.iter() {
+		extract_msi(msi_path, output_path, filters, deadline)?;
+	}
+	if msi_paths.is_empty() {
+		for cabinet_path in cabinet_paths {
+			let cabinet_tempdir = tempdir()?;
+			unpack_cabinet(
+				&std::fs::read(&cabinet_path)?,
+				cabinet_tempdir.path(),
+				deadline,
+			)?;
+			extract_unpacked(cabinet_tempdir.path(), output_path, filters, deadline)?;
+		}
+	}
+	for file_path in file_paths {
+		let relative_path = file_path.strip_prefix(unpacked_path).unwrap();
+		if !matches_filters(filters, relative_path) {
+			continue;
+		}
+		let path = output_path.join(relative_path);
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		copy_until(
+			&mut std::fs::File::open(&file_path)?,
+			&mut std::fs::File::create(path)?,
+			deadline,
+		)?;
+	}
+	Ok(())
+}
+
+fn zip_has_directory(zip_path: &Path, directory: &str) -> std::io::Result<bool> {
+	let archive = zip::ZipArchive::new(std::fs::File::open(zip_path)?)?;
+	let prefix = format!("{}/", directory);
+	let has_directory = archive.file_names().any(|name| name.starts_with(&prefix));
+	Ok(has_directory)
+}
+
+// Extract the files under the archive's `subtree` directory whose paths match the
+// filters, streaming them directly out of the archive.
+fn extract_zip(
+	zip_path: &Path,
+	subtree: &str,
+	output_path: &Path,
+	filters: &[ExtractFilter],
+	deadline: Deadline,
+) -> std::io::Result<()> {
+	let mut archive = zip::ZipArchive::new(std::fs::File::open(zip_path)?)?;
+	for index in 0..archive.len() {
+		deadline.check()?;
+		let mut entry = archive.by_index(index)?;
+		// Skip entries whose names would escape the output directory.
+		let path = match entry.enclosed_name() {
+			Some(path) => path.to_owned(),
+			None => continue,
+		};
+		let relative_path = match path.strip_prefix(subtree) {
+			Ok(relative_path) if relative_path.as_os_str().is_empty() => continue,
+			Ok(relative_path) => relative_path,
+			Err(_) => continue,
+		};
+		if entry.is_dir() {
+			if filters.is_empty() {
+				std::fs::create_dir_all(output_path.join(relative_path))?;
+			}
+			continue;
+		}
+		if !matches_filters(filters, relative_path) {
+			continue;
+		}
+		let path = output_path.join(relative_path);
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		let mut file = std::fs::File::create(&path)?;
+		copy_until(&mut entry, &mut file, deadline)?;
+	}
+	Ok(())
+}
+
+// Extract the files whose install paths match the filters, reading them directly out of
+// the cabinets using the MSI file table.
+fn extract_msi(
+	msi_path: &Path,
+	output_path: &Path,
+	filters: &[ExtractFilter],
+	deadline: Deadline,
+) -> std::io::Result<()> {
+	let mut package = msi::open(msi_path)?;
+	// Read the directory tree.
+	let directories = package
+		.select_rows(msi::Select::table("Directory"))?
+		.map(|row| {
+			let id = msi_string(&row, "Directory")?;
+			let parent = row["Directory_Parent"]
+				.as_str()
+				.map(|parent| parent.to_owned());
+			let name = msi_long_name(&msi_string(&row, "DefaultDir")?);
+			Ok((id, (parent, name)))
+		})
+		.collect::<std::io::Result<HashMap<_, _>>>()?;
+	let components = package
+		.select_rows(msi::Select::table("Component"))?
+		.map(|row| {
+			let id = msi_string(&row, "Component")?;
+			let directory = msi_string(&row, "Directory_")?;
+			Ok((id, directory))
+		})
+		.collect::<std::io::Result<HashMap<_, _>>>()?;
+	// Determine the install path of every file and keep the ones matching the filters,
+	// keyed by the name of their cabinet entry.
+	let mut files = HashMap::new();
+	let mut case_duplicates = BTreeMap::<_, Vec<_>>::new();
+	let mut short_names = Vec::new();
+	for row in package.select_rows(msi::Select::table("File"))? {
+		deadline.check()?;
+		let id = msi_string(&row, "File")?;
+		let component = msi_string(&row, "Component_")?;
+		let directory = components.get(&component).ok_or_else(|| {
+			std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				format!("file {} references unknown component {}", id, component),
+			)
+		})?;
+		let mut path = msi_directory_path(&directories, directory);
+		let name = msi_long_name(&msi_string(&row, "FileName")?);
+		let short = is_short_name(&name);
+		path.push(name);
+		// Directory and file names are not trusted to stay inside the output directory.
+		if !path
+			.components()
+			.all(|component| matches!(component, Component::Normal(_)))
+		{
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				format!("file {} has the invalid path {}", id, path.display()),
+			));
+		}
+		if matches_filters(filters, &path) {
+			if short {
+				short_names.push((id.clone(), path.clone()));
+			}
+			case_duplicates
+				.entry(path.to_string_lossy().to_lowercase())
+				.or_default()
+				.push((path.clone(), id.clone()));
+			files.insert(id, output_path.join(path));
+		}
+	}
+	// Files whose paths differ only in case would overwrite each other on case insensitive
+	// filesystems, and collide when postprocessing lowercases them on case sensitive ones.
+	// Keep the one with a lowercase name, or else the first in byte order, as
+	// postprocessing does.
+	for mut duplicates in case_duplicates
+		.into_values()
+		.filter(|paths| paths.len() > 1)
+	{
+		duplicates.sort();
+		let kept = duplicates
+			.iter()
+			.position(|(path, _)| {
+				let name = path.file_name().unwrap();
+				name.to_ascii_lowercase() == name
+			})
+			.unwrap_or(0);
+		let (kept_path, _) = duplicates.remove(kept);
+		for (path, id) in duplicates {
+			tracing::warn!(
+				"{} and {} in {} differ only in case, extracting {}",
+				kept_path.display(),
+				path.display(),
+				msi_path.file_name().unwrap().to_string_lossy(),
+				kept_path.display()
+			);
+			files.remove(&id);
+		}
+	}
+	// A file with only a short name, beside the file whose long name abbreviates to it, is
+	// a legacy entry for that file and is left out. Other short names are kept as they are.
+	let msi_name = msi_path.file_name().unwrap().to_string_lossy();
+	for (id, path) in short_names {
+		if !files.contains_key(&id) {
+			continue;
+		}
+		let name = path.file_name().unwrap().to_string_lossy();
+		let long_path = files.values().find(|other_path| {
+			let other_name = other_path.file_name().unwrap().to_string_lossy();
+			other_path.parent() == files[&id].parent()
+				&& !is_short_name(&other_name)
+				&& abbreviates(&name, &other_name)
+		});
+		match long_path {
+			Some(long_path) => {
+				tracing::warn!(
+					"{} in {} is the short name of {}, extracting {}",
+					path.display(),
+					msi_name,
+					long_path.file_name().unwrap().to_string_lossy(),
+					long_path.file_name().unwrap().to_string_lossy()
+				);
+				files.remove(&id);
+			}
+			None => {
+				tracing::warn!("{} in {} only has a short name", path.display(), msi_name);
+			}
+		}
+	}
+	if files.is_empty() {
+		return Ok(());
+	}
+	// Extract the matching files from each cabinet, which are either embedded as streams
+	// or sit next to the MSI.
+	let cabinets = package
+		.select_rows(msi::Select::table("Media"))?
+		.filter_map(|row| row["Cabinet"].as_str().map(|cabinet| cabinet.to_owned()))
+		.collect::<Vec<_>>();
+	for cabinet in cabinets {
+		if let Some(stream_name) = cabinet.strip_prefix('#') {
+			let mut cabinet_bytes = Vec::new();
+			package
+				.read_stream(stream_name)?
+				.read_to_end(&mut cabinet_bytes)?;
+			extract_cabinet_files(Cursor::new(cabinet_bytes), &files, deadline)?;
+		} else {
+			// A cabinet payload filtered out when downloading has already been reported as skipped.
+			let cabinet_path = msi_path.parent().unwrap().join(&cabinet);
+			let cabinet_file = match std::fs::File::open(cabinet_path) {
+				Ok(cabinet_file) => cabinet_file,
+				Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+					tracing::warn!(
+						"skipping the files of {} in {}, which is missing",
+						msi_path.file_name().unwrap().to_string_lossy(),
+						cabinet
+					);
+					continue;
+				}
+				Err(error) => return Err(error),
+			};
+			extract_cabinet_files(cabinet_file, &files, deadline)?;
+		}
+	}
+	Ok(())
+}
+
+fn extract_cabinet_files<R: Read + Seek>(
+	reader: R,
+	files: &HashMap<String, PathBuf>,
+	deadline: Deadline,
+) -> std::io::Result<()> {
+	let mut cabinet = cab::Cabinet::new(reader)?;
+	let names = cabinet
+		.folder_entries()
+		.flat_map(|folder| folder.file_entries())
+		.map(|file| file.name().to_owned())
+		.filter(|name| files.contains_key(name))
+		.collect::<Vec<_>>();
+	for name in names {
+		let path = &files[&name];
+		std::fs::create_dir_all(path.parent().unwrap())?;
+		let mut reader = cabinet.read_file(&name)?;
+		let mut file = std::fs::File::create(path)?;
+		copy_until(&mut reader, &mut file, deadline)?;
+	}
+	Ok(())
+}
+
+pub(crate) fn msi_string(row: &msi::Row, column: &str) -> std::io::Result<String> {
+	row[column]
+		.as_str()
+		.map(|value| value.to_owned())
+		.ok_or_else(|| {
+			std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				format!("expected a string in the {} column", column),
+			)
+		})
+}
+
+pub(crate) fn msi_directory_path(
+	directories: &HashMap<String, (Option<String>, String)>,
+	directory: &str,
+) -> PathBuf {
+	let mut names = Vec::new();
+	let mut directory = Some(directory);
+	while let Some(id) = directory {
+		let (parent, name) = match directories.get(id) {
+			Some(entry) => entry,
+			None => break,
+		};
+		// Match the names msiextract used for the standard folders, so the layout does not change.
+		let name = match id {
+			"TARGETDIR" => ".",
+			"ProgramFilesFolder" | "ProgramFiles64Folder" => "Program Files",
+			"CommonFilesFolder" | "CommonFiles64Folder" => "Program Files/Common Files",
+			"WindowsFolder" => "Windows",
+			"SystemFolder" | "System64Folder" => "Windows/System32",
+			_ => name,
+		};
+		if name != "." {
+			names.push(name);
+		}
+		directory = parent.as_deref().filter(|parent| *parent != id);
+	}
+	names.iter().rev().collect()
+}
+
+// Whether the name looks like an 8.3 name with a numeric tail, such as `WINDOW~1.H`,
+// which older MSIs use when they omit the long name.
+fn is_short_name(name: &str) -> bool {
+	let (stem, extension) = match name.rsplit_once('.') {
+		Some((stem, extension)) => (stem, extension),
+		None => (name, ""),
+	};
+	match stem.rsplit_once('~') {
+		Some((prefix, tail)) => {
+			stem.len() <= 8
+				&& extension.len() <= 3
+				&& !prefix.is_empty()
+				&& !tail.is_empty()
+				&& tail.bytes().all(|byte| byte.is_ascii_digit())
+		}
+		None => false,
+	}
+}
+
+// Whether the short name is one Windows could generate for the long name, which keeps
+// the start of the stem without spaces or dots, and the start of the extension, in
+// uppercase.
+fn abbreviates(short_name: &str, long_name: &str) -> bool {
+	let split = |name: &str| match name.rsplit_once('.') {
+		Some((stem, extension)) => (stem.to_owned(), extension.to_owned()),
+		None => (name.to_owned(), String::new()),
+	};
+	let (short_stem, short_extension) = split(short_name);
+	let (long_stem, long_extension) = split(long_name);
+	let prefix = match short_stem.rsplit_once('~') {
+		Some((prefix, _)) => prefix.to_ascii_uppercase(),
+		None => return false,
+	};
+	let long_stem = long_stem
+		.chars()
+		.filter(|char| !matches!(char, ' ' | '.'))
+		.map(|char| match char {
+			'+' | ',' | ';' | '=' | '[' | ']' => '_',
+			char => char.to_ascii_uppercase(),
+		})
+		.collect::<String>();
+	let long_extension = long_extension
+		.chars()
+		.take(3)
+		.collect::<String>()
+		.to_ascii_uppercase();
+	long_stem.starts_with(&prefix) && long_extension == short_extension.to_ascii_uppercase()
+}
+
+// MSI names have the form `target:source`, where each part is either `long` or `short|long`.
+pub(crate) fn msi_long_name(name: &str) -> String {
+	let target = name.split(':').next().unwrap();
+	target.rsplit('|').next().unwrap().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{abbreviates, copy_until, extract_msi, merge_staging_dir, Deadline};
+	use std::{collections::HashMap, time::Duration};
+
+	#[test]
+	fn test_copy_until() {
+		let bytes = vec![7; 300_000];
+		let mut copied = Vec::new();
+		copy_until(&mut bytes.as_slice(), &mut copied, Deadline::after(None)).unwrap();
+		assert_eq!(copied, bytes);
+		let mut copied = Vec::new();
+		let deadline = Deadline::after(Some(Duration::MAX));
+		assert!(!deadline.expired());
+		copy_until(&mut bytes.as_slice(), &mut copied, deadline).unwrap();
+		assert_eq!(copied, bytes);
+		let mut copied = Vec::new();
+		let error = copy_until(
+			&mut bytes.as_slice(),
+			&mut copied,
+			Deadline::after(Some(Duration::ZERO)),
+		)
+		.unwrap_err();
+		assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+		assert!(Deadline::caused(&error));
+		assert!(copied.is_empty());
+		let error = std::io::Error::new(std::io::ErrorKind::TimedOut, "the read timed out");
+		assert!(!Deadline::caused(&error));
+	}
+
+	#[test]
+	fn test_extract_msi_invalid_path() {
+		use msi::{Column, Insert, Package, PackageType, Value};
+		let string = |value: &str| Value::Str(value.to_owned());
+		let tempdir = tempfile::tempdir().unwrap();
+		let msi_path = tempdir.path().join("test.msi");
+		let file = std::fs::OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create_new(true)
+			.open(&msi_path)
+			.unwrap();
+		let mut package = Package::create(PackageType::Installer, file).unwrap();
+		package
+			.create_table(
+				"Directory",
+				vec![
+					Column::build("Directory").primary_key().id_string(72),
+					Column::build("Directory_Parent").nullable().id_string(72),
+					Column::build("DefaultDir").string(255),
+				],
+			)
+			.unwrap();
+		package
+			.create_table(
+				"Component",
+				vec![
+					Column::build("Component").primary_key().id_string(72),
+					Column::build("Directory_").id_string(72),
+				],
+			)
+			.unwrap();
+		package
+			.create_table(
+				"File",
+				vec![
+					Column::build("File").primary_key().id_string(72),
+					Column::build("Component_").id_string(72),
+					Column::build("FileName").string(255),
+				],
+			)
+			.unwrap();
+		package
+			.insert_rows(Insert::into("Directory").rows(vec![
+				vec![string("TARGETDIR"), Value::Null, string("SourceDir")],
+				vec![string("UP"), string("TARGETDIR"), string("..")],
+			]))
+			.unwrap();
+		package
+			.insert_rows(
+				Insert::into("Component").rows(vec![vec![string("component"), string("UP")]]),
+			)
+			.unwrap();
+		package
+			.insert_rows(Insert::into("File").rows(vec![vec![
+				string("file"),
+				string("component"),
+				string("escaped.txt"),
+			]]))
+			.unwrap();
+		drop(package);
+		let output_path = tempdir.path().join("output");
+		let error = extract_msi(&msi_path, &output_path, &[], Deadline::after(None)).unwrap_err();
+		assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+		assert!(error.to_string().contains("invalid path"));
+		assert!(!tempdir.path().join("escaped.txt").exists());
+	}
+
+	#[test]
+	fn test_abbreviates() {
+		assert!(abbreviates("WINDOW~1.H", "windows.h"));
+		assert!(abbreviates("KERNEL~10.LIB", "kernel32.lib"));
+		assert!(abbreviates("MYPROG~1.HTM", "my prog.html"));
+		assert!(!abbreviates("WINDOW~1.H", "winbase.h"));
+		assert!(!abbreviates("WINDOW~1.H", "windows.hpp"));
+		assert!(!abbreviates("windows.h", "windows.h"));
+	}
+
+	#[test]
+	fn test_merge_staging_dir_case_duplicates() {
+		let tempdir = tempfile::tempdir().unwrap();
+		let output_path = tempdir.path().join("output");
+		std::fs::create_dir(&output_path).unwrap();
+		let mut merged_paths = HashMap::new();
+		for (staging, name) in [("a", "Lib/x64/foo.lib"), ("b", "Lib/x64/FOO.LIB")] {
+			let staging_path = tempdir.path().join(staging);
+			let path = staging_path.join(name);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(&path, staging).unwrap();
+			let files = merge_staging_dir(&staging_path, &output_path, &mut merged_paths).unwrap();
+			assert_eq!(files.len(), usize::from(staging == "a"));
+		}
+		assert_eq!(
+			std::fs::read_to_string(output_path.join("Lib/x64/foo.lib")).unwrap(),
+			"a"
+		);
+		assert!(!output_path.join("Lib/x64/FOO.LIB").exists());
+	}
+}
@@ -0,0 +1,424 @@
+use super::{hash_file, Resolution, Stage};
+use crate::{
+	limits::RateLimiter,
+	progress::{BatchedProgress, DownloadState, Progress, ProgressSink},
+	Cache, Error, HttpOptions, Package, Payload, Result,
+};
+use futures::StreamExt;
+use std::{
+	collections::{HashMap, HashSet},
+	path::Path,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+use tokio::{
+	io::AsyncWriteExt,
+	sync::{OwnedSemaphorePermit, Semaphore},
+};
+use url::Url;
+
+// Downloads every payload missing from the cache, verifying each one before it is added.
+// Payloads that were already cached are not hashed here, but are listed in the output so
+// the verify stage can check them.
+pub struct Fetch {
+	pub cache: Cache,
+	pub progress: Arc<dyn ProgressSink>,
+	pub retry: Retry,
+	pub jobs: usize,
+	// The number of downloads from each host at once, or zero for no limit.
+	pub per_host_connections: usize,
+	// The number of bytes downloaded each second across all downloads, or none for no limit.
+	pub download_rate: Option<u64>,
+	pub http: HttpOptions,
+	// Fail instead of downloading when payloads are missing from the cache.
+	pub offline: bool,
+}
+
+// Failed downloads are retried after a delay that doubles with each attempt, resuming
+// from the bytes already written.
+#[derive(Clone, Debug)]
+pub struct Retry {
+	pub attempts: u32,
+	pub initial_delay: Duration,
+}
+
+impl Default for Retry {
+	fn default() -> Self {
+		Retry {
+			attempts: 5,
+			initial_delay: Duration::from_secs(1),
+		}
+	}
+}
+
+pub struct Fetched {
+	pub packages: Vec<Package>,
+	pub cached: Vec<[u8; 32]>,
+}
+
+impl From<Vec<Package>> for Fetched {
+	fn from(packages: Vec<Package>) -> Self {
+		let cached = packages
+			.iter()
+			.flat_map(|package| package.payloads.iter())
+			.map(|payload| payload.sha256)
+			.collect();
+		Fetched { packages, cached }
+	}
+}
+
+impl Stage<Vec<Package>> for Fetch {
+	type Output = Fetched;
+
+	fn run(&self, packages: Vec<Package>) -> Result<Fetched> {
+		tokio::runtime::Runtime::new()?.block_on(self.fetch(packages))
+	}
+}
+
+impl Fetch {
+	// Run the stage on the caller's runtime.
+	pub async fn fetch(&self, packages: Vec<Package>) -> Result<Fetched> {
+		self.cache.create()?;
+		let mut cached = Vec::new();
+		let mut missing = Vec::new();
+		let mut seen = HashSet::new();
+		for payload in packages.iter().flat_map(|package| package.payloads.iter()) {
+			if !seen.insert(payload.sha256) {
+				continue;
+			}
+			match payload_state(&self.cache, payload)? {
+				PayloadState::Cached => cached.push(payload.sha256),
+				PayloadState::Stale => {
+					std::fs::remove_file(self.cache.payload_path(payload))?;
+					missing.push(payload.clone());
+				}
+				PayloadState::Missing { .. } => missing.push(payload.clone()),
+			}
+		}
+		if self.offline && !missing.is_empty() {
+			for payload in missing.iter() {
+				tracing::error!("payload {} is missing from the cache", payload.file_name);
+			}
+			return Err(Error::MissingCachedPayloads {
+				count: missing.len(),
+			});
+		}
+		// Download the missing payloads.
+		let total_size = missing.iter().map(|payload| payload.size).sum();
+		let progress_bar = Progress::new(&self.progress, "download", total_size);
+		let connections = Connections {
+			http: &self.http,
+			client: self.http.client()?,
+			hosts: HostConnections::new(self.per_host_connections),
+			rate_limiter: RateLimiter::new(self.download_rate),
+		};
+		// Bound the number of simultaneous connections so large selections are not
+		// throttled by the server.
+		let downloads = futures::stream::iter(missing.into_iter().map(|payload| {
+			download_payload(
+				&connections,
+				&self.cache,
+				payload,
+				&self.retry,
+				&progress_bar,
+			)
+		}))
+		.buffer_unordered(self.jobs.max(1))
+		.collect::<Vec<_>>();
+		let results = downloads.await;
+		progress_bar.finish();
+		results.into_iter().collect::<Result<()>>()?;
+		Ok(Fetched { packages, cached })
+	}
+}
+
+// What downloading a resolution would fetch, so callers can tell users how much needs to
+// be downloaded before starting.
+#[derive(Debug, Default)]
+pub struct Prefetch {
+	// Payloads that are not in the cache, including ones whose download was interrupted.
+	pub missing: Vec<Payload>,
+	// Payloads in the cache whose size does not match the manifest, which will be downloaded again.
+	pub stale: Vec<Payload>,
+	pub cached: Vec<Payload>,
+	// The number of bytes left to download, not counting bytes already written by
+	// interrupted downloads.
+	pub download_size: u64,
+}
+
+pub fn prefetch(resolution: &Resolution, cache: &Cache) -> Result<Prefetch> {
+	prefetch_packages(&resolution.packages, cache)
+}
+
+pub fn prefetch_packages(packages: &[Package], cache: &Cache) -> Result<Prefetch> {
+	cache.migrate()?;
+	let mut prefetch = Prefetch::default();
+	let mut seen = HashSet::new();
+	for payload in packages.iter().flat_map(|package| package.payloads.iter()) {
+		if !seen.insert(payload.sha256) {
+			continue;
+		}
+		match payload_state(cache, payload)? {
+			PayloadState::Cached => prefetch.cached.push(payload.clone()),
+			PayloadState::Stale => {
+				prefetch.download_size += payload.size;
+				prefetch.stale.push(payload.clone());
+			}
+			PayloadState::Missing { partial_size } => {
+				prefetch.download_size += payload.size.saturating_sub(partial_size);
+				prefetch.missing.push(payload.clone());
+			}
+		}
+	}
+	Ok(prefetch)
+}
+
+enum PayloadState {
+	Cached,
+	Stale,
+	Missing { partial_size: u64 },
+}
+
+// Only the size is checked here, the verify stage checks the hash.
+fn payload_state(cache: &Cache, payload: &Payload) -> Result<PayloadState> {
+	let payload_cache_path = cache.payload_path(payload);
+	let file_size = |path: &Path| match std::fs::metadata(path) {
+		Ok(metadata) => Ok(Some(metadata.len())),
+		Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(error) => Err(error),
+	};
+	let state = match file_size(&payload_cache_path)? {
+		Some(size) if size == payload.size => PayloadState::Cached,
+		Some(_) => PayloadState::Stale,
+		None => PayloadState::Missing {
+			partial_size: file_size(&payload_cache_path.with_extension("partial"))?.unwrap_or(0),
+		},
+	};
+	Ok(state)
+}
+
+// Payloads are downloaded to `<hash>.partial` and only renamed into place once their hash
+// matches, so an interrupted download never leaves a corrupt payload in the cache. A
+// download that does not match is retried from each of the payload's other URLs in turn,
+// and the URL that matched is recorded in the cache.
+async fn download_payload(
+	connections: &Connections<'_>,
+	cache: &Cache,
+	payload: Payload,
+	retry: &Retry,
+	progress_bar: &Progress,
+) -> Result<()> {
+	let mut progress = progress_bar.batched(&payload);
+	let result = try_download_payload(connections, cache, payload, retry, &mut progress).await;
+	progress.flush();
+	match result {
+		Ok(()) => progress.report(DownloadState::Done),
+		Err(_) => progress.report(DownloadState::Failed),
+	}
+	result
+}
+
+async fn try_download_payload(
+	connections: &Connections<'_>,
+	cache: &Cache,
+	payload: Payload,
+	retry: &Retry,
+	progress: &mut BatchedProgress,
+) -> Result<()> {
+	let payload_cache_path = cache.payload_path(&payload);
+	tokio::fs::create_dir_all(payload_cache_path.parent().unwrap()).await?;
+	let partial_path = payload_cache_path.with_extension("partial");
+	let mut reported = 0;
+	let urls = connections.http.payload_urls(&payload);
+	'urls: for (index, url) in urls.iter().enumerate() {
+		let next_url = urls.get(index + 1);
+		let mut attempt = 0;
+		tracing::debug!("downloading {} from {}", payload.file_name, url);
+		loop {
+			let permit = connections.hosts.acquire(url).await;
+			let result = download_partial(
+				connections,
+				url,
+				&payload,
+				&partial_path,
+				&mut reported,
+				progress,
+			)
+			.await;
+			drop(permit);
+			match result {
+				Ok(()) => break,
+				Err(error) if attempt < retry.attempts && is_retryable(&error) => {
+					tracing::debug!(
+						"failed to download {} from {}: {}, retrying",
+						payload.file_name,
+						url,
+						error
+					);
+					progress.report(DownloadState::Retrying);
+					let delay = retry.initial_delay * 2u32.pow(attempt.min(6));
+					tokio::time::sleep(delay).await;
+					attempt += 1;
+				}
+				// A URL that keeps failing is skipped for the next one, so the alternate
+				// mirrors are tried whenever the primary is down.
+				Err(error) if next_url.is_some() => {
+					tokio::fs::remove_file(&partial_path).await.ok();
+					progress.report(DownloadState::Retrying);
+					tracing::warn!(
+						"failed to download {} from {}: {}, retrying from {}",
+						payload.file_name,
+						url,
+						error,
+						next_url.unwrap()
+					);
+					continue 'urls;
+				}
+				Err(error) => return Err(error),
+			}
+		}
+		// Hashing a large payload takes long enough to stall the other downloads on this thread.
+		let sha256 = {
+			let partial_path = partial_path.clone();
+			match tokio::task::spawn_blocking(move || hash_file(&partial_path)).await {
+				Ok(sha256) => sha256?,
+				Err(error) => std::panic::resume_unwind(error.into_panic()),
+			}
+		};
+		if sha256 == payload.sha256 {
+			tokio::fs::rename(&partial_path, &payload_cache_path).await?;
+			cache.record_source(&payload, url)?;
+			return Ok(());
+		}
+		// Do not leave the corrupt payload in the cache.
+		tokio::fs::remove_file(&partial_path).await?;
+		if let Some(next_url) = next_url {
+			progress.report(DownloadState::Retrying);
+			tracing::warn!(
+				"{} from {} does not match its sha256, retrying from {}",
+				payload.file_name,
+				url,
+				next_url
+			);
+		}
+	}
+	Err(Error::HashMismatch { url: payload.url })
+}
+
+// What the downloads share, the client and the limits on how much they use the network.
+struct Connections<'a> {
+	http: &'a HttpOptions,
+	client: reqwest::Client,
+	hosts: HostConnections,
+	rate_limiter: RateLimiter,
+}
+
+// Bounds the downloads from each host, including the mirrors and fallbacks.
+struct HostConnections {
+	limit: usize,
+	semaphores: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostConnections {
+	fn new(limit: usize) -> HostConnections {
+		HostConnections {
+			limit,
+			semaphores: Default::default(),
+		}
+	}
+
+	async fn acquire(&self, url: &Url) -> Option<OwnedSemaphorePermit> {
+		if self.limit == 0 {
+			return None;
+		}
+		let host = url.host_str().unwrap_or_default().to_owned();
+		let semaphore = self
+			.semaphores
+			.lock()
+			.unwrap()
+			.entry(host)
+			.or_insert_with(|| Arc::new(Semaphore::new(self.limit)))
+			.clone();
+		semaphore.acquire_owned().await.ok()
+	}
+}
+
+// Download the rest of the payload into the partial file, asking the server for only the
+// bytes that are missing.
+async fn download_partial(
+	connections: &Connections<'_>,
+	url: &Url,
+	payload: &Payload,
+	partial_path: &Path,
+	reported: &mut u64,
+	progress: &mut BatchedProgress,
+) -> Result<()> {
+	let mut position = match tokio::fs::metadata(partial_path).await {
+		Ok(metadata) => metadata.len(),
+		Err(error) if error.kind() == std::io::ErrorKind::NotFound => 0,
+		Err(error) => return Err(error.into()),
+	};
+	if position >= payload.size {
+		return Ok(());
+	}
+	let mut request = connections.http.get(&connections.client, url.clone());
+	if position > 0 {
+		request = request.header(reqwest::header::RANGE, format!("bytes={}-", position));
+	}
+	let response = request.send().await?.error_for_status()?;
+	// Servers that ignore the range send the whole payload, so start over.
+	if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+		position = 0;
+	}
+	let mut file = tokio::fs::OpenOptions::new()
+		.create(true)
+		.write(true)
+		.append(position > 0)
+		.truncate(position == 0)
+		.open(partial_path)
+		.await?;
+	let mut stream = response.bytes_stream();
+	while let Some(chunk) = stream.next().await {
+		let chunk = chunk?;
+		file.write_all(&chunk).await?;
+		connections.rate_limiter.take(chunk.len() as u64).await;
+		position += chunk.len() as u64;
+		if position > *reported {
+			progress.inc(position - *reported);
+			*reported = position;
+		}
+	}
+	file.flush().await?;
+	Ok(())
+}
+
+// Network errors and server errors are usually transient, while other client errors such
+// as a missing payload are not.
+fn is_retryable(error: &Error) -> bool {
+	match error {
+		Error::Io(_) => true,
+		Error::Network(error) => match error.status() {
+			Some(status) => {
+				status.is_server_error()
+					|| status == reqwest::StatusCode::REQUEST_TIMEOUT
+					|| status == reqwest::StatusCode::TOO_MANY_REQUESTS
+			}
+			None => true,
+		},
+		_ => false,
+	}
+}
+
+// Payload types are file extensions such as `msi` or `*.cab`, matched case-insensitively.
+// An empty list matches every payload.
+pub fn matches_payload_types(payload: &Payload, payload_types: &[String]) -> bool {
+	let file_name = payload.file_name.to_ascii_lowercase();
+	payload_types.is_empty()
+		|| payload_types.iter().any(|payload_type| {
+			let extension = payload_type
+				.trim_start_matches('*')
+				.trim_start_matches('.')
+				.to_ascii_lowercase();
+			file_name.ends_with(&format!(".{}", extension))
+		})
+}
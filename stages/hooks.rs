@@ -0,0 +1,137 @@
+use super::Stage;
+use crate::{DependencyChip, Result};
+use duct::cmd;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// A fixup run on the output directory after extraction and postprocessing.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Hook {
+	// Lowercase the name of every file and directory.
+	CaseNormalize,
+	// Write a clang/lld virtual file system overlay of the tree to `vfs-overlay.yaml`.
+	VfsOverlay,
+	// Remove the directories for every architecture except these.
+	PruneArch(Vec<DependencyChip>),
+	// Run a program with its arguments, followed by the output path.
+	Exec(Vec<String>),
+}
+
+impl std::str::FromStr for Hook {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		if let Some(arches) = value.strip_prefix("prune-arch:") {
+			let chips = arches
+				.split(',')
+				.map(|arch| arch.parse())
+				.collect::<Result<Vec<_>, _>>()?;
+			return Ok(Hook::PruneArch(chips));
+		}
+		if let Some(command) = value.strip_prefix("exec:") {
+			let command = command
+				.split_whitespace()
+				.map(|part| part.to_owned())
+				.collect::<Vec<_>>();
+			if command.is_empty() {
+				return Err("invalid hook \"exec:\", expected a command".to_owned());
+			}
+			return Ok(Hook::Exec(command));
+		}
+		match value {
+			"case-normalize" => Ok(Hook::CaseNormalize),
+			"vfs-overlay" => Ok(Hook::VfsOverlay),
+			_ => Err(format!(
+				"invalid hook \"{}\", expected case-normalize, vfs-overlay, prune-arch:<arch>[,<arch>...], or exec:<command>",
+				value
+			)),
+		}
+	}
+}
+
+// Runs the hooks in order. Programs are passed the path the tree is in, which is not yet
+// the install path when the tree is swapped into place after the hooks succeed.
+pub struct Hooks {
+	pub hooks: Vec<Hook>,
+	// Where the tree will be moved after the hooks, which the paths written into it refer
+	// to. Without one they refer to where it is.
+	pub install_path: Option<PathBuf>,
+}
+
+impl Stage<PathBuf> for Hooks {
+	type Output = PathBuf;
+
+	fn run(&self, output_path: PathBuf) -> Result<PathBuf> {
+		for hook in self.hooks.iter() {
+			match hook {
+				Hook::CaseNormalize => case_normalize(&output_path)?,
+				Hook::VfsOverlay => crate::vfs::write_vfs_overlay(
+					&output_path,
+					self.install_path.as_deref().unwrap_or(&output_path),
+					&output_path.join("vfs-overlay.yaml"),
+				)?,
+				Hook::PruneArch(chips) => prune_arch(&output_path, chips)?,
+				Hook::Exec(command) => {
+					let args = command[1..]
+						.iter()
+						.map(std::ffi::OsString::from)
+						.chain(std::iter::once(output_path.clone().into_os_string()));
+					cmd(&command[0], args).run()?;
+				}
+			}
+		}
+		Ok(output_path)
+	}
+}
+
+// Rename the deepest paths first so their parents are still at their original paths.
+// Names whose lowercase variant already exists are left alone.
+fn case_normalize(output_path: &Path) -> Result<()> {
+	for entry in WalkDir::new(output_path).min_depth(1).contents_first(true) {
+		let entry = entry?;
+		let name = entry.file_name();
+		let lowercase_name = name.to_ascii_lowercase();
+		if lowercase_name == name {
+			continue;
+		}
+		let lowercase_path = entry.path().parent().unwrap().join(lowercase_name);
+		if !lowercase_path.exists() {
+			std::fs::rename(entry.path(), lowercase_path)?;
+		}
+	}
+	Ok(())
+}
+
+// The directory names of each architecture, in the layouts of both the MSVC and Windows SDK trees.
+const ARCHES: &[(DependencyChip, &str)] = &[
+	(DependencyChip::X86, "x86"),
+	(DependencyChip::X64, "x64"),
+	(DependencyChip::X64, "amd64"),
+	(DependencyChip::Arm, "arm"),
+	(DependencyChip::Arm64, "arm64"),
+];
+
+fn prune_arch(output_path: &Path, chips: &[DependencyChip]) -> Result<()> {
+	let mut paths = Vec::new();
+	let mut walker = WalkDir::new(output_path).min_depth(1).into_iter();
+	while let Some(entry) = walker.next() {
+		let entry = entry?;
+		if is_pruned(&entry, chips) {
+			paths.push(entry.into_path());
+			walker.skip_current_dir();
+		}
+	}
+	for path in paths {
+		std::fs::remove_dir_all(path)?;
+	}
+	Ok(())
+}
+
+// Pruning `x64` also removes the `amd64` directories, while host directories like
+// `Hostx64` are kept.
+fn is_pruned(entry: &walkdir::DirEntry, chips: &[DependencyChip]) -> bool {
+	let name = entry.file_name().to_string_lossy();
+	entry.file_type().is_dir()
+		&& ARCHES
+			.iter()
+			.any(|(chip, arch)| name.eq_ignore_ascii_case(arch) && !chips.contains(chip))
+}
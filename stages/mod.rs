@@ -0,0 +1,33 @@
+pub mod dedup;
+pub mod extract;
+pub mod fetch;
+pub mod hooks;
+pub mod postprocess;
+pub mod resolve;
+pub mod resource_tools;
+pub mod signature;
+pub mod splat;
+pub mod tool_runtime;
+pub mod verify;
+
+pub use self::{
+	dedup::{Dedup, DedupMode},
+	extract::{Extract, Extracted, VsixSubtree},
+	fetch::{matches_payload_types, prefetch, prefetch_packages, Fetch, Fetched, Prefetch, Retry},
+	hooks::{Hook, Hooks},
+	postprocess::{CaseVariants, Postprocess},
+	resolve::{selection_drift, Decision, Resolution, Resolve, SkipReason},
+	resource_tools::ResourceTools,
+	signature::VerifySignatures,
+	splat::{Layout, Splat},
+	tool_runtime::ToolRuntime,
+	verify::{hash_file, verify_payload, Verify},
+};
+
+// A step of the pipeline. The top level functions chain resolve, fetch, verify, extract,
+// and postprocess in that order, but each stage only depends on its input, so embedders
+// can run any of them on their own.
+pub trait Stage<I> {
+	type Output;
+	fn run(&self, input: I) -> crate::Result<Self::Output>;
+}
@@ -0,0 +1,320 @@
+use super::Stage;
+use crate::Result;
+use duct::cmd;
+use once_cell::sync::Lazy;
+use regex::bytes::Regex;
+use std::{
+	collections::{BTreeSet, HashMap, HashSet},
+	path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+// Fixes up an extracted tree for case sensitive filesystems, then normalizes its
+// permissions and ownership.
+#[derive(Debug, Default)]
+pub struct Postprocess {
+	pub normalize_permissions: bool,
+	pub umask: Option<u32>,
+	pub owner: Option<String>,
+	pub case_variants: CaseVariants,
+	// Source trees and build files to scan for additional references to headers and
+	// import libraries.
+	pub scan_paths: Vec<PathBuf>,
+	// Generate scripts that run the extracted compiler, linker, librarian, and resource
+	// compiler under Wine.
+	pub wine_wrap: bool,
+	// Where the tree will be moved after postprocessing, which the paths written into it
+	// refer to. Without one they refer to where it is.
+	pub install_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CaseVariants {
+	Copy,
+	// Link each variant to the canonical file, falling back to copying on filesystems
+	// without symlinks.
+	Symlink,
+}
+
+impl Default for CaseVariants {
+	fn default() -> Self {
+		CaseVariants::Copy
+	}
+}
+
+impl std::str::FromStr for CaseVariants {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"copy" => Ok(CaseVariants::Copy),
+			"symlink" => Ok(CaseVariants::Symlink),
+			_ => Err(format!(
+				"invalid case variants \"{}\", expected copy or symlink",
+				value
+			)),
+		}
+	}
+}
+
+impl Stage<PathBuf> for Postprocess {
+	type Output = PathBuf;
+
+	fn run(&self, output_path: PathBuf) -> Result<PathBuf> {
+		// Lowercase all header and import library names. Names that differ only in case
+		// would overwrite each other, so the one that is already lowercase is kept, or
+		// else the first in byte order, and the others are removed. References to them
+		// get case variants of the kept file below.
+		let header_paths = || find_paths(&output_path, &["h"]);
+		let import_library_paths = || find_paths(&output_path, &["lib"]);
+		let mut paths = header_paths()?;
+		paths.extend(import_library_paths()?);
+		paths.sort();
+		let mut existing_paths = paths.iter().cloned().collect::<HashSet<_>>();
+		for path in paths.iter() {
+			let name = path.file_name().unwrap();
+			let lowercase_name = name.to_ascii_lowercase();
+			if lowercase_name == name {
+				continue;
+			}
+			let lowercase_path = path.parent().unwrap().join(lowercase_name);
+			if existing_paths.contains(&lowercase_path) {
+				tracing::warn!(
+					"{} and {} differ only in case, keeping {}",
+					lowercase_path.display(),
+					path.display(),
+					lowercase_path.display()
+				);
+				std::fs::remove_file(path)?;
+			} else {
+				std::fs::rename(path, &lowercase_path)?;
+				existing_paths.insert(lowercase_path);
+			}
+		}
+
+		// Add a variant of each header and import library for every differently cased
+		// reference to it, found in the headers, in the linker directives of the import
+		// libraries, and in the scan paths.
+		let canonical_paths = |paths: Vec<PathBuf>| {
+			let mut canonical_paths = HashMap::new();
+			for path in paths {
+				let lowercase_file_name =
+					path.file_name().unwrap().to_string_lossy().to_lowercase();
+				canonical_paths
+					.entry(lowercase_file_name)
+					.or_insert_with(HashSet::new)
+					.insert(path);
+			}
+			canonical_paths
+		};
+		let headers = canonical_paths(header_paths()?);
+		let import_libraries = canonical_paths(import_library_paths()?);
+		let mut references = References::default();
+		for header_path in header_paths()? {
+			references.scan(&std::fs::read(&header_path)?);
+		}
+		for import_library_path in import_library_paths()? {
+			references.scan_linker_directives(&std::fs::read(&import_library_path)?);
+		}
+		for scan_path in self.scan_paths.iter() {
+			for entry in WalkDir::new(scan_path) {
+				let entry = entry?;
+				if entry.file_type().is_file() {
+					references.scan(&std::fs::read(entry.path())?);
+				}
+			}
+		}
+		for (names, canonical_paths) in [
+			(&references.headers, &headers),
+			(&references.libraries, &import_libraries),
+		] {
+			for name in names.iter() {
+				let paths = match canonical_paths.get(&name.to_lowercase()) {
+					Some(paths) => paths,
+					None => continue,
+				};
+				for path in paths {
+					let variant_path = path.parent().unwrap().join(name);
+					if !variant_path.exists() {
+						create_case_variant(path, &variant_path, self.case_variants)?;
+					}
+				}
+			}
+		}
+
+		if self.wine_wrap {
+			crate::env::generate_wine_wrappers(
+				&output_path,
+				self.install_path.as_deref().unwrap_or(&output_path),
+			)?;
+		}
+
+		// Normalize permissions and ownership.
+		if self.normalize_permissions || self.umask.is_some() {
+			normalize_permissions(&output_path, self.normalize_permissions, self.umask)?;
+		}
+		if let Some(owner) = self.owner.as_ref() {
+			cmd!("chown", "-R", owner, &output_path).run()?;
+		}
+
+		// // Lowercase all includes in headers.
+		// let include_regex = regex::bytes::Regex::new(r#"#include(\s+)(["<])([^">]+)([">])"#).unwrap();
+		// for header_path in header_paths() {
+		// 	let header_bytes = std::fs::read(&header_path).unwrap();
+		// 	let header_bytes = include_regex.replace_all(&header_bytes, |captures: &regex::bytes::Captures| {
+		// 		let mut replacement = b"#include".to_vec();
+		// 		replacement.extend(&captures[1]);
+		// 		replacement.extend(&captures[2]);
+		// 		let name = std::str::from_utf8(&captures[3]).unwrap().to_lowercase();
+		// 		replacement.extend(name.as_bytes());
+		// 		replacement.extend(&captures[4]);
+		// 		replacement
+		// 	});
+		// 	std::fs::write(&header_path, &header_bytes).unwrap();
+		// }
+
+		Ok(output_path)
+	}
+}
+
+static INCLUDE_REGEX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r#"#include(\s+)(["<])([^">]+)([">])"#).unwrap());
+static PRAGMA_REGEX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r#"#pragma\s+comment\s*\(\s*lib\s*,\s*"([^"]+)"\s*\)"#).unwrap());
+// Build files such as Makefiles name import libraries directly.
+static LIBRARY_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)\b[\w.-]+\.lib\b"#).unwrap());
+static DIRECTIVE_REGEX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r#"(?i)[-/]defaultlib:"?([^"\s\x00]+)"#).unwrap());
+
+// The names of the headers and import libraries a file references, with their original casing.
+#[derive(Default)]
+pub(crate) struct References {
+	pub(crate) headers: BTreeSet<String>,
+	pub(crate) libraries: BTreeSet<String>,
+}
+
+impl References {
+	pub(crate) fn scan(&mut self, bytes: &[u8]) {
+		for capture in INCLUDE_REGEX.captures_iter(bytes) {
+			if let Ok(name) = std::str::from_utf8(&capture[3]) {
+				let name = name.rsplit(|c| c == '/' || c == '\\').next().unwrap();
+				self.headers.insert(name.to_owned());
+			}
+		}
+		for capture in PRAGMA_REGEX.captures_iter(bytes) {
+			if let Ok(name) = std::str::from_utf8(&capture[1]) {
+				self.insert_library(name);
+			}
+		}
+		for found in LIBRARY_REGEX.find_iter(bytes) {
+			if let Ok(name) = std::str::from_utf8(found.as_bytes()) {
+				self.insert_library(name);
+			}
+		}
+	}
+
+	// Import libraries pull in other libraries with `/DEFAULTLIB` directives embedded in
+	// their objects.
+	fn scan_linker_directives(&mut self, bytes: &[u8]) {
+		for capture in DIRECTIVE_REGEX.captures_iter(bytes) {
+			if let Ok(name) = std::str::from_utf8(&capture[1]) {
+				self.insert_library(name);
+			}
+		}
+	}
+
+	fn insert_library(&mut self, name: &str) {
+		if name.to_lowercase().ends_with(".lib") {
+			self.libraries.insert(name.to_owned());
+		} else {
+			self.libraries.insert(format!("{}.lib", name));
+		}
+	}
+}
+
+fn create_case_variant(
+	canonical_path: &Path,
+	variant_path: &Path,
+	case_variants: CaseVariants,
+) -> Result<()> {
+	if case_variants == CaseVariants::Symlink {
+		#[cfg(unix)]
+		let linked = std::os::unix::fs::symlink(canonical_path.file_name().unwrap(), variant_path);
+		#[cfg(windows)]
+		let linked = std::fs::hard_link(canonical_path, variant_path);
+		#[cfg(not(any(unix, windows)))]
+		let linked = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::Unsupported));
+		if linked.is_ok() {
+			return Ok(());
+		}
+	}
+	std::fs::copy(canonical_path, variant_path)?;
+	Ok(())
+}
+
+// Extensions are compared case-insensitively, as some MSIs name files such as `FOO.LIB`
+// in uppercase.
+fn find_paths(root: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>> {
+	let mut paths = Vec::new();
+	for entry in WalkDir::new(root) {
+		let entry = entry?;
+		let extension = entry
+			.path()
+			.extension()
+			.and_then(|extension| extension.to_str());
+		if let Some(extension) = extension {
+			if extensions
+				.iter()
+				.any(|candidate| candidate.eq_ignore_ascii_case(extension))
+			{
+				paths.push(entry.path().to_owned());
+			}
+		}
+	}
+	Ok(paths)
+}
+
+// MSI extraction leaves arbitrary modes behind. Normalizing sets directories and
+// executables to 755 and everything else, including headers and libraries, to 644, and
+// the umask is then applied on top.
+#[cfg(unix)]
+fn normalize_permissions(output_path: &Path, normalize: bool, umask: Option<u32>) -> Result<()> {
+	use std::os::unix::fs::PermissionsExt;
+	for entry in WalkDir::new(output_path) {
+		let entry = entry?;
+		if entry.file_type().is_symlink() {
+			continue;
+		}
+		let metadata = entry.metadata()?;
+		let mode = if !normalize {
+			metadata.permissions().mode() & 0o7777
+		} else if entry.file_type().is_dir() {
+			0o755
+		} else if entry
+			.path()
+			.strip_prefix(output_path)
+			.unwrap()
+			.starts_with("wine")
+		{
+			// The Wine wrapper scripts.
+			0o755
+		} else {
+			let extension = entry
+				.path()
+				.extension()
+				.and_then(|extension| extension.to_str())
+				.map(|extension| extension.to_ascii_lowercase());
+			match extension.as_deref() {
+				Some("exe") | Some("dll") | Some("bat") | Some("cmd") | Some("sh") => 0o755,
+				_ => 0o644,
+			}
+		};
+		let mode = mode & !umask.unwrap_or(0);
+		std::fs::set_permissions(entry.path(), std::fs::Permissions::from_mode(mode))?;
+	}
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn normalize_permissions(_output_path: &Path, _normalize: bool, _umask: Option<u32>) -> Result<()> {
+	Ok(())
+}
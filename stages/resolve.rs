@@ -0,0 +1,916 @@
+use super::Stage;
+use crate::{DependencyChip, DependencyType, Error, Manifest, Package, PackageFilter, Result};
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, Default)]
+pub struct ChoosePackagesOptions {
+	pub with_asan: bool,
+	pub with_crt_source: bool,
+	// Do not add the DIA SDK and the C runtime redistributables that the compiler and
+	// linker need to run.
+	pub without_tool_runtime: bool,
+	// Select a Windows SDK for its resource compiler and MIDL if the selection does not
+	// already contain one.
+	pub with_resource_tools: bool,
+	// Only select packages and dependencies for these architectures. An empty list
+	// selects every architecture.
+	pub chips: Vec<DependencyChip>,
+	// Select packages for each of these architectures in one tree. Requested packages
+	// named for an architecture, such as `Microsoft.VC.<toolset>.CRT.x64.Desktop`, are
+	// also selected in their variants for the others, and the architectures are added to
+	// the chips.
+	pub arches: Vec<DependencyChip>,
+	// Only select localized packages for these languages, such as `en-US`. Language
+	// neutral packages are always selected, and an empty list selects every language.
+	pub languages: Vec<String>,
+	// Select the packages each target needs, and add their architectures to the chips.
+	pub targets: Vec<Target>,
+	// The Windows SDK and MSVC toolset versions for the targets, matched by prefix, such
+	// as `10.0.22621` and `14.38`. The newest ones are selected by default.
+	pub sdk_version: Option<String>,
+	pub crt_version: Option<String>,
+	// Also select the Spectre-mitigated C runtime libraries for the targets.
+	pub with_spectre: bool,
+	// Select every version a package id pattern matches, rather than only the newest.
+	pub all_versions: bool,
+}
+
+// A Rust target triple for the MSVC toolchain, such as `x86_64-pc-windows-msvc`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+	I686,
+	X86_64,
+	Aarch64,
+	Thumbv7a,
+}
+
+impl Target {
+	pub fn chip(&self) -> DependencyChip {
+		match self {
+			Target::I686 => DependencyChip::X86,
+			Target::X86_64 => DependencyChip::X64,
+			Target::Aarch64 => DependencyChip::Arm64,
+			Target::Thumbv7a => DependencyChip::Arm,
+		}
+	}
+}
+
+impl std::fmt::Display for Target {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let arch = match self {
+			Target::I686 => "i686",
+			Target::X86_64 => "x86_64",
+			Target::Aarch64 => "aarch64",
+			Target::Thumbv7a => "thumbv7a",
+		};
+		write!(f, "{}-pc-windows-msvc", arch)
+	}
+}
+
+impl std::str::FromStr for Target {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value.to_ascii_lowercase().as_str() {
+			"i686-pc-windows-msvc" => Ok(Target::I686),
+			"x86_64-pc-windows-msvc" => Ok(Target::X86_64),
+			"aarch64-pc-windows-msvc" => Ok(Target::Aarch64),
+			"thumbv7a-pc-windows-msvc" => Ok(Target::Thumbv7a),
+			_ => Err(format!(
+				"invalid target \"{}\", expected x86_64-pc-windows-msvc, i686-pc-windows-msvc, aarch64-pc-windows-msvc, or thumbv7a-pc-windows-msvc",
+				value
+			)),
+		}
+	}
+}
+
+pub struct Resolve {
+	pub package_ids: Vec<String>,
+	pub options: ChoosePackagesOptions,
+}
+
+pub struct Resolution {
+	pub packages: Vec<Package>,
+	pub warnings: Vec<String>,
+	// Why each package was selected and why each dependency that was not followed was
+	// skipped, in the order the resolver made the decisions.
+	pub decisions: Vec<Decision>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Decision {
+	Requested {
+		id: String,
+	},
+	Dependency {
+		id: String,
+		of: String,
+	},
+	// Added by an option such as `--with-asan` rather than by a dependency in the manifest.
+	Added {
+		id: String,
+		option: &'static str,
+	},
+	// A variant of a package for an architecture or language that was not selected.
+	Filtered {
+		id: String,
+		chip: Option<String>,
+		language: Option<String>,
+	},
+	Skipped {
+		id: String,
+		of: String,
+		reason: SkipReason,
+	},
+}
+
+#[derive(Clone, Debug)]
+pub enum SkipReason {
+	Optional,
+	Recommended,
+	Chip(DependencyChip),
+	AlreadySeen,
+}
+
+impl std::fmt::Display for Decision {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Decision::Requested { id } => write!(f, "+ {}: requested", id),
+			Decision::Dependency { id, of } => write!(f, "+ {}: dependency of {}", id, of),
+			Decision::Added { id, option } => write!(f, "+ {}: added by {}", id, option),
+			Decision::Filtered { id, chip, language } => {
+				write!(f, "- {}", id)?;
+				if let Some(chip) = chip {
+					write!(f, " (chip {})", chip)?;
+				}
+				if let Some(language) = language {
+					write!(f, " (language {})", language)?;
+				}
+				write!(f, ": not a selected architecture or language")
+			}
+			Decision::Skipped { id, of, reason } => match reason {
+				SkipReason::Optional => write!(f, "- {}: optional dependency of {}", id, of),
+				SkipReason::Recommended => {
+					write!(f, "- {}: recommended dependency of {}", id, of)
+				}
+				SkipReason::Chip(chip) => write!(
+					f,
+					"- {}: dependency of {} for chip {}, which is not selected",
+					id, of, chip
+				),
+				SkipReason::AlreadySeen => {
+					write!(f, "- {}: dependency of {}, already selected", id, of)
+				}
+			},
+		}
+	}
+}
+
+impl<'a> Stage<&'a Manifest> for Resolve {
+	type Output = Resolution;
+
+	fn run(&self, manifest: &'a Manifest) -> Result<Resolution> {
+		// Make sure all the requested packages exist, and replace the patterns with the
+		// ids they match.
+		let package_ids =
+			expand_package_ids(manifest, &self.package_ids, self.options.all_versions)?;
+		// Find the payloads for all recursive dependencies of the requested packages.
+		let mut resolver = Resolver {
+			manifest,
+			options: &self.options,
+			chips: self
+				.options
+				.chips
+				.iter()
+				.chain(self.options.arches.iter())
+				.copied()
+				.chain(self.options.targets.iter().map(Target::chip))
+				.collect(),
+			seen_package_ids: HashSet::new(),
+			packages: Vec::new(),
+			decisions: Vec::new(),
+		};
+		resolver.resolve(package_ids.clone(), None);
+		if !self.options.targets.is_empty() {
+			let package_ids = target_package_ids(manifest, &self.options)?;
+			resolver.resolve(package_ids, Some("--target"));
+		}
+		if !self.options.arches.is_empty() {
+			let package_ids = arch_package_ids(manifest, &package_ids, &self.options.arches);
+			resolver.resolve(package_ids, Some("--arch"));
+		}
+		// Add the optional toolset packages for the selected toolsets.
+		for (enabled, component, option) in [
+			(self.options.with_asan, "ASAN", "--with-asan"),
+			(
+				self.options.with_crt_source,
+				"CRT.Source",
+				"--with-crt-source",
+			),
+		] {
+			if enabled {
+				let package_ids =
+					toolset_package_ids(manifest, &resolver.packages, &resolver.chips, component);
+				resolver.resolve(package_ids, Some(option));
+			}
+		}
+		// Add the runtime dependencies of the selected compiler and linker packages,
+		// which are not listed as dependencies in the manifest.
+		if !self.options.without_tool_runtime {
+			let package_ids = tool_runtime_package_ids(manifest, &resolver.packages);
+			resolver.resolve(package_ids, Some("the selected tools"));
+		}
+		if self.options.with_resource_tools {
+			let package_ids = sdk_package_ids(manifest, &resolver.packages);
+			resolver.resolve(package_ids, Some("--with-resource-tools"));
+		}
+		let warnings = toolset_sdk_warnings(&resolver.packages);
+		Ok(Resolution {
+			packages: resolver.packages.into_iter().cloned().collect(),
+			warnings,
+			decisions: resolver.decisions,
+		})
+	}
+}
+
+struct Resolver<'a> {
+	manifest: &'a Manifest,
+	options: &'a ChoosePackagesOptions,
+	// The chips and arches options combined.
+	chips: Vec<DependencyChip>,
+	seen_package_ids: HashSet<String>,
+	packages: Vec<&'a Package>,
+	decisions: Vec<Decision>,
+}
+
+impl<'a> Resolver<'a> {
+	// Select the packages and their recursive dependencies. Packages are requested unless
+	// an option added them.
+	fn resolve(&mut self, package_ids: Vec<String>, option: Option<&'static str>) {
+		let options = self.options;
+		let mut package_id_queue = Vec::new();
+		for package_id in package_ids {
+			if self
+				.seen_package_ids
+				.insert(package_id.to_ascii_lowercase())
+			{
+				let decision = match option {
+					Some(option) => Decision::Added {
+						id: package_id.to_owned(),
+						option,
+					},
+					None => Decision::Requested {
+						id: package_id.to_owned(),
+					},
+				};
+				self.decisions.push(decision);
+				package_id_queue.push(package_id);
+			}
+		}
+		while let Some(package_id) = package_id_queue.pop() {
+			for package in self
+				.manifest
+				.packages
+				.iter()
+				.filter(|package| package.id.eq_ignore_ascii_case(&package_id))
+			{
+				let selected = matches_chips(&self.chips, package.chip.as_deref())
+					&& matches_chips(&self.chips, package.machine_arch.as_deref())
+					&& matches_languages(&options.languages, package.language.as_deref());
+				if !selected {
+					self.decisions.push(Decision::Filtered {
+						id: package.id.to_owned(),
+						chip: package
+							.chip
+							.clone()
+							.or_else(|| package.machine_arch.clone()),
+						language: package.language.clone(),
+					});
+					continue;
+				}
+				self.packages.push(package);
+				for (id, dependency) in package.dependencies.iter() {
+					let reason = match (&dependency.ty, dependency.chip) {
+						(Some(DependencyType::Optional), _) => Some(SkipReason::Optional),
+						(Some(DependencyType::Recommended), _) => Some(SkipReason::Recommended),
+						(None, Some(chip))
+							if !self.chips.is_empty() && !self.chips.contains(&chip) =>
+						{
+							Some(SkipReason::Chip(chip))
+						}
+						_ if self.seen_package_ids.contains(&id.to_ascii_lowercase()) => {
+							Some(SkipReason::AlreadySeen)
+						}
+						_ => None,
+					};
+					match reason {
+						Some(reason) => self.decisions.push(Decision::Skipped {
+							id: id.to_owned(),
+							of: package.id.to_owned(),
+							reason,
+						}),
+						None => {
+							self.decisions.push(Decision::Dependency {
+								id: id.to_owned(),
+								of: package.id.to_owned(),
+							});
+							package_id_queue.push(id.to_owned());
+							self.seen_package_ids.insert(id.to_ascii_lowercase());
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+// Packages without a chip, or with a chip such as `neutral` that is not an architecture,
+// are selected for every architecture.
+fn matches_chips(chips: &[DependencyChip], chip: Option<&str>) -> bool {
+	match chip.and_then(|chip| chip.parse::<DependencyChip>().ok()) {
+		Some(chip) => chips.is_empty() || chips.contains(&chip),
+		None => true,
+	}
+}
+
+fn matches_languages(languages: &[String], language: Option<&str>) -> bool {
+	match language {
+		Some(language) if !language.eq_ignore_ascii_case("neutral") => {
+			languages.is_empty()
+				|| languages
+					.iter()
+					.any(|requested| requested.eq_ignore_ascii_case(language))
+		}
+		_ => true,
+	}
+}
+
+// Whether the package may have been left out of a selection by its architecture or language.
+fn is_filterable(package: &Package) -> bool {
+	package
+		.chip
+		.iter()
+		.chain(package.machine_arch.iter())
+		.any(|chip| chip.parse::<DependencyChip>().is_ok())
+		|| package
+			.language
+			.as_deref()
+			.map(|language| !language.eq_ignore_ascii_case("neutral"))
+			.unwrap_or(false)
+}
+
+// Swap the architecture in each package id for each of the arches, keeping the variants
+// that exist in the manifest. Architectures appear as their own component of the id, or
+// as the target of a `Host<arch>.Target<arch>` tools package, whose host is left alone.
+fn arch_package_ids(
+	manifest: &Manifest,
+	package_ids: &[String],
+	arches: &[DependencyChip],
+) -> Vec<String> {
+	let is_arch = |component: &str| component.parse::<DependencyChip>().is_ok();
+	let mut package_ids_for_arches = Vec::new();
+	for package_id in package_ids {
+		let components = package_id.split('.').collect::<Vec<_>>();
+		for arch in arches {
+			let arch_package_id = components
+				.iter()
+				.map(|component| {
+					let lowercase_component = component.to_ascii_lowercase();
+					match lowercase_component.strip_prefix("target") {
+						Some(target) if is_arch(target) => format!("target{}", arch),
+						_ if is_arch(&lowercase_component) => arch.to_string(),
+						_ => lowercase_component,
+					}
+				})
+				.collect::<Vec<_>>()
+				.join(".");
+			if let Some(package) = manifest
+				.packages
+				.iter()
+				.find(|package| package.id.eq_ignore_ascii_case(&arch_package_id))
+			{
+				package_ids_for_arches.push(package.id.to_owned());
+			}
+		}
+	}
+	package_ids_for_arches
+}
+
+// A target needs the compiler and linker for its architecture, hosted on x64, the C
+// runtime libraries for desktop apps, the C runtime headers, and the Windows SDK headers
+// and import libraries.
+fn target_package_ids(manifest: &Manifest, options: &ChoosePackagesOptions) -> Result<Vec<String>> {
+	let toolset_regex =
+		regex::Regex::new(r"(?i)^Microsoft\.VC\.(\d+\.\d+\.\d+\.\d+)\.CRT\.Headers\.base$")
+			.unwrap();
+	let toolset = newest_version(
+		manifest
+			.packages
+			.iter()
+			.filter_map(|package| toolset_regex.captures(&package.id))
+			.map(|captures| captures[1].to_owned()),
+		options.crt_version.as_deref(),
+	)
+	.ok_or_else(|| Error::MissingVersion {
+		component: "MSVC toolset",
+		version: options.crt_version.clone(),
+	})?;
+	let sdk_regex = regex::Regex::new(r"(?i)^Win\d+SDK_(10\.0\.\d+)").unwrap();
+	let sdk = newest_version(
+		manifest
+			.packages
+			.iter()
+			.filter_map(|package| sdk_regex.captures(&package.id))
+			.map(|captures| captures[1].to_owned()),
+		options.sdk_version.as_deref(),
+	)
+	.ok_or_else(|| Error::MissingVersion {
+		component: "Windows SDK",
+		version: options.sdk_version.clone(),
+	})?;
+	let sdk_package_id = manifest
+		.packages
+		.iter()
+		.filter(|package| {
+			sdk_regex
+				.captures(&package.id)
+				.map(|captures| captures[1] == sdk)
+				.unwrap_or(false)
+		})
+		.map(|package| package.id.to_owned())
+		.next()
+		.unwrap();
+	let mut package_ids = vec![format!("Microsoft.VC.{}.CRT.Headers.base", toolset)];
+	for target in options.targets.iter() {
+		let arch = target.chip();
+		package_ids.push(format!(
+			"Microsoft.VC.{}.Tools.HostX64.Target{}.base",
+			toolset, arch
+		));
+		package_ids.push(format!(
+			"Microsoft.VC.{}.CRT.{}.Desktop.base",
+			toolset, arch
+		));
+		if options.with_spectre {
+			package_ids.push(format!(
+				"Microsoft.VC.{}.CRT.{}.Desktop.spectre.base",
+				toolset, arch
+			));
+		}
+	}
+	// Use the ids as the manifest spells them.
+	let mut package_ids = package_ids
+		.into_iter()
+		.map(|package_id| {
+			manifest
+				.packages
+				.iter()
+				.find(|package| package.id.eq_ignore_ascii_case(&package_id))
+				.map(|package| package.id.to_owned())
+				.ok_or(Error::MissingPackage {
+					id: package_id,
+					suggestions: Vec::new(),
+				})
+		})
+		.collect::<Result<Vec<_>>>()?;
+	package_ids.push(sdk_package_id);
+	Ok(package_ids)
+}
+
+// A requested id is a pattern when it has a glob wildcard, such as
+// `Microsoft.VC.14.*.CRT.Headers.base`, or is a regex between slashes.
+fn is_package_id_pattern(package_id: &str) -> bool {
+	package_id.contains(|c| c == '*' || c == '?')
+		|| (package_id.len() > 1 && package_id.starts_with('/') && package_id.ends_with('/'))
+}
+
+// The requested ids with each pattern replaced by the manifest ids it matches. Ids that
+// differ only in their dotted versions, such as the toolset in
+// `Microsoft.VC.<toolset>.CRT.Headers.base` or the build in `Win11SDK_<build>`, are
+// versions of one package, and only the newest of those is kept unless all versions are
+// requested.
+fn expand_package_ids(
+	manifest: &Manifest,
+	package_ids: &[String],
+	all_versions: bool,
+) -> Result<Vec<String>> {
+	let mut manifest_package_ids = Vec::new();
+	let mut seen = HashSet::new();
+	for package in manifest.packages.iter() {
+		if seen.insert(package.id.to_ascii_lowercase()) {
+			manifest_package_ids.push(package.id.as_str());
+		}
+	}
+	let version_regex = regex::Regex::new(r"\d+(\.\d+)+").unwrap();
+	let mut expanded_package_ids = Vec::new();
+	for package_id in package_ids {
+		if !is_package_id_pattern(package_id) {
+			if !seen.contains(&package_id.to_ascii_lowercase()) {
+				return Err(Error::MissingPackage {
+					id: package_id.to_owned(),
+					suggestions: similar_package_ids(&manifest_package_ids, package_id),
+				});
+			}
+			expanded_package_ids.push(package_id.to_owned());
+			continue;
+		}
+		let filter =
+			package_id
+				.parse::<PackageFilter>()
+				.map_err(|reason| Error::InvalidArgument {
+					argument: "package regex".to_owned(),
+					reason,
+				})?;
+		let matches = manifest_package_ids
+			.iter()
+			.filter(|id| filter.matches(id))
+			.collect::<Vec<_>>();
+		if matches.is_empty() {
+			return Err(Error::MissingPackage {
+				id: package_id.to_owned(),
+				suggestions: similar_package_ids(&manifest_package_ids, package_id),
+			});
+		}
+		let mut newest = Vec::<(String, Vec<u64>, &str)>::new();
+		for id in matches {
+			if all_versions {
+				expanded_package_ids.push(id.to_string());
+				continue;
+			}
+			let shape = version_regex
+				.replace_all(&id.to_ascii_lowercase(), "#")
+				.into_owned();
+			let version = version_regex
+				.find_iter(id)
+				.flat_map(|version| parse_version(version.as_str()))
+				.collect::<Vec<_>>();
+			match newest
+				.iter_mut()
+				.find(|(other_shape, _, _)| *other_shape == shape)
+			{
+				Some(entry) if entry.1 < version => *entry = (shape, version, id),
+				Some(_) => {}
+				None => newest.push((shape, version, id)),
+			}
+		}
+		for (_, _, id) in newest {
+			tracing::debug!("{} matched {}", package_id, id);
+			expanded_package_ids.push(id.to_owned());
+		}
+	}
+	Ok(expanded_package_ids)
+}
+
+// Up to five ids in the manifest nearest to the requested id or pattern by edit distance,
+// ignoring case and wildcards, and no further than half its length from it.
+fn similar_package_ids(manifest_package_ids: &[&str], package_id: &str) -> Vec<String> {
+	let requested = package_id
+		.trim_matches('/')
+		.to_ascii_lowercase()
+		.replace(|c| c == '*' || c == '?', "");
+	let mut candidates = manifest_package_ids
+		.iter()
+		.map(|id| (edit_distance(&requested, &id.to_ascii_lowercase()), *id))
+		.filter(|(distance, _)| *distance <= requested.len() / 2)
+		.collect::<Vec<_>>();
+	candidates.sort();
+	candidates
+		.into_iter()
+		.take(5)
+		.map(|(_, id)| id.to_owned())
+		.collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b = b.chars().collect::<Vec<_>>();
+	let mut distances = (0..=b.len()).collect::<Vec<_>>();
+	for (i, a) in a.chars().enumerate() {
+		let mut previous = distances[0];
+		distances[0] = i + 1;
+		for (j, b) in b.iter().enumerate() {
+			let substitution = previous + if a == *b { 0 } else { 1 };
+			previous = distances[j + 1];
+			distances[j + 1] = substitution.min(previous + 1).min(distances[j] + 1);
+		}
+	}
+	distances[b.len()]
+}
+
+// The newest of the versions, or of the ones matching the requested version, where
+// `14.38` matches `14.38.17.8` but not `14.381.0.0`.
+fn newest_version(
+	versions: impl Iterator<Item = String>,
+	requested: Option<&str>,
+) -> Option<String> {
+	versions
+		.filter(|version| match requested {
+			Some(requested) => {
+				version == requested || version.starts_with(&format!("{}.", requested))
+			}
+			None => true,
+		})
+		.max_by_key(|version| parse_version(version))
+}
+
+// Optional toolset components such as the ASAN runtime and the CRT source ship per
+// toolset as `Microsoft.VC.<toolset>.<component>.*` packages. If no toolset was selected,
+// fall back to the `Microsoft.VisualCpp.<component>` and
+// `Microsoft.VisualCpp.<component>.*` components, which depend on the default toolset.
+// Packages named for an architecture, such as `Microsoft.VC.<toolset>.ASAN.X64.base`, are
+// only selected for the chips, or without chips for the architectures of the selected
+// packages.
+fn toolset_package_ids(
+	manifest: &Manifest,
+	packages: &[&Package],
+	chips: &[DependencyChip],
+	component: &str,
+) -> Vec<String> {
+	let component = component.to_ascii_lowercase();
+	let toolset_regex = regex::Regex::new(r"(?i)^Microsoft\.VC\.(\d+\.\d+\.\d+\.\d+)\.").unwrap();
+	let prefixes = packages
+		.iter()
+		.filter_map(|package| toolset_regex.captures(&package.id))
+		.map(|captures| format!("microsoft.vc.{}.{}", &captures[1], component))
+		.collect::<HashSet<_>>();
+	let prefixes = if prefixes.is_empty() {
+		std::iter::once(format!("microsoft.visualcpp.{}", component)).collect()
+	} else {
+		prefixes
+	};
+	let chips = if chips.is_empty() {
+		packages
+			.iter()
+			.flat_map(|package| package_id_chips(&package.id))
+			.collect::<HashSet<_>>()
+	} else {
+		chips.iter().copied().collect()
+	};
+	manifest
+		.packages
+		.iter()
+		.filter(|package| {
+			let id = package.id.to_ascii_lowercase();
+			prefixes.iter().any(|prefix| {
+				id == *prefix
+					|| id
+						.strip_prefix(prefix.as_str())
+						.map_or(false, |rest| rest.starts_with('.'))
+			})
+		})
+		.filter(|package| {
+			let package_chips = package_id_chips(&package.id);
+			chips.is_empty()
+				|| package_chips.is_empty()
+				|| package_chips.iter().any(|chip| chips.contains(chip))
+		})
+		.map(|package| package.id.to_owned())
+		.collect()
+}
+
+// The architectures named by the components of a package id, as in `CRT.x64.Desktop` or
+// `TargetX64`.
+fn package_id_chips(package_id: &str) -> Vec<DependencyChip> {
+	package_id
+		.split('.')
+		.filter_map(|component| {
+			let component = component.to_ascii_lowercase();
+			let component = component.strip_prefix("target").unwrap_or(&component);
+			component.parse::<DependencyChip>().ok()
+		})
+		.collect()
+}
+
+// The compiler and linker in `Microsoft.VC.<toolset>.Tools.Host<arch>.Target<arch>.base`
+// load `msdia140.dll` from the DIA SDK and the C runtime DLLs for their host architecture
+// from `Microsoft.VC.<toolset>.CRT.Redist.<arch>.base`.
+fn tool_runtime_package_ids(manifest: &Manifest, packages: &[&Package]) -> Vec<String> {
+	let tools_regex = regex::Regex::new(
+		r"(?i)^Microsoft\.VC\.(\d+\.\d+\.\d+\.\d+)\.Tools\.Host(\w+?)\.Target\w+\.base$",
+	)
+	.unwrap();
+	let mut package_ids = packages
+		.iter()
+		.filter_map(|package| tools_regex.captures(&package.id))
+		.map(|captures| {
+			format!(
+				"microsoft.vc.{}.crt.redist.{}.base",
+				&captures[1], &captures[2]
+			)
+			.to_ascii_lowercase()
+		})
+		.collect::<HashSet<_>>();
+	if package_ids.is_empty() {
+		return Vec::new();
+	}
+	package_ids.insert("microsoft.visualcpp.dia.sdk".to_owned());
+	manifest
+		.packages
+		.iter()
+		.filter(|package| package_ids.contains(&package.id.to_ascii_lowercase()))
+		.map(|package| package.id.to_owned())
+		.collect()
+}
+
+// The desktop tools MSIs with rc.exe and midl.exe are payloads of the `Win10SDK_*` and
+// `Win11SDK_*` packages, so pick the newest one unless an SDK is already selected.
+fn sdk_package_ids(manifest: &Manifest, packages: &[&Package]) -> Vec<String> {
+	let sdk_regex = regex::Regex::new(r"(?i)^Win\d+SDK_(10\.0\.\d+)").unwrap();
+	if packages
+		.iter()
+		.any(|package| sdk_regex.is_match(&package.id))
+	{
+		return Vec::new();
+	}
+	manifest
+		.packages
+		.iter()
+		.filter_map(|package| {
+			sdk_regex
+				.captures(&package.id)
+				.map(|captures| (parse_version(&captures[1]), package))
+		})
+		.max_by(|(a, _), (b, _)| a.cmp(b))
+		.map(|(_, package)| vec![package.id.to_owned()])
+		.unwrap_or_default()
+}
+
+// The oldest Windows SDK each MSVC toolset series supports.
+const MINIMUM_SDK_VERSIONS: &[(&str, &str)] = &[("14.30", "10.0.18362"), ("14.20", "10.0.17134")];
+
+fn toolset_sdk_warnings(packages: &[&Package]) -> Vec<String> {
+	let toolset_regex = regex::Regex::new(r"(?i)^Microsoft\.VC\.(\d+\.\d+)\.\d+\.\d+\.").unwrap();
+	let sdk_regex = regex::Regex::new(r"(?i)^Win\d+SDK_(10\.0\.\d+)").unwrap();
+	let toolset_packages = packages
+		.iter()
+		.filter_map(|package| {
+			toolset_regex
+				.captures(&package.id)
+				.map(|captures| (captures[1].to_owned(), *package))
+		})
+		.collect::<Vec<_>>();
+	let toolsets = toolset_packages
+		.iter()
+		.map(|(toolset, _)| toolset.to_owned())
+		.collect::<HashSet<_>>();
+	let sdks = packages
+		.iter()
+		.filter_map(|package| sdk_regex.captures(&package.id))
+		.map(|captures| captures[1].to_owned())
+		.collect::<HashSet<_>>();
+	let mut warnings = Vec::new();
+	if !toolsets.is_empty() && sdks.is_empty() {
+		warnings.push(
+			"the selection contains an MSVC toolset but no Windows SDK, so windows.h and the system import libraries will be missing. Add a Win10SDK_* or Win11SDK_* package.".to_owned(),
+		);
+	}
+	if toolsets.is_empty() && !sdks.is_empty() {
+		warnings.push(
+			"the selection contains a Windows SDK but no MSVC toolset, so the C runtime headers and libraries will be missing. Add the Microsoft.VC.*.CRT packages for your toolset.".to_owned(),
+		);
+	}
+	if !toolset_packages.is_empty()
+		&& toolset_packages
+			.iter()
+			.all(|(_, package)| package.id.to_ascii_lowercase().contains("headers"))
+	{
+		warnings.push(
+			"the selection only contains MSVC toolset headers, so linking will fail. Add the Microsoft.VC.*.CRT.<arch>.Desktop packages for your architectures.".to_owned(),
+		);
+	}
+	for toolset in toolsets.iter() {
+		let minimum_sdk_version = MINIMUM_SDK_VERSIONS
+			.iter()
+			.find(|(series, _)| version_at_least(toolset, series))
+			.map(|(_, minimum_sdk_version)| *minimum_sdk_version);
+		let minimum_sdk_version = match minimum_sdk_version {
+			Some(minimum_sdk_version) => minimum_sdk_version,
+			None => continue,
+		};
+		for sdk in sdks.iter() {
+			if !version_at_least(sdk, minimum_sdk_version) {
+				warnings.push(format!(
+					"MSVC toolset {} requires Windows SDK {} or newer, but Windows SDK {} was selected. Select a newer Windows SDK or an older toolset.",
+					toolset, minimum_sdk_version, sdk
+				));
+			}
+		}
+	}
+	warnings
+}
+
+fn version_at_least(version: &str, minimum_version: &str) -> bool {
+	parse_version(version) >= parse_version(minimum_version)
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+	version
+		.split('.')
+		.map(|part| part.parse::<u64>().unwrap_or(0))
+		.collect()
+}
+
+// Compare a previously resolved selection against the manifest, describing each package
+// that no longer matches it and each required dependency the selection is missing.
+pub fn selection_drift(manifest: &Manifest, packages: &[Package]) -> Vec<String> {
+	let mut drift = Vec::new();
+	for package in packages.iter() {
+		let candidates = manifest
+			.packages
+			.iter()
+			.filter(|candidate| {
+				candidate.id.eq_ignore_ascii_case(&package.id)
+					&& candidate.chip == package.chip
+					&& candidate.machine_arch == package.machine_arch
+					&& candidate.language == package.language
+			})
+			.collect::<Vec<_>>();
+		if candidates.is_empty() {
+			drift.push(format!(
+				"package {} is no longer in the manifest",
+				package.id
+			));
+			continue;
+		}
+		let candidate = match candidates
+			.iter()
+			.find(|candidate| candidate.version == package.version)
+		{
+			Some(candidate) => candidate,
+			None => {
+				drift.push(format!(
+					"package {} is version {} in the selection but version {} in the manifest",
+					package.id, package.version, candidates[0].version
+				));
+				continue;
+			}
+		};
+		let payloads = |package: &Package| {
+			package
+				.payloads
+				.iter()
+				.map(|payload| (payload.file_name.to_owned(), payload.sha256, payload.size))
+				.collect::<HashSet<_>>()
+		};
+		if payloads(package) != payloads(candidate) {
+			drift.push(format!(
+				"package {} has different payloads in the selection than in the manifest",
+				package.id
+			));
+		}
+	}
+	let selected_package_ids = packages
+		.iter()
+		.map(|package| package.id.to_ascii_lowercase())
+		.collect::<HashSet<_>>();
+	let mut missing_package_ids = HashSet::new();
+	for package in packages.iter() {
+		// Dependencies restricted to a chip, or whose packages are all specific to an
+		// architecture or language, may have been left out on purpose.
+		for (id, dependency) in package.dependencies.iter() {
+			if dependency.ty.is_some() || dependency.chip.is_some() {
+				continue;
+			}
+			if selected_package_ids.contains(&id.to_ascii_lowercase())
+				|| !missing_package_ids.insert(id.to_ascii_lowercase())
+			{
+				continue;
+			}
+			let required = manifest.packages.iter().any(|candidate| {
+				candidate.id.eq_ignore_ascii_case(id) && !is_filterable(candidate)
+			});
+			if required {
+				drift.push(format!(
+					"package {} depends on {}, which is missing from the selection",
+					package.id, id
+				));
+			}
+		}
+	}
+	drift
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{edit_distance, expand_package_ids};
+	use crate::{Error, Manifest};
+
+	#[test]
+	fn test_edit_distance() {
+		assert_eq!(edit_distance("", ""), 0);
+		assert_eq!(edit_distance("abc", ""), 3);
+		assert_eq!(edit_distance("", "abc"), 3);
+		assert_eq!(edit_distance("kitten", "sitting"), 3);
+		assert_eq!(edit_distance("microsoft.vc.crt", "microsoft.vc.crt"), 0);
+		assert_eq!(edit_distance("microsoft.vc.crt", "microsoft.vc.ctr"), 2);
+	}
+
+	#[test]
+	fn test_expand_invalid_package_regex() {
+		let manifest = Manifest {
+			manifest_version: "1.0".to_owned(),
+			engine_version: "1.0".to_owned(),
+			packages: Vec::new(),
+		};
+		let error = expand_package_ids(&manifest, &["/(/".to_owned()], false).unwrap_err();
+		assert!(matches!(error, Error::InvalidArgument { .. }));
+	}
+}
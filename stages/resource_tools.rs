@@ -0,0 +1,66 @@
+use super::Stage;
+use crate::{env, Result};
+use std::path::PathBuf;
+
+// The resource and interface compilers, and the DLLs they load.
+const RESOURCE_TOOL_FILES: &[&str] = &[
+	"rc.exe",
+	"rcdll.dll",
+	"midl.exe",
+	"midlc.exe",
+	"midlrtmd.dll",
+];
+const HOSTS: &[&str] = &["x86", "x64", "arm64"];
+const ARCHES: &[&str] = &["x86", "x64", "arm", "arm64"];
+
+// Copies the resource compiler and MIDL from the Windows SDK's desktop tools at `Program
+// Files/Windows Kits/10/bin/<version>/<host>` into `bin/<host>/<arch>` for each
+// architecture with libraries in the output, so cross builds of GUI apps can find them
+// next to the rest of the toolchain. The newest SDK version wins when several are
+// installed.
+pub struct ResourceTools;
+
+impl Stage<PathBuf> for ResourceTools {
+	type Output = PathBuf;
+
+	fn run(&self, output_path: PathBuf) -> Result<PathBuf> {
+		let version_paths = env::children(
+			&output_path,
+			&["Program Files", "Windows Kits", "10", "bin"],
+		);
+		if version_paths.is_empty() {
+			return Ok(output_path);
+		}
+		let directories = env::find_directories(&output_path);
+		for host in HOSTS {
+			let host_path = match version_paths
+				.iter()
+				.rev()
+				.filter_map(|version_path| env::child(version_path, &[host]))
+				.find(|host_path| env::child_file(host_path, &["rc.exe"]).is_some())
+			{
+				Some(host_path) => host_path,
+				None => continue,
+			};
+			let arches = ARCHES
+				.iter()
+				.filter(|arch| directories.lib.contains_key(*arch))
+				.collect::<Vec<_>>();
+			let arches = if arches.is_empty() {
+				vec![host]
+			} else {
+				arches
+			};
+			for arch in arches {
+				let tools_path = output_path.join("bin").join(host).join(arch);
+				std::fs::create_dir_all(&tools_path)?;
+				for file_name in RESOURCE_TOOL_FILES {
+					if let Some(path) = env::child_file(&host_path, &[file_name]) {
+						std::fs::copy(&path, tools_path.join(file_name))?;
+					}
+				}
+			}
+		}
+		Ok(output_path)
+	}
+}
@@ -0,0 +1,598 @@
+use super::Stage;
+use crate::{
+	progress::{Progress, ProgressSink},
+	Cache, Error, Package, Payload, Result,
+};
+use cms::{
+	content_info::ContentInfo,
+	signed_data::{SignedData, SignerIdentifier},
+};
+use der::{
+	asn1::{ObjectIdentifier, OctetString},
+	Decode, Encode, SliceReader,
+};
+use digest::Digest;
+use rayon::prelude::*;
+use rsa::{pkcs8::DecodePublicKey, Pkcs1v15Sign, RsaPublicKey};
+use std::{
+	collections::HashSet,
+	io::{Read, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+use x509_cert::{spki::AlgorithmIdentifierOwned, Certificate};
+
+// Microsoft Root Certificate Authority 2010 and 2011, which the code signing certificates
+// of Visual Studio and Windows SDK payloads are issued under.
+const MICROSOFT_ROOTS: &[u8] = include_bytes!("../microsoft_roots.pem");
+
+const SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+const CONTENT_TYPE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.3");
+const MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const SPC_INDIRECT_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.2.1.4");
+
+// The streams of a signed MSI that hold its signature, which are left out of its digest.
+const MSI_DIGITAL_SIGNATURE: &str = "\u{5}DigitalSignature";
+const MSI_DIGITAL_SIGNATURE_EX: &str = "\u{5}MsiDigitalSignatureEx";
+
+// Checks the Authenticode signatures embedded in cached MSI and EXE payloads, failing
+// when any of them is unsigned, does not match its signature, or is not signed by a
+// certificate issued under a trusted root. This only checks that a payload is what
+// Microsoft signed, so certificate validity periods, revocation, and timestamps are not
+// checked: the certificates expire long before the payloads signed with them stop being
+// served. Cabinets are listed as unchecked, since their signatures are not supported.
+pub struct VerifySignatures {
+	pub cache: Cache,
+	pub progress: Arc<dyn ProgressSink>,
+	// The number of payloads to check at once, or zero for one per CPU.
+	pub jobs: usize,
+	// Certificates to trust in addition to the Microsoft roots, in PEM or DER.
+	pub trusted_roots: Vec<PathBuf>,
+}
+
+impl Stage<Vec<Package>> for VerifySignatures {
+	type Output = Vec<Package>;
+
+	fn run(&self, packages: Vec<Package>) -> Result<Vec<Package>> {
+		let roots = trusted_roots(&self.trusted_roots)?;
+		let mut seen = HashSet::new();
+		let payloads = packages
+			.iter()
+			.flat_map(|package| package.payloads.iter())
+			.filter(|payload| seen.insert(payload.sha256))
+			.filter_map(|payload| Some((payload, SignedPayload::for_payload(payload)?)))
+			.collect::<Vec<_>>();
+		let cabinets = payloads
+			.iter()
+			.filter(|(_, kind)| *kind == SignedPayload::Cabinet)
+			.count();
+		if cabinets > 0 {
+			tracing::warn!(
+				"the signatures of {} cabinets were not checked, since cabinet signatures are not supported",
+				cabinets
+			);
+		}
+		let payloads = payloads
+			.into_iter()
+			.filter(|(_, kind)| *kind != SignedPayload::Cabinet)
+			.collect::<Vec<_>>();
+		let total_size = payloads.iter().map(|(payload, _)| payload.size).sum();
+		let progress_bar = Progress::new(&self.progress, "signatures", total_size);
+		let thread_pool = rayon::ThreadPoolBuilder::new()
+			.num_threads(self.jobs)
+			.build()
+			.map_err(|error| std::io::Error::new(std::io::ErrorKind::Other, error))?;
+		let count = thread_pool.install(|| {
+			payloads
+				.par_iter()
+				.filter(|(payload, kind)| {
+					let path = self.cache.payload_path(payload);
+					let result = match kind {
+						SignedPayload::Msi => verify_msi(&path, &roots),
+						SignedPayload::Pe => verify_pe(&path, &roots),
+						SignedPayload::Cabinet => Ok(()),
+					};
+					progress_bar.inc(payload.size);
+					match result {
+						Ok(()) => {
+							tracing::debug!("{} is signed", payload.file_name);
+							false
+						}
+						Err(InvalidSignature(reason)) => {
+							tracing::error!("{}: {}", payload.file_name, reason);
+							true
+						}
+					}
+				})
+				.count()
+		});
+		progress_bar.finish();
+		if count > 0 {
+			return Err(Error::InvalidSignatures { count });
+		}
+		tracing::info!("checked the signatures of {} payloads", payloads.len());
+		Ok(packages)
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SignedPayload {
+	Msi,
+	Pe,
+	Cabinet,
+}
+
+impl SignedPayload {
+	fn for_payload(payload: &Payload) -> Option<SignedPayload> {
+		let file_name = payload.file_name.to_ascii_lowercase();
+		if file_name.ends_with(".msi") {
+			Some(SignedPayload::Msi)
+		} else if file_name.ends_with(".exe") {
+			Some(SignedPayload::Pe)
+		} else if file_name.ends_with(".cab") {
+			Some(SignedPayload::Cabinet)
+		} else {
+			None
+		}
+	}
+}
+
+// Why a payload's signature was rejected, which is reported for the payload rather than
+// failing the stage.
+struct InvalidSignature(String);
+
+impl From<std::io::Error> for InvalidSignature {
+	fn from(error: std::io::Error) -> Self {
+		InvalidSignature(error.to_string())
+	}
+}
+
+impl From<der::Error> for InvalidSignature {
+	fn from(error: der::Error) -> Self {
+		InvalidSignature(format!("the signature is malformed: {}", error))
+	}
+}
+
+impl From<&str> for InvalidSignature {
+	fn from(reason: &str) -> Self {
+		InvalidSignature(reason.to_owned())
+	}
+}
+
+type SignatureResult<T> = std::result::Result<T, InvalidSignature>;
+
+// The embedded Microsoft roots and the certificates in the files, each of which holds one
+// PEM certificate or more, or one DER certificate.
+fn trusted_roots(paths: &[PathBuf]) -> Result<Vec<Certificate>> {
+	let mut roots = Certificate::load_pem_chain(MICROSOFT_ROOTS).unwrap();
+	for path in paths {
+		let bytes = std::fs::read(path)?;
+		let certificates = if bytes.starts_with(b"-----BEGIN") {
+			Certificate::load_pem_chain(&bytes)
+		} else {
+			Certificate::from_der(&bytes).map(|certificate| vec![certificate])
+		};
+		roots.extend(certificates.map_err(|error| Error::InvalidPath {
+			path: path.clone(),
+			reason: format!("not a certificate: {}", error),
+		})?);
+	}
+	Ok(roots)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum DigestAlgorithm {
+	Sha1,
+	Sha256,
+	Sha384,
+	Sha512,
+}
+
+impl DigestAlgorithm {
+	fn from_oid(oid: &ObjectIdentifier) -> Option<DigestAlgorithm> {
+		match oid.to_string().as_str() {
+			"1.3.14.3.2.26" => Some(DigestAlgorithm::Sha1),
+			"2.16.840.1.101.3.4.2.1" => Some(DigestAlgorithm::Sha256),
+			"2.16.840.1.101.3.4.2.2" => Some(DigestAlgorithm::Sha384),
+			"2.16.840.1.101.3.4.2.3" => Some(DigestAlgorithm::Sha512),
+			_ => None,
+		}
+	}
+
+	// The digest of an RSA signature algorithm, as certificates name them.
+	fn from_signature_oid(oid: &ObjectIdentifier) -> Option<DigestAlgorithm> {
+		match oid.to_string().as_str() {
+			"1.2.840.113549.1.1.5" => Some(DigestAlgorithm::Sha1),
+			"1.2.840.113549.1.1.11" => Some(DigestAlgorithm::Sha256),
+			"1.2.840.113549.1.1.12" => Some(DigestAlgorithm::Sha384),
+			"1.2.840.113549.1.1.13" => Some(DigestAlgorithm::Sha512),
+			_ => None,
+		}
+	}
+
+	fn hasher(self) -> Hasher {
+		match self {
+			DigestAlgorithm::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+			DigestAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+			DigestAlgorithm::Sha384 => Hasher::Sha384(sha2::Sha384::new()),
+			DigestAlgorithm::Sha512 => Hasher::Sha512(sha2::Sha512::new()),
+		}
+	}
+
+	fn digest(self, bytes: &[u8]) -> Vec<u8> {
+		let mut hasher = self.hasher();
+		hasher.update(bytes);
+		hasher.finalize()
+	}
+
+	fn padding(self) -> Pkcs1v15Sign {
+		match self {
+			DigestAlgorithm::Sha1 => Pkcs1v15Sign::new::<sha1::Sha1>(),
+			DigestAlgorithm::Sha256 => Pkcs1v15Sign::new::<sha2::Sha256>(),
+			DigestAlgorithm::Sha384 => Pkcs1v15Sign::new::<sha2::Sha384>(),
+			DigestAlgorithm::Sha512 => Pkcs1v15Sign::new::<sha2::Sha512>(),
+		}
+	}
+}
+
+enum Hasher {
+	Sha1(sha1::Sha1),
+	Sha256(sha2::Sha256),
+	Sha384(sha2::Sha384),
+	Sha512(sha2::Sha512),
+}
+
+impl Hasher {
+	fn update(&mut self, bytes: &[u8]) {
+		match self {
+			Hasher::Sha1(hasher) => hasher.update(bytes),
+			Hasher::Sha256(hasher) => hasher.update(bytes),
+			Hasher::Sha384(hasher) => hasher.update(bytes),
+			Hasher::Sha512(hasher) => hasher.update(bytes),
+		}
+	}
+
+	fn finalize(self) -> Vec<u8> {
+		match self {
+			Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+			Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+			Hasher::Sha384(hasher) => hasher.finalize().to_vec(),
+			Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+		}
+	}
+}
+
+impl Write for Hasher {
+	fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+		self.update(bytes);
+		Ok(bytes.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+// The content of an Authenticode signature: the digest of the signed file, and what kind
+// of file it is.
+#[derive(der::Sequence)]
+struct SpcIndirectDataContent {
+	data: der::Any,
+	message_digest: DigestInfo,
+}
+
+#[derive(der::Sequence)]
+struct DigestInfo {
+	digest_algorithm: AlgorithmIdentifierOwned,
+	digest: OctetString,
+}
+
+// Check a PKCS #7 Authenticode signature, computing the digest of the signed file with
+// the algorithm the signature names.
+fn verify_signed_data(
+	bytes: &[u8],
+	roots: &[Certificate],
+	content_digest: impl FnOnce(DigestAlgorithm) -> SignatureResult<Vec<u8>>,
+) -> SignatureResult<()> {
+	// Signatures are padded to a multiple of eight bytes, so only the first value is read.
+	let content_info = ContentInfo::decode(&mut SliceReader::new(bytes)?)?;
+	if content_info.content_type != SIGNED_DATA {
+		return Err("the signature is not PKCS #7 signed data".into());
+	}
+	let signed_data = content_info.content.decode_as::<SignedData>()?;
+	let content = match signed_data.encap_content_info.econtent {
+		Some(content) if signed_data.encap_content_info.econtent_type == SPC_INDIRECT_DATA => {
+			content
+		}
+		_ => return Err("the signature is not an Authenticode signature".into()),
+	};
+	let indirect_data = content.decode_as::<SpcIndirectDataContent>()?;
+	let algorithm = DigestAlgorithm::from_oid(&indirect_data.message_digest.digest_algorithm.oid)
+		.ok_or("the signature uses an unsupported digest algorithm")?;
+	if content_digest(algorithm)? != indirect_data.message_digest.digest.as_bytes() {
+		return Err("the payload does not match its signature".into());
+	}
+
+	let mut signer_infos = signed_data.signer_infos.0.iter();
+	let signer_info = match (signer_infos.next(), signer_infos.next()) {
+		(Some(signer_info), None) => signer_info,
+		_ => return Err("the signature does not have exactly one signer".into()),
+	};
+	let signer_algorithm = DigestAlgorithm::from_oid(&signer_info.digest_alg.oid)
+		.ok_or("the signature uses an unsupported digest algorithm")?;
+	let signed_attributes = signer_info
+		.signed_attrs
+		.as_ref()
+		.ok_or("the signature does not have signed attributes")?;
+	let attribute = |oid: ObjectIdentifier| {
+		signed_attributes
+			.iter()
+			.find(|attribute| attribute.oid == oid)
+			.and_then(|attribute| attribute.values.iter().next())
+	};
+	let content_type = attribute(CONTENT_TYPE)
+		.map(|value| value.decode_as::<ObjectIdentifier>())
+		.transpose()?;
+	if content_type != Some(SPC_INDIRECT_DATA) {
+		return Err("the signature does not sign its content".into());
+	}
+	// Authenticode signs the content without its tag and length.
+	let message_digest = attribute(MESSAGE_DIGEST)
+		.map(|value| value.decode_as::<OctetString>())
+		.transpose()?;
+	if message_digest.as_ref().map(|digest| digest.as_bytes())
+		!= Some(&signer_algorithm.digest(content.value()))
+	{
+		return Err("the signature does not sign its content".into());
+	}
+
+	let certificates = signed_data
+		.certificates
+		.iter()
+		.flat_map(|certificates| certificates.0.iter())
+		.filter_map(|certificate| match certificate {
+			cms::cert::CertificateChoices::Certificate(certificate) => Some(certificate.clone()),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	let signer = match &signer_info.sid {
+		SignerIdentifier::IssuerAndSerialNumber(id) => certificates.iter().find(|certificate| {
+			certificate.tbs_certificate.issuer == id.issuer
+				&& certificate.tbs_certificate.serial_number == id.serial_number
+		}),
+		SignerIdentifier::SubjectKeyIdentifier(_) => None,
+	}
+	.ok_or("the signature does not include the signing certificate")?;
+	if !is_rsa_signature(&signer_info.signature_algorithm.oid, signer_algorithm) {
+		return Err("the signature uses an unsupported signature algorithm".into());
+	}
+	public_key(signer)?
+		.verify(
+			signer_algorithm.padding(),
+			&signer_algorithm.digest(&signed_attributes.to_der()?),
+			signer_info.signature.as_bytes(),
+		)
+		.map_err(|_| "the signature was not made with the signing certificate")?;
+	verify_chain(signer, &certificates, roots)
+}
+
+// Signers name their algorithm either as RSA with the digest algorithm, or as plain RSA.
+fn is_rsa_signature(oid: &ObjectIdentifier, algorithm: DigestAlgorithm) -> bool {
+	oid.to_string() == "1.2.840.113549.1.1.1"
+		|| DigestAlgorithm::from_signature_oid(oid) == Some(algorithm)
+}
+
+fn public_key(certificate: &Certificate) -> SignatureResult<RsaPublicKey> {
+	let public_key_info = certificate
+		.tbs_certificate
+		.subject_public_key_info
+		.to_der()?;
+	RsaPublicKey::from_public_key_der(&public_key_info).map_err(|_| {
+		InvalidSignature(format!(
+			"{} does not have an RSA key",
+			certificate.tbs_certificate.subject
+		))
+	})
+}
+
+fn is_issued_by(certificate: &Certificate, issuer: &Certificate) -> bool {
+	if certificate.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+		return false;
+	}
+	let algorithm = match DigestAlgorithm::from_signature_oid(&certificate.signature_algorithm.oid)
+	{
+		Some(algorithm) => algorithm,
+		None => return false,
+	};
+	let (public_key, tbs_certificate) =
+		match (public_key(issuer), certificate.tbs_certificate.to_der()) {
+			(Ok(public_key), Ok(tbs_certificate)) => (public_key, tbs_certificate),
+			_ => return false,
+		};
+	let signature = match certificate.signature.as_bytes() {
+		Some(signature) => signature,
+		None => return false,
+	};
+	public_key
+		.verify(
+			algorithm.padding(),
+			&algorithm.digest(&tbs_certificate),
+			signature,
+		)
+		.is_ok()
+}
+
+// Follow the issuers of the signing certificate through the certificates in the signature
+// until one is a trusted root or is issued by one.
+fn verify_chain(
+	signer: &Certificate,
+	certificates: &[Certificate],
+	roots: &[Certificate],
+) -> SignatureResult<()> {
+	let mut certificate = signer;
+	for _ in 0..=certificates.len() {
+		if roots
+			.iter()
+			.any(|root| root == certificate || is_issued_by(certificate, root))
+		{
+			return Ok(());
+		}
+		certificate = match certificates
+			.iter()
+			.find(|issuer| *issuer != certificate && is_issued_by(certificate, issuer))
+		{
+			Some(issuer) => issuer,
+			None => break,
+		};
+	}
+	Err(InvalidSignature(format!(
+		"the signing certificate {} is not issued by a trusted root",
+		signer.tbs_certificate.subject
+	)))
+}
+
+// The digest of a PE file leaves out its checksum, the location of its certificate table,
+// and the table, which are all written when it is signed.
+fn verify_pe(path: &Path, roots: &[Certificate]) -> SignatureResult<()> {
+	let mut file = std::fs::File::open(path)?;
+	let file_size = file.metadata()?.len();
+	let mut headers = Vec::new();
+	(&mut file).take(4096).read_to_end(&mut headers)?;
+	let u16_at = |offset: usize| {
+		headers
+			.get(offset..offset + 2)
+			.map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+			.ok_or("the payload is not a PE file")
+	};
+	let u32_at = |offset: usize| {
+		headers
+			.get(offset..offset + 4)
+			.map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+			.ok_or("the payload is not a PE file")
+	};
+	if !headers.starts_with(b"MZ") {
+		return Err("the payload is not a PE file".into());
+	}
+	let pe_offset = u32_at(0x3c)?;
+	if headers.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0") {
+		return Err("the payload is not a PE file".into());
+	}
+	let optional_header_offset = pe_offset + 24;
+	let (directory_count_offset, directories_offset) = match u16_at(optional_header_offset)? {
+		0x10b => (optional_header_offset + 92, optional_header_offset + 96),
+		0x20b => (optional_header_offset + 108, optional_header_offset + 112),
+		_ => return Err("the payload is not a PE file".into()),
+	};
+	let checksum_offset = optional_header_offset + 64;
+	// The certificate table is the fifth data directory, and its address is an offset in the file.
+	let certificate_directory_offset = directories_offset + 4 * 8;
+	if u32_at(directory_count_offset)? < 5 {
+		return Err("the payload is not signed".into());
+	}
+	let certificate_table_offset = u32_at(certificate_directory_offset)? as u64;
+	let certificate_table_size = u32_at(certificate_directory_offset + 4)? as u64;
+	if certificate_table_size == 0 {
+		return Err("the payload is not signed".into());
+	}
+	if certificate_table_offset + certificate_table_size > file_size {
+		return Err("the certificate table is outside the payload".into());
+	}
+
+	// Each entry of the table is a `WIN_CERTIFICATE`, and the signature is the first of
+	// type `WIN_CERT_TYPE_PKCS_SIGNED_DATA`.
+	file.seek(SeekFrom::Start(certificate_table_offset))?;
+	let mut table = Vec::new();
+	(&mut file)
+		.take(certificate_table_size)
+		.read_to_end(&mut table)?;
+	let mut offset = 0;
+	let signature = loop {
+		let entry = table
+			.get(offset..offset + 8)
+			.ok_or("the payload is not signed")?;
+		let length = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+		let certificate_type = u16::from_le_bytes([entry[6], entry[7]]);
+		let certificate = table
+			.get(offset + 8..offset + length)
+			.ok_or("the certificate table is malformed")?;
+		if certificate_type == 2 {
+			break certificate;
+		}
+		offset += (length + 7) & !7;
+	};
+
+	verify_signed_data(signature, roots, |algorithm| {
+		let mut hasher = algorithm.hasher();
+		let ranges = [
+			(0, checksum_offset as u64),
+			(
+				checksum_offset as u64 + 4,
+				certificate_directory_offset as u64,
+			),
+			(
+				certificate_directory_offset as u64 + 8,
+				certificate_table_offset,
+			),
+		];
+		for (start, end) in ranges {
+			file.seek(SeekFrom::Start(start))?;
+			std::io::copy(&mut (&mut file).take(end - start), &mut hasher)?;
+		}
+		Ok(hasher.finalize())
+	})
+}
+
+// The digest of an MSI covers the contents of its streams, in the order of their names'
+// UTF-16LE bytes, and the class id of each storage after its children. Signing with
+// MsiDigitalSignatureEx also covers the streams' metadata, which is not supported.
+fn verify_msi(path: &Path, roots: &[Certificate]) -> SignatureResult<()> {
+	let mut msi = cfb::open(path)?;
+	if msi.exists(MSI_DIGITAL_SIGNATURE_EX) {
+		return Err(
+			"the payload is signed with MsiDigitalSignatureEx, which is not supported".into(),
+		);
+	}
+	if !msi.is_stream(MSI_DIGITAL_SIGNATURE) {
+		return Err("the payload is not signed".into());
+	}
+	let mut signature = Vec::new();
+	msi.open_stream(MSI_DIGITAL_SIGNATURE)?
+		.read_to_end(&mut signature)?;
+	verify_signed_data(&signature, roots, |algorithm| {
+		let mut hasher = algorithm.hasher();
+		hash_msi_storage(&mut msi, Path::new("/"), &mut hasher)?;
+		Ok(hasher.finalize())
+	})
+}
+
+fn hash_msi_storage<F: Read + Seek>(
+	msi: &mut cfb::CompoundFile<F>,
+	path: &Path,
+	hasher: &mut Hasher,
+) -> std::io::Result<()> {
+	let is_root = path == Path::new("/");
+	let mut entries = msi
+		.read_storage(path)?
+		.filter(|entry| {
+			!(is_root && [MSI_DIGITAL_SIGNATURE, MSI_DIGITAL_SIGNATURE_EX].contains(&entry.name()))
+		})
+		.map(|entry| {
+			let name = entry
+				.name()
+				.encode_utf16()
+				.flat_map(|unit| unit.to_le_bytes())
+				.collect::<Vec<_>>();
+			(name, entry.path().to_owned(), entry.is_stream())
+		})
+		.collect::<Vec<_>>();
+	entries.sort();
+	for (_, entry_path, is_stream) in entries {
+		if is_stream {
+			std::io::copy(&mut msi.open_stream(&entry_path)?, hasher)?;
+		} else {
+			hash_msi_storage(msi, &entry_path, hasher)?;
+		}
+	}
+	hasher.write_all(&msi.entry(path)?.clsid().to_bytes_le())?;
+	Ok(())
+}
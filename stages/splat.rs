@@ -0,0 +1,307 @@
+use super::Stage;
+use crate::Result;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Layout {
+	// Keep the install paths of the payloads, such as `VC/Tools/MSVC/<version>/include`.
+	Flat,
+	// Rearrange the CRT and SDK under `crt` and `sdk`, such as `crt/lib/<arch>` and
+	// `sdk/include/<kind>`, so cross compilers can be pointed at a few stable paths.
+	// See `splat_path` for the full mapping.
+	Splat,
+}
+
+impl Default for Layout {
+	fn default() -> Self {
+		Layout::Flat
+	}
+}
+
+impl std::str::FromStr for Layout {
+	type Err = String;
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"flat" => Ok(Layout::Flat),
+			"splat" => Ok(Layout::Splat),
+			_ => Err(format!(
+				"invalid layout \"{}\", expected flat or splat",
+				value
+			)),
+		}
+	}
+}
+
+impl std::fmt::Display for Layout {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Layout::Flat => write!(f, "flat"),
+			Layout::Splat => write!(f, "splat"),
+		}
+	}
+}
+
+// Moves an extracted tree into the splat layout. Files that have no place in it
+// are removed with a warning.
+pub struct Splat;
+
+impl Stage<PathBuf> for Splat {
+	type Output = PathBuf;
+
+	fn run(&self, output_path: PathBuf) -> Result<PathBuf> {
+		let splat_tempdir = tempfile::Builder::new()
+			.prefix(".splat")
+			.tempdir_in(&output_path)?;
+		let splat_name = splat_tempdir.path().file_name().unwrap().to_owned();
+		let file_paths = WalkDir::new(&output_path)
+			.into_iter()
+			.filter_entry(|entry| entry.depth() != 1 || entry.file_name() != splat_name)
+			.filter(|entry| {
+				entry
+					.as_ref()
+					.map(|entry| entry.file_type().is_file())
+					.unwrap_or(true)
+			})
+			.map(|entry| Ok(entry?.into_path()))
+			.collect::<Result<Vec<_>>>()?;
+		let mut dropped_count = 0;
+		for file_path in file_paths {
+			let relative_path = file_path.strip_prefix(&output_path).unwrap();
+			match splat_path(relative_path) {
+				Some(splat_path) => {
+					let path = splat_tempdir.path().join(splat_path);
+					std::fs::create_dir_all(path.parent().unwrap())?;
+					std::fs::rename(&file_path, path)?;
+				}
+				None => {
+					tracing::debug!(
+						"{} has no place in the splat layout",
+						relative_path.display()
+					);
+					dropped_count += 1;
+				}
+			}
+		}
+		if dropped_count > 0 {
+			tracing::warn!(
+				"removing {} files that have no place in the splat layout, run with -v to list them, or use --layout flat to keep them",
+				dropped_count
+			);
+		}
+		// Replace the extracted tree with the splat tree.
+		for entry in std::fs::read_dir(&output_path)? {
+			let entry = entry?;
+			if entry.file_name() == splat_name {
+				continue;
+			}
+			if entry.file_type()?.is_dir() {
+				std::fs::remove_dir_all(entry.path())?;
+			} else {
+				std::fs::remove_file(entry.path())?;
+			}
+		}
+		for entry in std::fs::read_dir(splat_tempdir.path())? {
+			let entry = entry?;
+			std::fs::rename(entry.path(), output_path.join(entry.file_name()))?;
+		}
+		Ok(output_path)
+	}
+}
+
+const ARCHES: &[&str] = &["x86", "x64", "arm", "arm64"];
+const SDK_INCLUDE_KINDS: &[&str] = &["um", "ucrt", "shared", "winrt", "cppwinrt"];
+const SDK_LIB_KINDS: &[&str] = &["um", "ucrt"];
+const CRT_LIB_VARIANTS: &[&str] = &["spectre", "onecore"];
+
+fn splat_path(relative_path: &Path) -> Option<PathBuf> {
+	let components = relative_path
+		.iter()
+		.map(|component| component.to_string_lossy().into_owned())
+		.collect::<Vec<_>>();
+	let matches = |index: usize, names: &[&str]| {
+		components
+			.get(index)
+			.map(|component| {
+				names
+					.iter()
+					.any(|name| component.eq_ignore_ascii_case(name))
+			})
+			.unwrap_or(false)
+	};
+	let join = |prefix: &[&str], rest: &[String]| {
+		let mut path = prefix.iter().collect::<PathBuf>();
+		path.extend(rest);
+		path
+	};
+	let lowercase = |index: usize| components[index].to_ascii_lowercase();
+	// The records of the extraction, such as which package each file came from.
+	if components.len() == 1 && components[0].starts_with(".windows_sdk-") {
+		return Some(relative_path.to_owned());
+	}
+	if matches(0, &["dotnet", "bin"]) {
+		return Some(relative_path.to_owned());
+	}
+	// VC/Tools/MSVC/<version>/{include,lib/<variant>/<arch>,crt/src,atlmfc,bin}
+	if matches(0, &["VC"]) && matches(1, &["Tools"]) && matches(2, &["MSVC"]) {
+		if matches(4, &["include"]) && components.len() > 5 {
+			return Some(join(&["crt", "include"], &components[5..]));
+		}
+		if matches(4, &["lib"]) {
+			let mut prefix = vec!["crt".to_owned(), "lib".to_owned()];
+			let mut index = 5;
+			while matches(index, CRT_LIB_VARIANTS) {
+				prefix.push(lowercase(index));
+				index += 1;
+			}
+			if matches(index, ARCHES) && components.len() > index + 1 {
+				prefix.push(lowercase(index));
+				let prefix = prefix.iter().map(String::as_str).collect::<Vec<_>>();
+				return Some(join(&prefix, &components[index + 1..]));
+			}
+			return None;
+		}
+		if matches(4, &["crt"]) && matches(5, &["src"]) && components.len() > 6 {
+			return Some(join(&["crt", "src"], &components[6..]));
+		}
+		if matches(4, &["atlmfc"]) && components.len() > 5 {
+			return Some(join(&["crt", "atlmfc"], &components[5..]));
+		}
+		if matches(4, &["bin"]) && components.len() > 5 {
+			return Some(join(&["crt", "bin"], &components[5..]));
+		}
+		return None;
+	}
+	// The runtime DLLs the toolset loads.
+	if matches(0, &["VC"])
+		&& matches(1, &["Redist"])
+		&& matches(2, &["MSVC"])
+		&& components.len() > 4
+	{
+		return Some(join(&["crt", "redist"], &components[4..]));
+	}
+	if matches(0, &["DIA SDK"]) && components.len() > 1 {
+		return Some(join(&["dia"], &components[1..]));
+	}
+	// Program Files/Windows Kits/10/{Include/<version>/<kind>,Lib/<version>/<kind>/<arch>,bin/[<version>/]<host>}
+	if matches(0, &["Program Files"]) && matches(1, &["Windows Kits"]) && matches(2, &["10"]) {
+		if matches(3, &["bin"]) {
+			let host = if matches(4, ARCHES) { 4 } else { 5 };
+			if matches(host, ARCHES) && components.len() > host + 1 {
+				return Some(join(
+					&["sdk", "bin", &lowercase(host)],
+					&components[host + 1..],
+				));
+			}
+		}
+		if matches(3, &["Include"]) && matches(5, SDK_INCLUDE_KINDS) && components.len() > 6 {
+			return Some(join(&["sdk", "include", &lowercase(5)], &components[6..]));
+		}
+		if matches(3, &["Lib"])
+			&& matches(5, SDK_LIB_KINDS)
+			&& matches(6, ARCHES)
+			&& components.len() > 7
+		{
+			return Some(join(
+				&["sdk", "lib", &lowercase(5), &lowercase(6)],
+				&components[7..],
+			));
+		}
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::splat_path;
+	use std::path::{Path, PathBuf};
+
+	fn splat(path: &str) -> Option<PathBuf> {
+		splat_path(Path::new(path))
+	}
+
+	#[test]
+	fn test_crt() {
+		let msvc = "VC/Tools/MSVC/14.38.33130";
+		assert_eq!(
+			splat(&format!("{}/include/vcruntime.h", msvc)),
+			Some(PathBuf::from("crt/include/vcruntime.h"))
+		);
+		assert_eq!(
+			splat(&format!("{}/lib/X64/msvcrt.lib", msvc)),
+			Some(PathBuf::from("crt/lib/x64/msvcrt.lib"))
+		);
+		assert_eq!(
+			splat(&format!("{}/lib/spectre/arm64/msvcrt.lib", msvc)),
+			Some(PathBuf::from("crt/lib/spectre/arm64/msvcrt.lib"))
+		);
+		assert_eq!(
+			splat(&format!("{}/lib/onecore/x86/msvcrt.lib", msvc)),
+			Some(PathBuf::from("crt/lib/onecore/x86/msvcrt.lib"))
+		);
+		assert_eq!(
+			splat(&format!("{}/crt/src/vcruntime/undname.cxx", msvc)),
+			Some(PathBuf::from("crt/src/vcruntime/undname.cxx"))
+		);
+		assert_eq!(
+			splat(&format!("{}/atlmfc/lib/x64/atls.lib", msvc)),
+			Some(PathBuf::from("crt/atlmfc/lib/x64/atls.lib"))
+		);
+		assert_eq!(
+			splat(&format!("{}/bin/Hostx64/x64/cl.exe", msvc)),
+			Some(PathBuf::from("crt/bin/Hostx64/x64/cl.exe"))
+		);
+		assert_eq!(
+			splat("VC/Redist/MSVC/14.38.33135/x64/Microsoft.VC143.CRT/vcruntime140.dll"),
+			Some(PathBuf::from(
+				"crt/redist/x64/Microsoft.VC143.CRT/vcruntime140.dll"
+			))
+		);
+		assert_eq!(
+			splat("DIA SDK/bin/amd64/msdia140.dll"),
+			Some(PathBuf::from("dia/bin/amd64/msdia140.dll"))
+		);
+		assert_eq!(splat(&format!("{}/lib/msvcrt.lib", msvc)), None);
+		assert_eq!(splat(&format!("{}/lib/spectre/msvcrt.lib", msvc)), None);
+		assert_eq!(splat(&format!("{}/Auxiliary/VS/x.h", msvc)), None);
+	}
+
+	#[test]
+	fn test_sdk() {
+		let kit = "Program Files/Windows Kits/10";
+		assert_eq!(
+			splat(&format!("{}/Include/10.0.22621.0/um/windows.h", kit)),
+			Some(PathBuf::from("sdk/include/um/windows.h"))
+		);
+		assert_eq!(
+			splat(&format!("{}/Lib/10.0.22621.0/UCRT/ARM64/ucrt.lib", kit)),
+			Some(PathBuf::from("sdk/lib/ucrt/arm64/ucrt.lib"))
+		);
+		assert_eq!(
+			splat(&format!("{}/Include/10.0.22621.0/km/wdm.h", kit)),
+			None
+		);
+		assert_eq!(splat(&format!("{}/Lib/10.0.22621.0/um/x.lib", kit)), None);
+		assert_eq!(
+			splat(&format!("{}/bin/10.0.22621.0/x64/rc.exe", kit)),
+			Some(PathBuf::from("sdk/bin/x64/rc.exe"))
+		);
+		assert_eq!(
+			splat(&format!("{}/bin/x86/mt.exe", kit)),
+			Some(PathBuf::from("sdk/bin/x86/mt.exe"))
+		);
+		assert_eq!(splat(&format!("{}/bin/10.0.22621.0/x.txt", kit)), None);
+	}
+
+	#[test]
+	fn test_kept() {
+		for path in [
+			".windows_sdk-files.json",
+			"dotnet/ref/System.dll",
+			"bin/x64/rc.exe",
+		] {
+			assert_eq!(splat(path), Some(PathBuf::from(path)));
+		}
+		assert_eq!(splat("Debuggers/x64/cdb.exe"), None);
+	}
+}
@@ -0,0 +1,125 @@
+use super::Stage;
+use crate::Result;
+use std::{
+	collections::BTreeSet,
+	path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+// Copies the DLLs the compiler and linker load into their directories, so the
+// extracted tools run under Wine or on Windows without an installed runtime. It
+// reads the flat layout, so it runs before the tree is splatted.
+pub struct ToolRuntime;
+
+impl Stage<PathBuf> for ToolRuntime {
+	type Output = PathBuf;
+
+	fn run(&self, output_path: PathBuf) -> Result<PathBuf> {
+		place_tool_runtime(&output_path)?;
+		Ok(output_path)
+	}
+}
+
+// Tools are at `VC/Tools/MSVC/<version>/bin/Host<arch>/<arch>`, the C runtime DLLs
+// at `VC/Redist/MSVC/<version>/<arch>/Microsoft.VC<version>.CRT`, and the DIA SDK
+// DLLs at `DIA SDK/bin` for x86 and `DIA SDK/bin/<arch>` otherwise.
+fn place_tool_runtime(output_path: &Path) -> Result<()> {
+	let crt_regex = regex::Regex::new(r"(?i)^Microsoft\.VC\d+\.CRT$").unwrap();
+	let mut tool_paths = BTreeSet::new();
+	let mut redist_paths = Vec::new();
+	let mut dia_paths = Vec::new();
+	for entry in WalkDir::new(output_path) {
+		let entry = entry?;
+		if !entry.file_type().is_file() {
+			continue;
+		}
+		let relative_path = entry.path().strip_prefix(output_path).unwrap();
+		let components = relative_path
+			.iter()
+			.map(|component| component.to_string_lossy().to_ascii_lowercase())
+			.collect::<Vec<_>>();
+		let file_name = components.last().unwrap();
+		match components.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+			["vc", "tools", "msvc", version, "bin", host, _, "cl.exe" | "link.exe"]
+				if host.starts_with("host") =>
+			{
+				tool_paths.insert((
+					entry.path().parent().unwrap().to_owned(),
+					version.to_owned(),
+					host["host".len()..].to_owned(),
+				));
+			}
+			["vc", "redist", "msvc", version, arch, crt, _]
+				if crt_regex.is_match(crt) && file_name.ends_with(".dll") =>
+			{
+				redist_paths.push((entry.path().to_owned(), version.to_owned(), arch.to_owned()));
+			}
+			["dia sdk", "bin", ref rest @ ..]
+				if file_name.starts_with("msdia") && file_name.ends_with(".dll") =>
+			{
+				let arch = match rest {
+					[_] => "x86",
+					["amd64", _] => "x64",
+					[arch, _] => arch,
+					_ => continue,
+				};
+				dia_paths.push((entry.path().to_owned(), arch.to_owned()));
+			}
+			_ => {}
+		}
+	}
+	for (tool_path, tool_version, host) in tool_paths {
+		// Prefer the redistributables of the tools' own version, falling back to the newest one.
+		let redist_version = redist_paths
+			.iter()
+			.filter(|(_, _, arch)| *arch == host)
+			.map(|(_, version, _)| version)
+			.max_by_key(|version| (**version == tool_version, (*version).to_owned()));
+		let redist = redist_paths
+			.iter()
+			.filter(|(_, version, arch)| Some(version) == redist_version && *arch == host)
+			.map(|(path, _, _)| path);
+		let dia = dia_paths
+			.iter()
+			.filter(|(_, arch)| *arch == host)
+			.map(|(path, _)| path);
+		for path in redist.chain(dia) {
+			let runtime_path = tool_path.join(path.file_name().unwrap());
+			if !runtime_path.exists() {
+				std::fs::copy(path, runtime_path)?;
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::ToolRuntime;
+	use crate::stages::{Splat, Stage};
+	use std::path::Path;
+
+	fn write(root: &Path, path: &str) {
+		let path = root.join(path);
+		std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+		std::fs::write(path, b"").unwrap();
+	}
+
+	#[test]
+	fn test_splat_layout() {
+		let tempdir = tempfile::tempdir().unwrap();
+		let root = tempdir.path();
+		write(root, "VC/Tools/MSVC/14.38.33130/bin/Hostx64/x64/cl.exe");
+		write(
+			root,
+			"VC/Redist/MSVC/14.38.33130/x64/Microsoft.VC143.CRT/vcruntime140.dll",
+		);
+		write(root, "DIA SDK/bin/amd64/msdia140.dll");
+		let output_path = ToolRuntime.run(root.to_owned()).unwrap();
+		let output_path = Splat.run(output_path).unwrap();
+		let bin_path = output_path.join("crt/bin/Hostx64/x64");
+		assert!(bin_path.join("cl.exe").is_file());
+		assert!(bin_path.join("vcruntime140.dll").is_file());
+		assert!(bin_path.join("msdia140.dll").is_file());
+	}
+}
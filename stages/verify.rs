@@ -0,0 +1,76 @@
+use super::{Fetched, Stage};
+use crate::{
+	progress::{Progress, ProgressSink},
+	Cache, Error, Package, Payload, Result,
+};
+use digest::Digest;
+use rayon::prelude::*;
+use sha2::Sha256;
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+// Re-hashes cached payloads and fails on the first one that does not match the manifest.
+pub struct Verify {
+	pub cache: Cache,
+	pub progress: Arc<dyn ProgressSink>,
+	// The number of payloads to hash at once, or zero for one per CPU.
+	pub jobs: usize,
+}
+
+impl Stage<Fetched> for Verify {
+	type Output = Vec<Package>;
+
+	fn run(&self, fetched: Fetched) -> Result<Vec<Package>> {
+		self.cache.migrate()?;
+		let cached = fetched.cached.into_iter().collect::<HashSet<_>>();
+		let mut seen = HashSet::new();
+		let payloads = fetched
+			.packages
+			.iter()
+			.flat_map(|package| package.payloads.iter())
+			.filter(|payload| cached.contains(&payload.sha256) && seen.insert(payload.sha256))
+			.collect::<Vec<_>>();
+		let total_size = payloads.iter().map(|payload| payload.size).sum();
+		let progress_bar = Progress::new(&self.progress, "verify", total_size);
+		let thread_pool = rayon::ThreadPoolBuilder::new()
+			.num_threads(self.jobs)
+			.build()
+			.map_err(|error| std::io::Error::new(std::io::ErrorKind::Other, error))?;
+		thread_pool.install(|| {
+			payloads.par_iter().try_for_each(|payload| {
+				verify_payload(&self.cache.payload_path(payload), payload)?;
+				progress_bar.inc(payload.size);
+				self.cache.record_verified(payload)
+			})
+		})?;
+		progress_bar.finish();
+		Ok(fetched.packages)
+	}
+}
+
+impl Stage<Vec<Package>> for Verify {
+	type Output = Vec<Package>;
+
+	fn run(&self, packages: Vec<Package>) -> Result<Vec<Package>> {
+		self.run(Fetched::from(packages))
+	}
+}
+
+// The sha256 of the file, read a buffer at a time so memory use does not grow with its size.
+pub fn hash_file(path: &Path) -> Result<[u8; 32]> {
+	let mut file = std::fs::File::open(path)?;
+	let mut sha256 = Sha256::new();
+	std::io::copy(&mut file, &mut sha256)?;
+	Ok(sha256.finalize().into())
+}
+
+// Check a file against a payload the way cached payloads are checked, by its size and
+// then its sha256, failing with `Error::HashMismatch` for the payload's URL, for
+// embedders that store payloads themselves.
+pub fn verify_payload(path: &Path, payload: &Payload) -> Result<()> {
+	if std::fs::metadata(path)?.len() != payload.size || hash_file(path)? != payload.sha256 {
+		return Err(Error::HashMismatch {
+			url: payload.url.clone(),
+		});
+	}
+	Ok(())
+}
@@ -0,0 +1,285 @@
+use crate::{
+	env::{child, child_file, children, find_directories, ARCHES},
+	stages::postprocess::References,
+	DependencyChip, Error, Result,
+};
+use std::{
+	collections::{HashMap, HashSet},
+	path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+#[derive(Debug, Default)]
+pub struct VerifyOutputOptions {
+	// Check these architectures. An empty list checks every architecture with libraries
+	// in the output.
+	pub chips: Vec<DependencyChip>,
+	// Fail instead of warning when an architecture has no compiler and linker, for trees
+	// that are used to run the MSVC tools rather than clang-cl and lld-link.
+	pub require_tools: bool,
+}
+
+// The headers every build includes, with the component that provides them.
+const HEADERS: &[(&str, &str)] = &[
+	("windows.h", "the Windows SDK"),
+	("stdio.h", "the Universal CRT of the Windows SDK"),
+	("vcruntime.h", "the MSVC CRT headers"),
+];
+
+// The import libraries every build links for each architecture, with the component that
+// provides them.
+const LIBRARIES: &[(&str, &str)] = &[
+	("kernel32.lib", "the Windows SDK"),
+	("ucrt.lib", "the Universal CRT of the Windows SDK"),
+	("vcruntime.lib", "the MSVC CRT"),
+];
+
+// The number of unresolved references listed before the rest are counted.
+const MAX_LISTED_REFERENCES: usize = 10;
+
+enum Outcome {
+	Ok(String),
+	Warning(String, String),
+	Failure(String, String),
+}
+
+// Check that an extracted tree, in either the flat or the splat layout, has the headers,
+// import libraries, and tools for each architecture, and that the headers' includes
+// resolve with their original casing, printing what is missing. Warnings do not fail the
+// check.
+pub fn verify_output(output_path: PathBuf, options: VerifyOutputOptions) -> Result<()> {
+	if !output_path.is_dir() {
+		return Err(Error::InvalidPath {
+			path: output_path,
+			reason: "it is not a directory".to_owned(),
+		});
+	}
+	let directories = find_directories(&output_path);
+	let mut outcomes = Vec::new();
+	for (name, component) in HEADERS {
+		let outcome = match find_file(&directories.include, name) {
+			Some(path) => Outcome::Ok(path.display().to_string()),
+			None => Outcome::Failure(
+				format!("{} is in none of the include directories", name),
+				format!("extract {}", component),
+			),
+		};
+		outcomes.push(("header".to_owned(), outcome));
+	}
+	let arches = ARCHES
+		.iter()
+		.filter(|(chip, arch, _)| {
+			if options.chips.is_empty() {
+				directories.lib.contains_key(arch)
+			} else {
+				options.chips.contains(chip)
+			}
+		})
+		.map(|(_, arch, _)| *arch)
+		.collect::<Vec<_>>();
+	if arches.is_empty() {
+		outcomes.push((
+			"library".to_owned(),
+			Outcome::Failure(
+				"there are no libraries for any architecture".to_owned(),
+				"extract the packages for an architecture, such as with --target".to_owned(),
+			),
+		));
+	}
+	let msvc_paths = children(&output_path, &["VC", "Tools", "MSVC"]);
+	for arch in arches {
+		let lib_paths = directories.lib.get(arch).map(Vec::as_slice).unwrap_or(&[]);
+		for (name, component) in LIBRARIES {
+			let outcome = match find_file(lib_paths, name) {
+				Some(path) => Outcome::Ok(path.display().to_string()),
+				None => Outcome::Failure(
+					format!("{} is in none of the {} library directories", name, arch),
+					format!("extract {} for {}", component, arch),
+				),
+			};
+			outcomes.push((format!("library {}", arch), outcome));
+		}
+		outcomes.push((
+			format!("tools {}", arch),
+			check_tools(&msvc_paths, arch, options.require_tools),
+		));
+	}
+	outcomes.push((
+		"case".to_owned(),
+		check_case(&output_path, &directories.include)?,
+	));
+	let mut failures = 0;
+	for (check, outcome) in outcomes {
+		match outcome {
+			Outcome::Ok(message) => println!("ok {}: {}", check, message),
+			Outcome::Warning(message, fix) => {
+				println!("warning {}: {}\n\tfix: {}", check, message, fix)
+			}
+			Outcome::Failure(message, fix) => {
+				println!("error {}: {}\n\tfix: {}", check, message, fix);
+				failures += 1;
+			}
+		}
+	}
+	if failures > 0 {
+		return Err(Error::OutputVerificationFailed { count: failures });
+	}
+	Ok(())
+}
+
+fn find_file(directories: &[PathBuf], name: &str) -> Option<PathBuf> {
+	directories
+		.iter()
+		.find_map(|directory| child_file(directory, &[name]))
+}
+
+// The compiler and linker are only in the flat layout, in
+// `VC/Tools/MSVC/<version>/bin/Host<host>/<arch>`.
+fn check_tools(msvc_paths: &[PathBuf], arch: &str, require_tools: bool) -> Outcome {
+	let tools_path = msvc_paths.iter().rev().find_map(|msvc_path| {
+		["Hostx64", "Hostx86"].iter().find_map(|host| {
+			let path = child(msvc_path, &["bin", host, arch])?;
+			child_file(&path, &["cl.exe"])?;
+			child_file(&path, &["link.exe"])?;
+			Some(path)
+		})
+	});
+	match tools_path {
+		Some(path) => Outcome::Ok(path.display().to_string()),
+		None => {
+			let message = format!("there is no cl.exe and link.exe targeting {}", arch);
+			let fix = "extract the MSVC tools package for the architecture in the flat layout, or use clang-cl and lld-link".to_owned();
+			if require_tools {
+				Outcome::Failure(message, fix)
+			} else {
+				Outcome::Warning(message, fix)
+			}
+		}
+	}
+}
+
+// Every header included by another with a different casing needs a case variant next to
+// it, unless the tree has a valid vfs overlay, which clang and lld resolve
+// case-insensitively.
+fn check_case(output_path: &Path, include_paths: &[PathBuf]) -> Result<Outcome> {
+	let overlay_path = output_path.join("vfs-overlay.yaml");
+	if overlay_path.exists() {
+		return Ok(match check_vfs_overlay(output_path, &overlay_path) {
+			Ok(()) => Outcome::Ok(format!(
+				"{} resolves includes case-insensitively",
+				overlay_path.display()
+			)),
+			Err(message) => Outcome::Failure(message, "run the vfs-overlay hook again".to_owned()),
+		});
+	}
+	let mut header_paths = Vec::new();
+	for include_path in include_paths {
+		for entry in WalkDir::new(include_path) {
+			let entry = entry?;
+			let is_header = entry
+				.path()
+				.extension()
+				.map_or(false, |extension| extension == "h");
+			if entry.file_type().is_file() && is_header {
+				header_paths.push(entry.into_path());
+			}
+		}
+	}
+	let mut references = References::default();
+	let mut canonical_paths = HashMap::new();
+	for header_path in header_paths.iter() {
+		references.scan(&std::fs::read(header_path)?);
+		let lowercase_file_name = header_path
+			.file_name()
+			.unwrap()
+			.to_string_lossy()
+			.to_lowercase();
+		canonical_paths
+			.entry(lowercase_file_name)
+			.or_insert_with(HashSet::new)
+			.insert(header_path.parent().unwrap());
+	}
+	let mut unresolved = Vec::new();
+	for name in references.headers.iter() {
+		let directories = match canonical_paths.get(&name.to_lowercase()) {
+			Some(directories) => directories,
+			None => continue,
+		};
+		if directories
+			.iter()
+			.any(|directory| !directory.join(name).exists())
+		{
+			unresolved.push(name.as_str());
+		}
+	}
+	if unresolved.is_empty() {
+		return Ok(Outcome::Ok(format!(
+			"the includes of {} headers resolve with their casing",
+			header_paths.len()
+		)));
+	}
+	let mut message = format!(
+		"{} included headers have no case variant: {}",
+		unresolved.len(),
+		unresolved
+			.iter()
+			.take(MAX_LISTED_REFERENCES)
+			.copied()
+			.collect::<Vec<_>>()
+			.join(", ")
+	);
+	if unresolved.len() > MAX_LISTED_REFERENCES {
+		message.push_str(", ...");
+	}
+	Ok(Outcome::Failure(
+		message,
+		"extract again to create the case variants, or use --hook vfs-overlay with clang"
+			.to_owned(),
+	))
+}
+
+fn check_vfs_overlay(output_path: &Path, overlay_path: &Path) -> std::result::Result<(), String> {
+	let overlay = std::fs::read(overlay_path)
+		.map_err(|error| format!("failed to read {}: {}", overlay_path.display(), error))?;
+	let overlay: serde_json::Value = serde_json::from_slice(&overlay)
+		.map_err(|error| format!("failed to parse {}: {}", overlay_path.display(), error))?;
+	if overlay["case-sensitive"] != "false" {
+		return Err(format!("{} is case sensitive", overlay_path.display()));
+	}
+	// The overlay names the tree by its absolute path, so it no longer applies once the
+	// tree is moved.
+	let output_path = output_path
+		.canonicalize()
+		.map_err(|error| error.to_string())?;
+	let root = overlay["roots"]
+		.as_array()
+		.and_then(|roots| roots.first())
+		.filter(|root| root["name"] == output_path.to_string_lossy().as_ref())
+		.ok_or_else(|| {
+			format!(
+				"{} does not describe {}",
+				overlay_path.display(),
+				output_path.display()
+			)
+		})?;
+	let mut missing = 0;
+	let mut entries = vec![root];
+	while let Some(entry) = entries.pop() {
+		if let Some(contents) = entry["contents"].as_array() {
+			entries.extend(contents);
+		}
+		if let Some(path) = entry["external-contents"].as_str() {
+			if !Path::new(path).exists() {
+				missing += 1;
+			}
+		}
+	}
+	if missing > 0 {
+		return Err(format!(
+			"{} files in {} no longer exist",
+			missing,
+			overlay_path.display()
+		));
+	}
+	Ok(())
+}
@@ -0,0 +1,73 @@
+use crate::{Error, Result};
+use std::path::{Path, PathBuf};
+
+pub fn resolve_path_case_insensitive(root: &Path, relative_path: &Path) -> Option<PathBuf> {
+	let mut path = root.to_owned();
+	for component in relative_path.components() {
+		let name = component.as_os_str();
+		let exact_path = path.join(name);
+		if exact_path.exists() {
+			path = exact_path;
+			continue;
+		}
+		let name = name.to_str()?;
+		let entry = std::fs::read_dir(&path).ok()?.find_map(|entry| {
+			let entry = entry.ok()?;
+			if entry.file_name().to_str()?.eq_ignore_ascii_case(name) {
+				Some(entry)
+			} else {
+				None
+			}
+		})?;
+		path = entry.path();
+	}
+	Some(path)
+}
+
+pub fn generate_vfs_overlay(output_path: PathBuf, overlay_path: PathBuf) -> Result<()> {
+	write_vfs_overlay(&output_path, &output_path, &overlay_path)
+}
+
+// Describe the tree at the output path with the paths it will have at the install path,
+// where it will be moved.
+pub(crate) fn write_vfs_overlay(
+	output_path: &Path,
+	install_path: &Path,
+	overlay_path: &Path,
+) -> Result<()> {
+	// Describe the whole tree in a case insensitive clang/lld virtual file system
+	// overlay, so lookups with any casing resolve to the extracted files.
+	fn entry(path: &Path, installed: &dyn Fn(&Path) -> PathBuf) -> Result<serde_json::Value> {
+		let name = path.file_name().unwrap().to_string_lossy();
+		if path.is_dir() {
+			let mut children = std::fs::read_dir(path)?
+				.map(|entry| Ok(entry?.path()))
+				.collect::<Result<Vec<_>>>()?;
+			children.sort();
+			Ok(serde_json::json!({
+				"name": name,
+				"type": "directory",
+				"contents": children.iter().map(|child| entry(child, installed)).collect::<Result<Vec<_>>>()?,
+			}))
+		} else {
+			Ok(serde_json::json!({
+				"name": name,
+				"type": "file",
+				"external-contents": installed(path).to_string_lossy(),
+			}))
+		}
+	}
+	let output_path = output_path.canonicalize()?;
+	let install_path = crate::paths::absolute_path(install_path)?;
+	let installed = |path: &Path| install_path.join(path.strip_prefix(&output_path).unwrap());
+	let mut root = entry(&output_path, &installed)?;
+	root["name"] = install_path.to_string_lossy().into();
+	let overlay = serde_json::json!({
+		"version": 0,
+		"case-sensitive": "false",
+		"roots": [root],
+	});
+	let overlay_bytes = serde_json::to_vec_pretty(&overlay)?;
+	std::fs::write(overlay_path, &overlay_bytes).map_err(Error::file(overlay_path))?;
+	Ok(())
+}